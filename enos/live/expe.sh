@@ -26,7 +26,8 @@ do
 		},
 		"targetNode": "'$TARGET_NODE'",
 		"requestDestinations": [],
-		"requestSources": []
+		"requestSources": [],
+		"client": "expe"
 	}'
 	sleep $DELAY
 done
@@ -0,0 +1,118 @@
+//! Benchmarks of the market, run with `just bench_market`.
+//! The market being a binary, its modules are compiled here as well; only some of their items
+//! are used, and their tests are left out.
+#![allow(dead_code, unused_imports)]
+#[macro_use]
+extern crate log;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use manager::helper::snapshot::Snapshot;
+use manager::model::domain::heartbeat::HeartbeatThresholds;
+use manager::model::domain::routing::FunctionRoutingStack;
+use manager::model::domain::sla::Sla;
+use manager::model::view::auction::{BidProposal, BidProposals};
+use manager::model::view::node::RegisterNode;
+use manager::model::{BidId, NodeId};
+
+use crate::repository::fog_node::{FogNode, FogNodeImpl};
+use crate::repository::node_communication::{Error, NodeCommunication};
+use crate::service::faas::{FogNodeFaaS, FogNodeFaaSImpl};
+use crate::service::fog_node_network::{FogNodeNetwork, FogNodeNetworkHashTreeImpl};
+use crate::service::routing::{Router, RouterImpl};
+
+#[path = "../src/bin/market/controller.rs"]
+mod controller;
+#[path = "../src/bin/market/repository/mod.rs"]
+mod repository;
+#[path = "../src/bin/market/service/mod.rs"]
+mod service;
+
+/// Nodes that accept everything, without any network
+#[derive(Debug)]
+struct Nodes;
+
+#[async_trait]
+impl NodeCommunication for Nodes {
+    async fn request_bids_from_node(&self, _to: NodeId, _sla: Sla) -> Result<BidProposals, Error> {
+        Ok(BidProposals { bids: vec![], failures: vec![] })
+    }
+
+    async fn take_offer(&self,
+                        _to: NodeId,
+                        _bid: &BidProposal,
+                        _function: &BidId)
+                        -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn cancel_bid(&self, _to: NodeId, _bid: &BidProposal) -> Result<(), Error> { Ok(()) }
+
+    async fn establish_route(&self, _stack: FunctionRoutingStack) -> Result<(), Error> { Ok(()) }
+
+    async fn remove_route(&self, _stack: FunctionRoutingStack) -> Result<(), Error> { Ok(()) }
+
+    async fn remove_function(&self, _to: NodeId, _id: &BidId) -> Result<(), Error> { Ok(()) }
+
+    async fn change_parent(&self, _to: NodeId, _parent: &NodeId) -> Result<(), Error> { Ok(()) }
+
+    async fn adopt_child(&self, _to: NodeId, _child: &NodeId) -> Result<(), Error> { Ok(()) }
+}
+
+/// Register [count] nodes through the controller, in a tree saved to a snapshot
+async fn register_nodes(count: usize) {
+    let path = std::env::temp_dir().join(format!("fog_nodes_{}.json", Uuid::new_v4()));
+    let fog_node = Arc::new(FogNodeImpl::load(Snapshot::new(path.clone())).await.unwrap());
+    let nodes = Arc::new(Nodes);
+    let fog_net: Arc<dyn FogNodeNetwork> =
+        Arc::new(FogNodeNetworkHashTreeImpl::new(fog_node.clone(),
+                                                 nodes.clone(),
+                                                 HeartbeatThresholds::default()));
+    let faas: Arc<dyn FogNodeFaaS> =
+        Arc::new(FogNodeFaaSImpl::new(fog_node.clone(), nodes.clone()));
+    let router: Arc<dyn Router> = Arc::new(RouterImpl::new(fog_node.clone(), nodes));
+    let root = NodeId::from(Uuid::new_v4());
+    controller::register_node(RegisterNode::MarketNode { node_id: root.clone(),
+                                                         ip:      IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                                         port:    3000,
+                                                         tags:    vec![], },
+                              &fog_net,
+                              &faas,
+                              &router).await
+                                      .unwrap();
+
+    let start = Instant::now();
+    let mut ids = vec![root];
+    for ii in 0..count {
+        let id = NodeId::from(Uuid::new_v4());
+        // Each node picks one of the former ones as parent, making a deep enough tree
+        let parent = ids[(ii * 7919) % ids.len()].clone();
+        controller::register_node(RegisterNode::Node { parent,
+                                                       node_id: id.clone(),
+                                                       ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                                       port: 3001,
+                                                       tags: vec![] },
+                                  &fog_net,
+                                  &faas,
+                                  &router).await
+                                          .unwrap();
+        ids.push(id);
+    }
+    let elapsed = start.elapsed();
+
+    println!("registered {} nodes in {:?} ({:?} per node), {} in the tree",
+             count,
+             elapsed,
+             elapsed / count as u32,
+             fog_node.get_subtree(&ids[0]).await.len());
+    let _ = tokio::fs::remove_file(path.with_extension("journal")).await;
+    tokio::fs::remove_file(path).await.unwrap();
+}
+
+#[tokio::main]
+async fn main() { register_nodes(10_000).await; }
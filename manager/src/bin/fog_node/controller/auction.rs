@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use manager::model::view::auction::{BidProposals, BidRequest};
+use manager::model::view::auction::{BidProposals, BidRequest, TakeOffer};
 use manager::model::BidId;
 
 use crate::controller::ControllerError;
@@ -13,7 +13,8 @@ pub async fn bid_on(bid_request: BidRequest,
     trace!("bidding on... {:?}", bid_request);
     function.bid_on_new_function_and_transmit(bid_request.sla,
                                               bid_request.node_origin,
-                                              bid_request.accumulated_latency)
+                                              bid_request.accumulated_latency,
+                                              bid_request.timeout)
             .await
             .map_err(ControllerError::from)
 }
@@ -21,11 +22,34 @@ pub async fn bid_on(bid_request: BidRequest,
 /// Returns a bid for the SLA.
 /// Creates the function on OpenFaaS and use the SLA to enable the limits
 pub async fn provision_from_bid(id: BidId,
+                                offer: TakeOffer,
                                 function: &Arc<dyn FunctionLife>)
                                 -> Result<(), ControllerError> {
-    trace!("Transforming bid into provisioned resource {:?}", id);
+    trace!("Transforming bid {:?} into provisioned resource {:?}", id, offer.function);
 
-    function.validate_bid_and_provision_function(id).await?;
+    function.validate_bid_and_provision_function(id, offer.function).await?;
+
+    Ok(())
+}
+
+/// Drops the bid and releases the resources it reserved.
+pub async fn cancel_bid(id: BidId,
+                        function: &Arc<dyn FunctionLife>)
+                        -> Result<(), ControllerError> {
+    trace!("Cancelling bid {:?}", id);
+
+    function.cancel_bid(id).await?;
+
+    Ok(())
+}
+
+/// Removes the function from OpenFaaS and releases the resources it was holding.
+pub async fn remove_function(id: BidId,
+                             function: &Arc<dyn FunctionLife>)
+                             -> Result<(), ControllerError> {
+    trace!("Removing provisioned function {:?}", id);
+
+    function.remove_function(id).await?;
 
     Ok(())
 }
@@ -1,5 +1,5 @@
 use crate::NodeLife;
-use manager::model::view::node::RegisterNode;
+use manager::model::view::node::{FogNodeHealth, NeighborNode, RegisterNode, UnregisterNode};
 use std::sync::Arc;
 
 pub async fn register_child_node(register: RegisterNode,
@@ -7,3 +7,23 @@ pub async fn register_child_node(register: RegisterNode,
                                  -> anyhow::Result<()> {
     router.register_child_node(register).await.map_err(|e| anyhow::anyhow!(e))
 }
+
+pub async fn unregister_child_node(unregister: UnregisterNode,
+                                   node_life: &Arc<dyn NodeLife>)
+                                   -> anyhow::Result<()> {
+    node_life.unregister_child_node(unregister).await.map_err(|e| anyhow::anyhow!(e))
+}
+
+pub async fn change_parent(parent: NeighborNode,
+                           node_life: &Arc<dyn NodeLife>)
+                           -> anyhow::Result<()> {
+    node_life.change_parent(parent).await.map_err(|e| anyhow::anyhow!(e))
+}
+
+pub async fn adopt_child(child: NeighborNode, node_life: &Arc<dyn NodeLife>) -> anyhow::Result<()> {
+    node_life.adopt_child(child).await.map_err(|e| anyhow::anyhow!(e))
+}
+
+pub async fn health(node_life: &Arc<dyn NodeLife>) -> FogNodeHealth {
+    FogNodeHealth { registration: node_life.get_registration_state().await }
+}
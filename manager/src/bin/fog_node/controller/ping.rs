@@ -1,3 +1,14 @@
+use std::sync::Arc;
+
 use manager::model::view::ping::{Ping, PingResponse};
 
-pub async fn ping(_: Ping) -> PingResponse { PingResponse { received_at: chrono::Utc::now() } }
+use crate::service::neighbor_monitor::NeighborMonitor;
+
+pub async fn ping(ping: Ping, neighbor_monitor: &Arc<dyn NeighborMonitor>) -> PingResponse {
+    let received_at = chrono::Utc::now();
+    let forgotten = match &ping.from {
+        Some(from) => !neighbor_monitor.is_neighbor(from).await,
+        None => false,
+    };
+    PingResponse { received_at, sent_at: chrono::Utc::now(), forgotten }
+}
@@ -1,8 +1,7 @@
 use std::sync::Arc;
 
-use bytes::Bytes;
-
 use manager::model::domain::routing::{FunctionRoutingStack, Packet};
+use manager::model::dto::routing::RoutedResponse;
 
 use crate::service::routing::Router;
 
@@ -13,9 +12,16 @@ pub async fn register_route(router: &Arc<dyn Router>,
     router.register_function_route(stack).await.map_err(|e| anyhow::anyhow!(e))
 }
 
+pub async fn unregister_route(router: &Arc<dyn Router>,
+                              stack: FunctionRoutingStack)
+                              -> anyhow::Result<()> {
+    trace!("delete routing {:?}", stack.function);
+    router.unregister_function_route(stack).await.map_err(|e| anyhow::anyhow!(e))
+}
+
 pub async fn post_forward_function_routing(packet: &Packet<'_>,
                                            router: &Arc<dyn Router>)
-                                           -> anyhow::Result<Bytes> {
+                                           -> anyhow::Result<RoutedResponse> {
     trace!("post forward routing from packet {:?}", packet);
     router.forward(packet).await.map_err(|e| anyhow::anyhow!(e))
 }
@@ -1,11 +1,15 @@
-use crate::prom_metrics::{CPU_ALLOCATABLE_GAUGE, CPU_USAGE_GAUGE, MEMORY_ALLOCATABLE_GAUGE,
-                          MEMORY_USAGE_GAUGE};
-use crate::repository::k8s::K8s;
+use crate::repository::resource_tracking::ResourceTracking;
+use crate::service::auction::Auction;
 use crate::service::neighbor_monitor::NeighborMonitor;
+use crate::service::node_life::NodeLife;
+use manager::model::view::node::RegistrationState;
 use std::sync::Arc;
 use tokio_cron_scheduler::{Job, JobScheduler};
 
-pub fn init(neighbor_monitor: Arc<dyn NeighborMonitor>, k8s_repo: Arc<dyn K8s>) {
+pub fn init(neighbor_monitor: Arc<dyn NeighborMonitor>,
+            resource_tracking: Arc<dyn ResourceTracking>,
+            auction: Arc<dyn Auction>,
+            node_life: Arc<dyn NodeLife>) {
     let sched = JobScheduler::new().unwrap();
 
     // TODO option to configure ?
@@ -18,13 +22,25 @@ pub fn init(neighbor_monitor: Arc<dyn NeighborMonitor>, k8s_repo: Arc<dyn K8s>)
          .unwrap();
 
     sched.add(Job::new_async("1/15 * * * * *", move |_, _| {
-                  let k8s_repo = k8s_repo.clone();
+                  let resource_tracking = resource_tracking.clone();
                   Box::pin(async move {
-                      let _ = measure(k8s_repo).await.map_err(|err| {
-                                                         warn!("An error occurred while CRON \
-                                                                measuring from K8S: {}",
-                                                               err)
-                                                     });
+                      reconcile(resource_tracking).await;
+                  })
+              }).unwrap())
+         .unwrap();
+
+    sched.add(Job::new_async("1/1 * * * * *", move |_, _| {
+                  let auction = auction.clone();
+                  Box::pin(async move {
+                      expire_bids(auction).await;
+                  })
+              }).unwrap())
+         .unwrap();
+
+    sched.add(Job::new_async("1/15 * * * * *", move |_, _| {
+                  let node_life = node_life.clone();
+                  Box::pin(async move {
+                      heartbeat(node_life).await;
                   })
               }).unwrap())
          .unwrap();
@@ -32,31 +48,34 @@ pub fn init(neighbor_monitor: Arc<dyn NeighborMonitor>, k8s_repo: Arc<dyn K8s>)
     sched.start().unwrap();
 }
 
+async fn heartbeat(node_life: Arc<dyn NodeLife>) {
+    if node_life.get_registration_state().await != RegistrationState::Registered {
+        return;
+    }
+    match node_life.send_heartbeat().await {
+        Ok(ack) if ack.forgotten => {
+            warn!("The market forgot about this node, registering again");
+            tokio::spawn(async move { node_life.register().await });
+        }
+        Ok(_) => (),
+        Err(e) => warn!("send_heartbeat failed: {}", e.to_string()),
+    }
+}
+
+async fn expire_bids(auction: Arc<dyn Auction>) {
+    if let Err(e) = auction.expire_bids().await {
+        warn!("expire_bids failed: {}", e.to_string());
+    };
+}
+
 async fn ping(neighbor_monitor: Arc<dyn NeighborMonitor>) {
     if let Err(e) = neighbor_monitor.ping_neighbors_rtt().await {
         warn!("ping_neighbors_rtt failed: {}", e.to_string());
     };
 }
 
-async fn measure(k8s_repo: Arc<dyn K8s>) -> anyhow::Result<()> {
-    let aggregated_metrics = k8s_repo.get_k8s_metrics().await?;
-    for (name, metrics) in aggregated_metrics {
-        let allocatable =
-            metrics.allocatable
-                   .as_ref()
-                   .ok_or_else(|| {
-                       anyhow::anyhow!("Allocatable resource key not found in retrieved metrics")
-                   })?;
-        let usage = metrics.usage
-                           .as_ref()
-                           .ok_or_else(|| {
-                               anyhow::anyhow!("Usage resource key not found in retrieved metrics")
-                           })?;
-        MEMORY_ALLOCATABLE_GAUGE.with_label_values(&[&name]).set(allocatable.memory.value);
-        MEMORY_USAGE_GAUGE.with_label_values(&[&name]).set(usage.memory.value);
-        CPU_ALLOCATABLE_GAUGE.with_label_values(&[&name]).set(allocatable.cpu.value);
-        CPU_USAGE_GAUGE.with_label_values(&[&name]).set(usage.cpu.value);
-    }
-
-    Ok(())
+async fn reconcile(resource_tracking: Arc<dyn ResourceTracking>) {
+    if let Err(e) = resource_tracking.reconcile().await {
+        warn!("An error occurred while CRON reconciling the resources from K8S: {}", e.to_string());
+    };
 }
@@ -1,15 +1,17 @@
 use crate::service::function_life::FunctionLife;
+use crate::service::neighbor_monitor::NeighborMonitor;
 use crate::service::routing::Router;
 use crate::{controller, NodeLife};
-use manager::helper::handler::{BytesResponse, Resp};
+use manager::helper::handler::Resp;
 use manager::model::domain::routing::{FunctionRoutingStack, Packet};
-use manager::model::view::auction::{BidProposals, BidRequest};
-use manager::model::view::node::RegisterNode;
+use manager::model::dto::routing::RoutedResponse;
+use manager::model::view::auction::{BidProposals, BidRequest, TakeOffer};
+use manager::model::view::node::{FogNodeHealth, NeighborNode, RegisterNode, UnregisterNode};
 use manager::model::view::ping::{Ping, PingResponse};
 use manager::model::BidId;
 use manager::respond;
 use rocket::serde::json::Json;
-use rocket::{get, post, put, State};
+use rocket::{delete, get, post, put, State};
 use rocket_okapi::openapi;
 use std::sync::Arc;
 
@@ -23,11 +25,32 @@ pub async fn post_bid(payload: Json<BidRequest>,
 }
 
 /// Second function called after [post_bid] if the bid is accepted and the transaction starts.
-/// Will then proceed to provision the SLA and thus, the function.
+/// Will then proceed to provision the SLA and thus, the function, under the identity given by
+/// the market.
+/// Accepting a bid whose function is already provisioned succeeds without doing anything, so
+/// the market can retry.
 #[openapi]
-#[post("/bid/<id>")]
-pub async fn post_bid_accept(id: BidId, function: &State<Arc<dyn FunctionLife>>) -> Resp {
-    respond!(controller::auction::provision_from_bid(id, function.inner()).await)
+#[post("/bid/<id>", data = "<payload>")]
+pub async fn post_bid_accept(id: BidId,
+                             payload: Json<TakeOffer>,
+                             function: &State<Arc<dyn FunctionLife>>)
+                             -> Resp {
+    respond!(controller::auction::provision_from_bid(id, payload.0, function.inner()).await)
+}
+
+/// Drop a bid that won but is not needed, e.g., because the function stays where it is, and
+/// release its reservation.
+#[openapi]
+#[post("/bid/<id>/cancel")]
+pub async fn post_bid_cancel(id: BidId, function: &State<Arc<dyn FunctionLife>>) -> Resp {
+    respond!(controller::auction::cancel_bid(id, function.inner()).await)
+}
+
+/// Remove the provisioned function, and release the resources it was using.
+#[openapi]
+#[post("/function/<id>/remove")]
+pub async fn post_function_remove(id: BidId, function: &State<Arc<dyn FunctionLife>>) -> Resp {
+    respond!(controller::auction::remove_function(id, function.inner()).await)
 }
 
 /// Routes the request to the correct URL and node.
@@ -35,10 +58,8 @@ pub async fn post_bid_accept(id: BidId, function: &State<Arc<dyn FunctionLife>>)
 #[post("/routing", data = "<packet>")]
 pub async fn post_routing(packet: Json<Packet<'_>>,
                           router: &State<Arc<dyn Router>>)
-                          -> Result<BytesResponse, manager::helper::handler::Error> {
-    Ok(BytesResponse::from(
-        controller::routing::post_forward_function_routing(&packet.0, router.inner()).await?,
-    ))
+                          -> Result<RoutedResponse, manager::helper::handler::Error> {
+    Ok(controller::routing::post_forward_function_routing(&packet.0, router.inner()).await?)
 }
 
 /// Register a route.
@@ -50,6 +71,15 @@ pub async fn put_routing(router: &State<Arc<dyn Router>>,
     respond!(controller::routing::register_route(router.inner(), stack.0).await)
 }
 
+/// Unregister a route.
+#[openapi]
+#[delete("/routing", data = "<stack>")]
+pub async fn delete_routing(router: &State<Arc<dyn Router>>,
+                            stack: Json<FunctionRoutingStack>)
+                            -> Resp {
+    respond!(controller::routing::unregister_route(router.inner(), stack.0).await)
+}
+
 /// Register a child node to this one
 #[openapi]
 #[post("/register", data = "<payload>")]
@@ -59,13 +89,43 @@ pub async fn post_register_child_node(payload: Json<RegisterNode>,
     respond!(controller::node::register_child_node(payload.0, router.inner()).await)
 }
 
+/// Unregister a child node leaving the network
+#[openapi]
+#[post("/unregister", data = "<payload>")]
+pub async fn post_unregister_child_node(payload: Json<UnregisterNode>,
+                                        node_life: &State<Arc<dyn NodeLife>>)
+                                        -> Resp {
+    respond!(controller::node::unregister_child_node(payload.0, node_life.inner()).await)
+}
+
+/// Replace the parent, that left the network
+#[openapi]
+#[post("/parent", data = "<payload>")]
+pub async fn post_parent(payload: Json<NeighborNode>,
+                         node_life: &State<Arc<dyn NodeLife>>)
+                         -> Resp {
+    respond!(controller::node::change_parent(payload.0, node_life.inner()).await)
+}
+
+/// Adopt the child of a child that left the network
+#[openapi]
+#[post("/child", data = "<payload>")]
+pub async fn post_child(payload: Json<NeighborNode>, node_life: &State<Arc<dyn NodeLife>>) -> Resp {
+    respond!(controller::node::adopt_child(payload.0, node_life.inner()).await)
+}
+
 /// Route to compute latencies
 #[openapi]
 #[post("/ping", data = "<payload>")]
-pub async fn post_ping(payload: Json<Ping>) -> Json<PingResponse> {
-    controller::ping::ping(payload.0).await.into()
+pub async fn post_ping(payload: Json<Ping>,
+                       neighbor_monitor: &State<Arc<dyn NeighborMonitor>>)
+                       -> Json<PingResponse> {
+    controller::ping::ping(payload.0, neighbor_monitor.inner()).await.into()
 }
 
+/// Get the state of the node, e.g., whether it is registered to its parent and the market
 #[openapi]
 #[get("/health")]
-pub fn health() {}
+pub async fn health(node_life: &State<Arc<dyn NodeLife>>) -> Json<FogNodeHealth> {
+    controller::node::health(node_life.inner()).await.into()
+}
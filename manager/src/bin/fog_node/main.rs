@@ -5,16 +5,23 @@ extern crate core;
 extern crate log;
 
 use crate::handler::*;
+use crate::repository::faas_routing_table::FaaSRoutingTableHashMap;
 use crate::repository::latency_estimation::LatencyEstimationImpl;
 use crate::repository::node_query::{NodeQuery, NodeQueryRESTImpl};
 use crate::repository::node_situation::{NodeSituation, NodeSituationHashSetImpl};
+use crate::repository::pricing::{CostPlusMarginPricing, LinearPricing, PricingStrategy,
+                                 SurgePricing, UtilizationPricing};
 use crate::repository::provisioned::ProvisionedHashMapImpl;
-use crate::repository::resource_tracking::ResourceTracking;
+use crate::repository::resource_tracking::{ResourceTracking, ResourceTrackingImpl};
 use crate::service::auction::{Auction, AuctionImpl};
-use crate::service::faas::{FaaSBackend, OpenFaaSBackend};
-use crate::service::neighbor_monitor::{NeighborMonitor, NeighborMonitorImpl};
+use crate::service::faas::OpenFaaSBackend;
+use crate::service::function_life::{FunctionLife, FunctionLifeImpl};
+use crate::service::neighbor_monitor::NeighborMonitorImpl;
 use crate::service::node_life::{NodeLife, NodeLifeImpl};
 use crate::service::routing::{Router, RouterImpl};
+use manager::helper::snapshot::Snapshot;
+use manager::model::domain::liveness::FailureThresholds;
+use manager::model::domain::pricing::Pricing;
 use manager::model::dto::node::{NodeSituationData, NodeSituationDisk};
 use manager::openfaas::{Configuration, DefaultApiClient};
 use reqwest::Client;
@@ -25,7 +32,10 @@ use rocket_okapi::swagger_ui::*;
 use rocket_prometheus::prometheus::GaugeVec;
 use rocket_prometheus::PrometheusMetrics;
 use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 
 mod controller;
 mod cron;
@@ -50,6 +60,22 @@ fn load_config_from_env() -> anyhow::Result<String> {
     Ok(config)
 }
 
+/// Directory where the repositories save their state, from the DATA_DIR env variable, if set, so
+/// that the node resumes with the same functions, routes, bids and children after a restart.
+/// Otherwise, everything is only kept in memory.
+async fn data_dir() -> anyhow::Result<Option<PathBuf>> {
+    match env::var("DATA_DIR") {
+        Ok(dir) => {
+            let dir = PathBuf::from(dir);
+            tokio::fs::create_dir_all(&dir).await?;
+            info!("Persisting the state in {:?}", dir);
+            Ok(Some(dir))
+        }
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
 #[cfg(feature = "fake_k8s")]
 use crate::repository::k8s::K8sFakeImpl as k8s;
 #[cfg(not(feature = "fake_k8s"))]
@@ -66,32 +92,22 @@ fn k8s_factory() -> k8s {
     k8s::new()
 }
 
-#[cfg(feature = "bottom_up_placement")]
-use crate::service::function_life::FunctionLifeBottomUpImpl as FunctionLifeService;
-#[cfg(not(feature = "bottom_up_placement"))]
-use crate::service::function_life::FunctionLifeImpl as FunctionLifeService;
-
-fn function_life_factory(faas_service: Arc<dyn FaaSBackend>,
-                         auction_service: Arc<dyn Auction>,
-                         node_situation: Arc<dyn NodeSituation>,
-                         neighbor_monitor_service: Arc<dyn NeighborMonitor>,
-                         node_query: Arc<dyn NodeQuery>)
-                         -> FunctionLifeService {
-    #[cfg(feature = "bottom_up_placement")]
-    {
-        debug!("Using bottom-up placement");
-    }
-
-    #[cfg(not(feature = "bottom_up_placement"))]
-    {
-        info!("Using default placement");
-    }
-
-    FunctionLifeService::new(faas_service,
-                             auction_service,
-                             node_situation,
-                             neighbor_monitor_service,
-                             node_query)
+fn pricing_factory(pricing: Pricing) -> anyhow::Result<Arc<dyn PricingStrategy>> {
+    info!("Using the pricing strategy {:?}", pricing);
+    Ok(match pricing {
+        Pricing::Utilization => Arc::new(UtilizationPricing::new()),
+        Pricing::Linear { memory_price, cpu_price } => {
+            Arc::new(LinearPricing::new(memory_price, cpu_price))
+        }
+        Pricing::Surge { memory_price, cpu_price, threshold, surge_factor } => {
+            anyhow::ensure!((0.0..1.0).contains(&threshold),
+                            "The surge threshold is a utilization, within [0, 1)");
+            Arc::new(SurgePricing::new(memory_price, cpu_price, threshold, surge_factor))
+        }
+        Pricing::CostPlusMargin { fixed_cost, memory_cost, cpu_cost, margin } => {
+            Arc::new(CostPlusMarginPricing::new(fixed_cost, memory_cost, cpu_cost, margin))
+        }
+    })
 }
 
 // TODO: Use https://crates.io/crates/rnp instead of a HTTP ping as it is currently the case
@@ -121,8 +137,33 @@ async fn rocket() -> _ {
                                        .unwrap();
     info!("Loaded config from CONFIG env variable.");
 
+    let reservation_ttl =
+        env::var("BID_RESERVATION_TTL").ok().and_then(|ttl| ttl.parse::<u64>().ok()).unwrap_or(30);
+    debug!("bid reservation ttl: {}s", reservation_ttl);
+    let bid_timeout =
+        env::var("BID_TIMEOUT").ok().and_then(|timeout| timeout.parse::<u64>().ok()).unwrap_or(10);
+    debug!("bid timeout: {}s", bid_timeout);
+    let default_thresholds = FailureThresholds::default();
+    let suspect_after =
+        env::var("SUSPECT_AFTER_MISSED_PINGS").ok()
+                                              .and_then(|n| n.parse::<u32>().ok())
+                                              .unwrap_or(default_thresholds.suspect_after);
+    let dead_after = env::var("DEAD_AFTER_MISSED_PINGS").ok()
+                                                        .and_then(|n| n.parse::<u32>().ok())
+                                                        .unwrap_or(default_thresholds.dead_after);
+    let failure_thresholds = FailureThresholds { suspect_after, dead_after };
+    debug!("failure detection thresholds: {:?}", failure_thresholds);
+
     let auth = username.map(|username| (username, password));
 
+    let data_dir = data_dir().await
+                             .map_err(|err| {
+                                 error!("Error preparing the DATA_DIR: {}", err);
+                                 std::process::exit(1);
+                             })
+                             .unwrap();
+    let snapshot = |name: &str| data_dir.as_ref().map(|dir| Snapshot::new(dir.join(name)));
+
     // Repositories
     let client = Arc::new(DefaultApiClient::new(Configuration { base_path:  format!("http://{}:{}",
                                                                                     ip_openfaas,
@@ -130,44 +171,108 @@ async fn rocket() -> _ {
                                                                 client:     Client::new(),
                                                                 basic_auth: auth, }));
 
-    let disk_data = NodeSituationDisk::new(config);
+    let disk_data = NodeSituationDisk::new(config).unwrap();
+    let pricing = pricing_factory(disk_data.pricing().clone()).map_err(|err| {
+                                                                  error!("Error loading the \
+                                                                          pricing strategy: {}",
+                                                                         err);
+                                                                  std::process::exit(1);
+                                                              })
+                                                              .unwrap();
+    let placement = disk_data.placement().clone();
+    info!("Using the placement strategy {:?}", placement);
+    let situation = NodeSituationData::from(disk_data);
     let node_situation =
-        Arc::new(NodeSituationHashSetImpl::new(NodeSituationData::from(disk_data.unwrap())));
+        Arc::new(match snapshot("children.json") {
+                     Some(snapshot) => NodeSituationHashSetImpl::load(situation, snapshot).await,
+                     None => Ok(NodeSituationHashSetImpl::new(situation)),
+                 }.map_err(|err| {
+                      error!("Error restoring the children nodes: {}", err);
+                      std::process::exit(1);
+                  })
+                  .unwrap());
 
     info!("Current node ID is {}", node_situation.get_my_id().await);
     info!("Current node has been tagged {:?}", node_situation.get_my_tags().await);
     let node_query = Arc::new(NodeQueryRESTImpl::new(node_situation.clone()));
-    let provisioned_repo = Arc::new(ProvisionedHashMapImpl::new());
+    let provisioned_repo =
+        Arc::new(match snapshot("provisioned.json") {
+                     Some(snapshot) => ProvisionedHashMapImpl::load(snapshot).await,
+                     None => Ok(ProvisionedHashMapImpl::new()),
+                 }.map_err(|err| {
+                      error!("Error restoring the provisioned functions: {}", err);
+                      std::process::exit(1);
+                  })
+                  .unwrap());
     let k8s_repo = Arc::new(k8s_factory());
-    let resource_tracking_repo = Arc::new(
-        crate::repository::resource_tracking::ResourceTrackingImpl::new(k8s_repo.clone())
-            .await
-            .expect("Failed to instanciate the ResourceTrackingRepo"),
-    );
-    let auction_repo = Arc::new(crate::repository::auction::AuctionImpl::new());
+    let resource_tracking_repo =
+        Arc::new(match snapshot("resources.json") {
+                     Some(snapshot) => ResourceTrackingImpl::load(k8s_repo, snapshot).await,
+                     None => ResourceTrackingImpl::new(k8s_repo).await,
+                 }.map_err(|err| {
+                      error!("Error instanciating the ResourceTrackingRepo: {}", err);
+                      std::process::exit(1);
+                  })
+                  .unwrap());
+    let auction_repo =
+        Arc::new(match snapshot("bids.json") {
+                     Some(snapshot) => {
+                         crate::repository::auction::AuctionImpl::load(snapshot).await
+                     }
+                     None => Ok(crate::repository::auction::AuctionImpl::new()),
+                 }.map_err(|err| {
+                      error!("Error restoring the pending bids: {}", err);
+                      std::process::exit(1);
+                  })
+                  .unwrap());
+    let routing_table_repo =
+        Arc::new(match snapshot("routing_table.json") {
+                     Some(snapshot) => FaaSRoutingTableHashMap::load(snapshot).await,
+                     None => Ok(FaaSRoutingTableHashMap::new()),
+                 }.map_err(|err| {
+                      error!("Error restoring the routing table: {}", err);
+                      std::process::exit(1);
+                  })
+                  .unwrap());
     let latency_estimation_repo = Arc::new(LatencyEstimationImpl::new(node_situation.clone()));
 
     // Services
     let auction_service = Arc::new(AuctionImpl::new(resource_tracking_repo.clone()
                                                     as Arc<dyn ResourceTracking>,
-                                                    auction_repo.clone()).await);
-    let faas_service = Arc::new(OpenFaaSBackend::new(client.clone(), provisioned_repo.clone()));
-    let router_service = Arc::new(RouterImpl::new(
-        Arc::new(crate::repository::faas_routing_table::FaaSRoutingTableHashMap::new()),
-        node_situation.clone(),
-        Arc::new(crate::repository::routing::RoutingImpl),
-        faas_service.clone(),
-        client.clone(),
-    ));
+                                                    auction_repo.clone(),
+                                                    pricing,
+                                                    Duration::from_secs(reservation_ttl)).await);
+    let faas_service = Arc::new(OpenFaaSBackend::new(client.clone(),
+                                                     provisioned_repo.clone(),
+                                                     node_situation.get_my_id().await));
+    let router_service =
+        Arc::new(RouterImpl::new(routing_table_repo,
+                                 node_situation.clone(),
+                                 Arc::new(crate::repository::routing::RoutingImpl),
+                                 faas_service.clone(),
+                                 client.clone()));
     let node_life_service = Arc::new(NodeLifeImpl::new(router_service.clone(),
                                                        node_situation.clone(),
-                                                       node_query.clone()));
-    let neighbor_monitor_service = Arc::new(NeighborMonitorImpl::new(latency_estimation_repo));
-    let function_life_service = Arc::new(function_life_factory(faas_service.clone(),
+                                                       node_query.clone(),
+                                                       faas_service.clone(),
+                                                       latency_estimation_repo.clone(),
+                                                       resource_tracking_repo.clone()));
+    let neighbor_monitor_service = Arc::new(NeighborMonitorImpl::new(latency_estimation_repo,
+                                                                     node_situation.clone(),
+                                                                     router_service.clone(),
+                                                                     node_life_service.clone(),
+                                                                     failure_thresholds));
+    let function_life_service = Arc::new(FunctionLifeImpl::new(faas_service.clone(),
                                                                auction_service.clone(),
                                                                node_situation.clone(),
                                                                neighbor_monitor_service.clone(),
-                                                               node_query.clone()));
+                                                               node_query.clone(),
+                                                               placement,
+                                                               Duration::from_secs(bid_timeout)));
+
+    if let Err(err) = function_life_service.reconcile_functions().await {
+        warn!("Failed to reconcile the provisioned functions with OpenFaaS: {}", err);
+    }
 
     if node_situation.is_market().await {
         info!("This node is a provider node located at the market node");
@@ -177,22 +282,32 @@ async fn rocket() -> _ {
 
     let prometheus = PrometheusMetrics::new();
 
-    let metrics: [&GaugeVec; 11] = [&prom_metrics::BID_GAUGE,
+    let metrics: [&GaugeVec; 14] = [&prom_metrics::BID_GAUGE,
                                     &prom_metrics::MEMORY_USAGE_GAUGE,
                                     &prom_metrics::MEMORY_ALLOCATABLE_GAUGE,
                                     &prom_metrics::CPU_USAGE_GAUGE,
                                     &prom_metrics::CPU_ALLOCATABLE_GAUGE,
+                                    &prom_metrics::FUNCTION_MEMORY_USAGE_GAUGE,
+                                    &prom_metrics::FUNCTION_CPU_USAGE_GAUGE,
                                     &prom_metrics::MEMORY_USED_GAUGE,
                                     &prom_metrics::MEMORY_AVAILABLE_GAUGE,
                                     &prom_metrics::CPU_USED_GAUGE,
                                     &prom_metrics::CPU_AVAILABLE_GAUGE,
                                     &prom_metrics::LATENCY_NEIGHBORS_GAUGE,
-                                    &prom_metrics::LATENCY_NEIGHBORS_AVG_GAUGE];
+                                    &prom_metrics::LATENCY_NEIGHBORS_AVG_GAUGE,
+                                    &prom_metrics::LATENCY_NEIGHBORS_STATS_GAUGE];
     for metric in metrics {
         prometheus.registry().register(Box::new(metric.clone())).unwrap();
     }
 
-    rocket::build().attach(prometheus.clone())
+    let auction_service_cron = auction_service.clone() as Arc<dyn Auction>;
+    let node_life_departure = node_life_service.clone() as Arc<dyn NodeLife>;
+    let node_life_cron = node_life_service.clone() as Arc<dyn NodeLife>;
+
+    // SIGTERM is handled by the departure hook, so that the node still answers while leaving
+    let figment = rocket::Config::figment().merge(("shutdown.signals", Vec::<String>::new()));
+
+    rocket::custom(figment).attach(prometheus.clone())
                    .manage(auction_service as Arc<dyn crate::service::auction::Auction>)
                    .manage(faas_service as Arc<dyn crate::service::faas::FaaSBackend>)
                    .manage(function_life_service
@@ -210,33 +325,55 @@ async fn rocket() -> _ {
                    .mount("/api/",
                           openapi_get_routes![post_bid,
                                               post_bid_accept,
+                                              post_bid_cancel,
+                                              post_function_remove,
                                               post_routing,
                                               put_routing,
+                                              delete_routing,
                                               post_register_child_node,
+                                              post_unregister_child_node,
+                                              post_parent,
+                                              post_child,
                                               post_ping,
                                               health])
                    .attach(AdHoc::on_liftoff("Registration to the parent & market", |_rocket| {
                                Box::pin(async {
                                    info!("Registering to market and parent...");
-                                   // let address = address.clone();
-                                   // trace!("Register using address: {}:{}", address, port);
-                                   register_to_market(node_life_service, node_situation).await;
-                                   info!("Registered to market and parent.");
+                                   tokio::spawn(async move { node_life_service.register().await });
                                })
                            }))
                    .attach(AdHoc::on_liftoff("Starting CRON jobs", |_rocket| {
                                Box::pin(async {
-                                   cron::init(neighbor_monitor_service, k8s_repo);
+                                   cron::init(neighbor_monitor_service,
+                                              resource_tracking_repo,
+                                              auction_service_cron,
+                                              node_life_cron);
                                    info!("Initialized CRON jobs.");
                                })
                            }))
+                   .attach(AdHoc::on_liftoff("Graceful departure on SIGTERM", |rocket| {
+                               let shutdown = rocket.shutdown();
+                               Box::pin(async move {
+                                   tokio::spawn(leave_on_sigterm(node_life_departure, shutdown));
+                               })
+                           }))
 }
 
-async fn register_to_market(node_life: Arc<dyn NodeLife>, node_situation: Arc<dyn NodeSituation>) {
-    let my_ip = node_situation.get_my_public_ip().await;
-    let my_port = node_situation.get_my_public_port().await;
-    if let Err(err) = node_life.init_registration(my_ip, my_port).await {
-        error!("Failed to register to market: {}", err.to_string());
-        std::process::exit(1);
+/// Leave the network on SIGTERM, while the server still answers the market relocating the
+/// functions hosted here, then shut the server down
+async fn leave_on_sigterm(node_life: Arc<dyn NodeLife>, shutdown: rocket::Shutdown) {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(err) => {
+            error!("Failed to listen to SIGTERM: {}", err);
+            return;
+        }
+    };
+    sigterm.recv().await;
+    info!("Received SIGTERM, leaving the network...");
+    match node_life.unregister().await {
+        Ok(()) => info!("Left the network."),
+        Err(err) => error!("Failed to leave the network gracefully: {}", err),
     }
+    shutdown.notify();
 }
@@ -52,6 +52,22 @@ lazy_static! {
         .unwrap()
     };
 
+    pub static ref FUNCTION_MEMORY_USAGE_GAUGE: GaugeVec = {
+        GaugeVec::new(
+            opts!(concat!(PREFIX!(),"function_memory_usage"), "Memory usage of the pods of a provisioned function"),
+            &["function_name", "name"],
+        )
+        .unwrap()
+    };
+
+    pub static ref FUNCTION_CPU_USAGE_GAUGE: GaugeVec = {
+        GaugeVec::new(
+            opts!(concat!(PREFIX!(),"function_cpu_usage"), "CPU usage of the pods of a provisioned function"),
+            &["function_name", "name"],
+        )
+        .unwrap()
+    };
+
     pub static ref MEMORY_AVAILABLE_GAUGE: GaugeVec = {
         GaugeVec::new(
             opts!(concat!(PREFIX!(),"memory_available"), "Memory available on fog_node (from fog_node's perspective)"),
@@ -105,4 +121,13 @@ lazy_static! {
         .unwrap()
     };
 
+    pub static ref LATENCY_NEIGHBORS_STATS_GAUGE: GaugeVec = {
+        GaugeVec::new(
+            opts!(concat!(PREFIX!(),"neighbors_latency_stats"), "Statistics (ewma, p50, p95, p99, jitter) of the latency with neighbors (parent & children) over the recent pings"),
+                        &["instance_to", "statistic"],
+
+        )
+        .unwrap()
+    };
+
 }
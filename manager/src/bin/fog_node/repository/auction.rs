@@ -1,38 +1,111 @@
 use std::collections::HashMap;
+use std::time::{Instant, SystemTime};
 
 use async_trait::async_trait;
-use tokio::sync::RwLock;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use uuid::Uuid;
 
+use manager::helper::snapshot::{Persisted, Snapshot};
 use manager::model::dto::auction::BidRecord;
 use manager::model::BidId;
 
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Snapshot(#[from] manager::helper::snapshot::Error),
+}
+
 #[async_trait]
 pub trait Auction: Sync + Send {
-    async fn insert(&self, auction: BidRecord) -> BidId;
+    /// Insert the bid, that will be considered expired after [expires_at]
+    async fn insert(&self, auction: BidRecord, expires_at: Instant) -> Result<BidId, Error>;
+    /// Insert back a bid that was removed, under the same id
+    async fn restore(&self,
+                     id: BidId,
+                     auction: BidRecord,
+                     expires_at: Instant)
+                     -> Result<(), Error>;
     async fn get(&self, id: &BidId) -> Option<BidRecord>;
-    async fn remove(&self, id: &BidId);
+    async fn remove(&self, id: &BidId) -> Result<Option<BidRecord>, Error>;
+    /// Remove and return all the bids that expired at [now]
+    async fn remove_expired(&self, now: Instant) -> Result<Vec<(BidId, BidRecord)>, Error>;
+}
+
+/// An expiration date, saved as a wall-clock time to survive a restart
+#[derive(Debug, Clone, Copy)]
+struct Expiry(Instant);
+
+impl Serialize for Expiry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (SystemTime::now() + self.0.saturating_duration_since(Instant::now())).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expiry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let expires_at = SystemTime::deserialize(deserializer)?;
+        Ok(Expiry(Instant::now()
+                  + expires_at.duration_since(SystemTime::now()).unwrap_or_default()))
+    }
 }
 
 pub struct AuctionImpl {
-    database: RwLock<HashMap<BidId, BidRecord>>,
+    database: Persisted<HashMap<BidId, (BidRecord, Expiry)>>,
 }
 
 impl AuctionImpl {
-    pub fn new() -> AuctionImpl { AuctionImpl { database: RwLock::new(HashMap::new()) } }
+    pub fn new() -> AuctionImpl { AuctionImpl { database: Persisted::new(HashMap::new()) } }
+
+    pub async fn load(snapshot: Snapshot) -> Result<AuctionImpl, Error> {
+        Ok(AuctionImpl { database: Persisted::load(snapshot).await? })
+    }
 }
 
 #[async_trait]
 impl Auction for AuctionImpl {
-    async fn insert(&self, auction: BidRecord) -> BidId {
+    async fn insert(&self, auction: BidRecord, expires_at: Instant) -> Result<BidId, Error> {
         let id = BidId::from(Uuid::new_v4());
-        self.database.write().await.insert(id.to_owned(), auction);
-        id
+        self.database
+            .write(|database| database.insert(id.clone(), (auction, Expiry(expires_at))))
+            .await?;
+        Ok(id)
+    }
+
+    async fn restore(&self,
+                     id: BidId,
+                     auction: BidRecord,
+                     expires_at: Instant)
+                     -> Result<(), Error> {
+        self.database.write(|database| database.insert(id, (auction, Expiry(expires_at)))).await?;
+        Ok(())
     }
 
     async fn get(&self, id: &BidId) -> Option<BidRecord> {
-        self.database.read().await.get(id).cloned()
+        self.database.read().await.get(id).map(|(record, _)| record.clone())
+    }
+
+    async fn remove(&self, id: &BidId) -> Result<Option<BidRecord>, Error> {
+        Ok(self.database.write(|database| database.remove(id).map(|(record, _)| record)).await?)
     }
 
-    async fn remove(&self, id: &BidId) { self.database.write().await.remove(id); }
+    async fn remove_expired(&self, now: Instant) -> Result<Vec<(BidId, BidRecord)>, Error> {
+        let expired = self.database
+                          .read()
+                          .await
+                          .iter()
+                          .filter(|(_, (_, Expiry(expires_at)))| *expires_at <= now)
+                          .map(|(id, _)| id.clone())
+                          .collect::<Vec<_>>();
+        if expired.is_empty() {
+            return Ok(vec![]);
+        }
+        // Removed under the write lock, in case one was removed meanwhile
+        Ok(self.database
+               .write(|database| {
+                   expired.into_iter()
+                          .filter_map(|id| database.remove(&id).map(|(record, _)| (id, record)))
+                          .collect()
+               })
+               .await?)
+    }
 }
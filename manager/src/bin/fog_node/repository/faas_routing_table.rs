@@ -2,35 +2,98 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 
 use async_trait::async_trait;
-use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
 
+use manager::helper::snapshot::{Persisted, Snapshot};
 use manager::model::dto::routing::Direction;
-use manager::model::BidId;
+use manager::model::{BidId, NodeId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Snapshot(#[from] manager::helper::snapshot::Error),
+}
 
 #[async_trait]
 pub trait FaaSRoutingTable: Debug + Sync + Send {
-    /// Update the breadcrumb route to the [BidId] passing by the next [NodeId].
-    async fn update(&self, source: BidId, target: Direction);
+    /// Update the breadcrumb route to the [BidId], or from it to the [destination], passing by
+    /// the next [NodeId].
+    async fn update(&self,
+                    function: BidId,
+                    destination: Option<NodeId>,
+                    target: Direction)
+                    -> Result<(), Error>;
+
+    async fn get(&self, function: &BidId, destination: Option<&NodeId>) -> Option<Direction>;
+
+    /// Forget the route to the [BidId], or from it to the [destination].
+    async fn remove(&self, function: &BidId, destination: Option<&NodeId>) -> Result<(), Error>;
+}
 
-    async fn get(&self, bid_id: &BidId) -> Option<Direction>;
+/// The routes to the functions, and the ones from the functions to their destinations
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Table {
+    functions:    HashMap<BidId, Direction>,
+    destinations: HashMap<BidId, HashMap<NodeId, Direction>>,
 }
 
 #[derive(Debug)]
 pub struct FaaSRoutingTableHashMap {
-    table: RwLock<HashMap<BidId, Direction>>,
+    table: Persisted<Table>,
 }
 
 impl FaaSRoutingTableHashMap {
-    pub fn new() -> Self { Self { table: RwLock::new(HashMap::new()) } }
+    pub fn new() -> Self { Self { table: Persisted::new(Table::default()) } }
+
+    pub async fn load(snapshot: Snapshot) -> Result<Self, Error> {
+        Ok(Self { table: Persisted::load(snapshot).await? })
+    }
 }
 
 #[async_trait]
 impl FaaSRoutingTable for FaaSRoutingTableHashMap {
-    async fn update(&self, source: BidId, target: Direction) {
-        self.table.write().await.insert(source, target);
+    async fn update(&self,
+                    function: BidId,
+                    destination: Option<NodeId>,
+                    target: Direction)
+                    -> Result<(), Error> {
+        self.table
+            .write(|table| match destination {
+                Some(destination) => {
+                    table.destinations.entry(function).or_default().insert(destination, target);
+                }
+                None => {
+                    table.functions.insert(function, target);
+                }
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, function: &BidId, destination: Option<&NodeId>) -> Option<Direction> {
+        let table = self.table.read().await;
+        match destination {
+            Some(destination) => table.destinations.get(function)?.get(destination).cloned(),
+            None => table.functions.get(function).cloned(),
+        }
     }
 
-    async fn get(&self, bid_id: &BidId) -> Option<Direction> {
-        self.table.read().await.get(bid_id).cloned()
+    async fn remove(&self, function: &BidId, destination: Option<&NodeId>) -> Result<(), Error> {
+        self.table
+            .write(|table| match destination {
+                Some(destination) => {
+                    if let Some(destinations) = table.destinations.get_mut(function) {
+                        destinations.remove(destination);
+                        if destinations.is_empty() {
+                            table.destinations.remove(function);
+                        }
+                    }
+                }
+                None => {
+                    table.functions.remove(function);
+                }
+            })
+            .await?;
+        Ok(())
     }
 }
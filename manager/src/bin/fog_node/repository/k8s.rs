@@ -1,6 +1,7 @@
 extern crate uom;
 
 use std::collections::HashMap;
+use std::fmt::Debug;
 
 use async_trait::async_trait;
 
@@ -17,8 +18,13 @@ pub enum Error {
 }
 
 #[async_trait]
-pub trait K8s: Sync + Send {
+pub trait K8s: Debug + Sync + Send {
     async fn get_k8s_metrics(&self) -> Result<HashMap<String, Metrics>, Error>;
+
+    /// Get the resources actually consumed by the pods of the functions, aggregated by k8s node
+    /// and then by function name.
+    async fn get_k8s_functions_usage(&self)
+                                     -> Result<HashMap<String, HashMap<String, Usage>>, Error>;
 }
 
 #[cfg(not(feature = "fake_k8s"))]
@@ -28,13 +34,23 @@ pub use k8s_impl::*;
 mod k8s_impl {
     use super::*;
 
-    use k8s_openapi::api::core::v1::Node;
+    use k8s_openapi::api::core::v1::{Node, Pod};
     use kube::api::ListParams;
     use kube::{Api, Client};
     use lazy_regex::regex;
     use manager::kube_metrics::node::NodeMetrics;
+    use manager::kube_metrics::pod::PodMetrics;
     use std::str::FromStr;
+    use uom::si::f64::{Information, Ratio};
+    use uom::si::information::byte;
+    use uom::si::ratio::part_per_billion;
 
+    /// Namespace where OpenFaaS deploys the functions
+    const FUNCTIONS_NAMESPACE: &str = "openfaas-fn";
+    /// Label set by OpenFaaS on the pods of a function, its value is the name of the function
+    const FUNCTION_LABEL: &str = "faas_function";
+
+    #[derive(Debug)]
     pub struct K8sImpl;
 
     impl K8sImpl {
@@ -94,6 +110,52 @@ mod k8s_impl {
 
             Ok(aggregated_metrics)
         }
+
+        async fn get_k8s_functions_usage(
+            &self)
+            -> Result<HashMap<String, HashMap<String, Usage>>, Error> {
+            let client = Client::try_default().await.map_err(Error::Kube)?;
+            let params = ListParams::default().labels(FUNCTION_LABEL);
+
+            // Pod name -> (k8s node, function name)
+            let mut placements = HashMap::new();
+            let pods: Api<Pod> = Api::namespaced(client.clone(), FUNCTIONS_NAMESPACE);
+            for pod in pods.list(&params).await.map_err(Error::Kube)? {
+                let key = pod.metadata.name.ok_or(Error::MissingKey("metadata:name"))?;
+                let function = pod.metadata
+                                  .labels
+                                  .and_then(|labels| labels.get(FUNCTION_LABEL).cloned())
+                                  .ok_or(Error::MissingKey(FUNCTION_LABEL))?;
+                // Pods that are not scheduled yet do not consume anything
+                if let Some(node) = pod.spec.and_then(|spec| spec.node_name) {
+                    placements.insert(key, (node, function));
+                }
+            }
+
+            let mut usages: HashMap<String, HashMap<String, Usage>> = HashMap::new();
+            let pod_metrics: Api<PodMetrics> = Api::namespaced(client, FUNCTIONS_NAMESPACE);
+            for metric in pod_metrics.list(&params).await.map_err(Error::Kube)? {
+                let key = metric.metadata.name.ok_or(Error::MissingKey("metadata:name"))?;
+                let (node, function) = match placements.get(&key) {
+                    Some(placement) => placement,
+                    None => continue,
+                };
+
+                let usage = usages.entry(node.clone())
+                                  .or_default()
+                                  .entry(function.clone())
+                                  .or_insert(Usage { cpu:    Ratio::new::<part_per_billion>(0.0),
+                                                     memory: Information::new::<byte>(0.0), });
+                for container in metric.containers {
+                    usage.cpu += parse_quantity::<Ratio>(&container.usage.cpu.0[..],
+                                                         &MissingUnitType::Complete("ppb"))?;
+                    usage.memory += parse_quantity::<Information>(&container.usage.memory.0[..],
+                                                                  &MissingUnitType::Suffix("B"))?;
+                }
+            }
+
+            Ok(usages)
+        }
     }
     enum MissingUnitType<'a> {
         /// Missing just the rightmost part, eg. B for Bytes
@@ -179,6 +241,7 @@ mod fake_impl {
     use uom::si::f64::{Information, Ratio};
     use uom::si::information::{gibibyte, mebibyte};
 
+    #[derive(Debug)]
     pub struct K8sFakeImpl;
 
     impl K8sFakeImpl {
@@ -205,5 +268,11 @@ mod fake_impl {
             );
             Ok(aggregated_metrics)
         }
+
+        async fn get_k8s_functions_usage(
+            &self)
+            -> Result<HashMap<String, HashMap<String, Usage>>, Error> {
+            Ok(HashMap::new())
+        }
     }
 }
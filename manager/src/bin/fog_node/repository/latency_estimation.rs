@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::fmt::Debug;
 use std::sync::Arc;
@@ -7,12 +7,15 @@ use async_trait::async_trait;
 use tokio::sync::RwLock;
 use uom::si::f64::Time;
 
-use manager::model::domain::rolling_avg::RollingAvg;
-use manager::model::view::ping::Ping;
+use manager::model::domain::latency_stats::{LatencyStats, LatencySummary};
+use manager::model::view::ping::{Ping, PingResponse};
 use manager::model::NodeId;
 
 use crate::NodeSituation;
 
+/// Number of exchanges kept to estimate the clock offset, as the NTP clock filter does
+const CLOCK_FILTER_SIZE: usize = 8;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Rtt estimation was carried for {0} nodes, got {1} errors: {2}")]
@@ -23,6 +26,8 @@ pub enum Error {
 pub enum IndividualError {
     #[error("Got negative RTTs")]
     NegativeTimeInterval,
+    #[error("The node answered but doesn't know us as one of its neighbors")]
+    Forgotten,
     #[error("Did not found node: {0}")]
     NodeNotFound(NodeId),
     #[error(transparent)]
@@ -38,6 +43,24 @@ impl fmt::Display for IndividualErrorList {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{:?}", self.list) }
 }
 
+impl IndividualErrorList {
+    /// The nodes that failed to answer
+    pub fn unreachable(&self) -> impl Iterator<Item = &NodeId> {
+        self.list
+            .iter()
+            .filter(|(_, err)| !matches!(err, IndividualError::Forgotten))
+            .map(|(id, _)| id)
+    }
+
+    /// The nodes that answered without knowing us
+    pub fn forgotten(&self) -> impl Iterator<Item = &NodeId> {
+        self.list
+            .iter()
+            .filter(|(_, err)| matches!(err, IndividualError::Forgotten))
+            .map(|(id, _)| id)
+    }
+}
+
 impl From<Vec<(NodeId, IndividualError)>> for IndividualErrorList {
     fn from(list: Vec<(NodeId, IndividualError)>) -> Self { IndividualErrorList { list } }
 }
@@ -46,52 +69,127 @@ impl From<Vec<(NodeId, IndividualError)>> for IndividualErrorList {
 pub trait LatencyEstimation: Debug + Sync + Send {
     /// Make the requests to the neighbors to get the Latency to our children + parent.
     async fn latency_to_neighbors(&self) -> Result<(), Error>;
-    async fn get_latency_to_avg(&self, id: &NodeId) -> Option<Time>;
-    async fn get_latency_from_avg(&self, id: &NodeId) -> Option<Time>;
+    async fn get_latency_to(&self, id: &NodeId) -> Option<LatencySummary>;
+    async fn get_latency_from(&self, id: &NodeId) -> Option<LatencySummary>;
+    /// Estimated offset of the clock of the neighbor relative to ours
+    async fn get_clock_offset(&self, id: &NodeId) -> Option<Time>;
+}
+
+/// Timestamps of an NTP-like exchange, in ms relative to the sending of the ping
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Exchange {
+    /// Ping received by the neighbor (its clock)
+    remote_received: f64,
+    /// Response sent by the neighbor (its clock)
+    remote_sent:     f64,
+    /// Response received (our clock)
+    received:        f64,
+}
+
+impl Exchange {
+    fn new(ping: &Ping,
+           response: &PingResponse,
+           received_at: &chrono::DateTime<chrono::Utc>)
+           -> Self {
+        let since_sent = |date: &chrono::DateTime<chrono::Utc>| {
+            (*date - ping.sent_at).num_microseconds().unwrap_or(i64::MAX) as f64 / 1000.0
+        };
+        Exchange { remote_received: since_sent(&response.received_at),
+                   remote_sent:     since_sent(&response.sent_at),
+                   received:        since_sent(received_at), }
+    }
+
+    /// Time spent on the network, without the processing time of the neighbor
+    fn round_trip(&self) -> f64 { self.received - (self.remote_sent - self.remote_received) }
+
+    /// Offset of the clock of the neighbor, exact if the paths are symmetric. Split with it, the
+    /// round trip of this very exchange gives two equal halves.
+    fn offset(&self) -> f64 { (self.remote_received + (self.remote_sent - self.received)) / 2.0 }
+
+    /// Split the round trip in (outgoing, incoming) using a clock offset estimated from other
+    /// exchanges
+    fn one_way_latencies(&self, offset: f64) -> (f64, f64) {
+        let round_trip = self.round_trip();
+        let outgoing = (self.remote_received - offset).clamp(0.0, round_trip);
+        (outgoing, round_trip - outgoing)
+    }
+}
+
+/// Keep the last exchanges and estimate the clock offset from the one with the lowest round
+/// trip, being the least affected by queuing delays.
+/// That exchange is assumed to be symmetric: a constant asymmetry of the paths cannot be told
+/// apart from an offset of the clocks, so the base latency is split evenly between both
+/// directions. Only the delays the other exchanges take on top of it are attributed to the
+/// direction they occurred in.
+#[derive(Debug, Default)]
+struct ClockFilter {
+    samples: VecDeque<(f64, f64)>,
+}
+
+impl ClockFilter {
+    /// Add the (round trip, offset) sample and return the new offset estimation
+    fn update(&mut self, round_trip: f64, offset: f64) -> f64 {
+        if self.samples.len() == CLOCK_FILTER_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((round_trip, offset));
+        self.get_offset().unwrap_or(offset)
+    }
+
+    fn get_offset(&self) -> Option<f64> {
+        self.samples.iter().min_by(|(a, _), (b, _)| a.total_cmp(b)).map(|(_, offset)| *offset)
+    }
 }
 
 #[derive(Debug)]
 pub struct LatencyEstimationImpl {
     node_situation:     Arc<dyn NodeSituation>,
-    outgoing_latencies: Arc<RwLock<HashMap<NodeId, RollingAvg>>>,
-    incoming_latencies: Arc<RwLock<HashMap<NodeId, RollingAvg>>>,
+    outgoing_latencies: Arc<RwLock<HashMap<NodeId, LatencyStats>>>,
+    incoming_latencies: Arc<RwLock<HashMap<NodeId, LatencyStats>>>,
+    clock_offsets:      Arc<RwLock<HashMap<NodeId, ClockFilter>>>,
 }
 
 impl LatencyEstimationImpl {
     pub fn new(node_situation: Arc<dyn NodeSituation>) -> Self {
         Self { node_situation,
                outgoing_latencies: Arc::new(RwLock::new(HashMap::new())),
-               incoming_latencies: Arc::new(RwLock::new(HashMap::new())) }
+               incoming_latencies: Arc::new(RwLock::new(HashMap::new())),
+               clock_offsets: Arc::new(RwLock::new(HashMap::new())) }
     }
 
     /// Compute latencies and return (outgoing, incoming)
-    #[inline]
-    fn compute_latency(&self,
-                       ping: &Ping,
-                       received_at: &chrono::DateTime<chrono::Utc>)
-                       -> Result<(Time, Time), IndividualError> {
-        // TODO fix that simplistic estimation with somthing considering the real values instead of
-        // symetric ones!!
-
-        let latency_round =
-            (received_at.timestamp_millis() - ping.sent_at.timestamp_millis()) as i64;
-
-        if latency_round < 0 {
-            warn!("Got negative latency: {}", latency_round);
+    async fn compute_latency(&self,
+                             node_id: &NodeId,
+                             ping: &Ping,
+                             response: &PingResponse,
+                             received_at: &chrono::DateTime<chrono::Utc>)
+                             -> Result<(Time, Time), IndividualError> {
+        let exchange = Exchange::new(ping, response, received_at);
+        let round_trip = exchange.round_trip();
+
+        if round_trip < 0.0 {
+            warn!("Got negative latency: {}", round_trip);
             return Err(IndividualError::NegativeTimeInterval);
         }
 
-        let latency = latency_round as f64 / 2.0;
-        let outgoing_latency = Time::new::<uom::si::time::millisecond>(latency);
-        let incoming_latency = outgoing_latency;
+        let offset = self.clock_offsets
+                         .write()
+                         .await
+                         .entry(node_id.clone())
+                         .or_default()
+                         .update(round_trip, exchange.offset());
 
-        Ok((outgoing_latency, incoming_latency))
+        let (outgoing, incoming) = exchange.one_way_latencies(offset);
+
+        Ok((Time::new::<uom::si::time::millisecond>(outgoing),
+            Time::new::<uom::si::time::millisecond>(incoming)))
     }
 
-    /// Do the packet exchanges to get the latencies and return (outgoing, incoming)
+    /// Do the packet exchanges to get the latencies and return (outgoing, incoming), along with
+    /// whether the node forgot us
     async fn make_latency_request_to(&self,
                                      node_id: &NodeId)
-                                     -> Result<(Time, Time), IndividualError> {
+                                     -> Result<(Time, Time, bool), IndividualError> {
         let desc = self.node_situation
                        .get_fog_node_neighbor(node_id)
                        .await
@@ -101,14 +199,20 @@ impl LatencyEstimationImpl {
         let port = desc.port;
 
         let client = reqwest::Client::new();
-        let ping = Ping { sent_at: chrono::Utc::now() };
-        let _response = client.post(format!("http://{}:{}/api/ping", ip, port).as_str())
-                              .json(&ping)
-                              .send()
-                              .await?;
+        let ping = Ping { sent_at: chrono::Utc::now(),
+                          from:    Some(self.node_situation.get_my_id().await), };
+        let response: PingResponse =
+            client.post(format!("http://{}:{}/api/ping", ip, port).as_str())
+                  .json(&ping)
+                  .send()
+                  .await?
+                  .json()
+                  .await?;
         let received_at = chrono::Utc::now();
 
-        self.compute_latency(&ping, &received_at)
+        let (outgoing, incoming) =
+            self.compute_latency(node_id, &ping, &response, &received_at).await?;
+        Ok((outgoing, incoming, response.forgotten))
     }
 }
 
@@ -120,7 +224,8 @@ impl LatencyEstimation for LatencyEstimationImpl {
         for node in self.node_situation.get_neighbors().await {
             tried_nodes.push(node.clone());
             handles.push(async move {
-                       let (incoming, outgoing) = self.make_latency_request_to(&node).await?;
+                       let (outgoing, incoming, forgotten) =
+                           self.make_latency_request_to(&node).await?;
                        self.incoming_latencies
                            .write()
                            .await
@@ -145,10 +250,24 @@ impl LatencyEstimation for LatencyEstimationImpl {
                     .with_label_values(&[&format!("{}:{}", ip, port)])
                     .set(outgoing.value);
 
-                       if let Some(lat) = self.outgoing_latencies.read().await.get(&node) {
+                       if let Some(summary) = self.get_latency_to(&node).await {
+                           let instance_to = format!("{}:{}", ip, port);
                            crate::prom_metrics::LATENCY_NEIGHBORS_AVG_GAUGE
-                        .with_label_values(&[&format!("{}:{}", ip, port)])
-                        .set(lat.get_avg().value);
+                            .with_label_values(&[&instance_to])
+                            .set(summary.ewma.value);
+                           for (statistic, value) in [("ewma", summary.ewma),
+                                                      ("p50", summary.p50),
+                                                      ("p95", summary.p95),
+                                                      ("p99", summary.p99),
+                                                      ("jitter", summary.jitter)]
+                           {
+                               crate::prom_metrics::LATENCY_NEIGHBORS_STATS_GAUGE
+                            .with_label_values(&[&instance_to, statistic])
+                            .set(value.value);
+                           }
+                       }
+                       if forgotten {
+                           return Err(IndividualError::Forgotten);
                        }
                        Ok(())
                    });
@@ -171,11 +290,72 @@ impl LatencyEstimation for LatencyEstimationImpl {
         Ok(())
     }
 
-    async fn get_latency_to_avg(&self, id: &NodeId) -> Option<Time> {
-        self.outgoing_latencies.read().await.get(id).map(|avg| avg.get_avg())
+    async fn get_latency_to(&self, id: &NodeId) -> Option<LatencySummary> {
+        self.outgoing_latencies.read().await.get(id).and_then(|stats| stats.get_summary())
     }
 
-    async fn get_latency_from_avg(&self, id: &NodeId) -> Option<Time> {
-        self.incoming_latencies.read().await.get(id).map(|avg| avg.get_avg())
+    async fn get_latency_from(&self, id: &NodeId) -> Option<LatencySummary> {
+        self.incoming_latencies.read().await.get(id).and_then(|stats| stats.get_summary())
+    }
+
+    async fn get_clock_offset(&self, id: &NodeId) -> Option<Time> {
+        self.clock_offsets
+            .read()
+            .await
+            .get(id)
+            .and_then(|filter| filter.get_offset())
+            .map(Time::new::<uom::si::time::millisecond>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(remote_received: f64, remote_sent: f64, received: f64) -> Exchange {
+        Exchange { remote_received, remote_sent, received }
+    }
+
+    #[test]
+    fn test_offset_of_symmetric_exchange() {
+        // neighbor clock is 100ms ahead, 10ms each way, 2ms of processing
+        let exchange = exchange(110.0, 112.0, 22.0);
+        assert_eq!(exchange.round_trip(), 20.0);
+        assert_eq!(exchange.offset(), 100.0);
+        assert_eq!(exchange.one_way_latencies(100.0), (10.0, 10.0));
+    }
+
+    #[test]
+    fn test_queuing_delay_is_attributed_to_its_direction() {
+        // neighbor clock is 100ms ahead, 10ms each way, 2ms of processing
+        let mut filter = ClockFilter::default();
+        let quiet = exchange(110.0, 112.0, 22.0);
+        let offset = filter.update(quiet.round_trip(), quiet.offset());
+        assert_eq!(quiet.one_way_latencies(offset), (10.0, 10.0));
+
+        // the ping is queued for 20ms on its way
+        let queued = exchange(130.0, 132.0, 42.0);
+        let offset = filter.update(queued.round_trip(), queued.offset());
+        assert_eq!(offset, 100.0);
+        assert_eq!(queued.one_way_latencies(offset), (30.0, 10.0));
+    }
+
+    #[test]
+    fn test_one_way_latencies_are_clamped() {
+        let exchange = exchange(130.0, 130.0, 40.0);
+        assert_eq!(exchange.one_way_latencies(150.0), (0.0, 40.0));
+        assert_eq!(exchange.one_way_latencies(50.0), (40.0, 0.0));
+    }
+
+    #[test]
+    fn test_clock_filter_keeps_lowest_round_trip() {
+        let mut filter = ClockFilter::default();
+        assert_eq!(filter.update(20.0, 100.0), 100.0);
+        assert_eq!(filter.update(40.0, 110.0), 100.0);
+        assert_eq!(filter.update(10.0, 98.0), 98.0);
+        for _ in 0..CLOCK_FILTER_SIZE {
+            filter.update(50.0, 120.0);
+        }
+        assert_eq!(filter.get_offset(), Some(120.0));
     }
 }
@@ -4,6 +4,7 @@ pub(crate) mod k8s;
 pub(crate) mod latency_estimation;
 pub(crate) mod node_query;
 pub(crate) mod node_situation;
+pub(crate) mod pricing;
 pub(crate) mod provisioned;
 pub(crate) mod resource_tracking;
 pub(crate) mod routing;
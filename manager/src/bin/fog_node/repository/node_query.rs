@@ -8,7 +8,7 @@ use serde::Serialize;
 
 use manager::model::dto::node::NodeDescription;
 use manager::model::view::auction::{BidProposals, BidRequest};
-use manager::model::view::node::RegisterNode;
+use manager::model::view::node::{RegisterNode, UnregisterNode};
 use manager::model::NodeId;
 
 use crate::NodeSituation;
@@ -29,6 +29,8 @@ pub enum Error {
 pub trait NodeQuery: Debug + Sync + Send {
     /// Update the breadcrumb route to the [BidId] passing by the next [NodeId].
     async fn register_to_parent(&self, register: RegisterNode) -> Result<(), Error>;
+    /// Tell the parent, or the market, that this node is leaving the network
+    async fn unregister_from_parent(&self, unregister: UnregisterNode) -> Result<(), Error>;
     async fn request_neighbor_bid(&self,
                                   request: BidRequest,
                                   node: NodeId)
@@ -61,23 +63,33 @@ impl NodeQueryRESTImpl {
             Err(Error::RequestStatus(response.status()))
         }
     }
-}
 
-#[async_trait]
-impl NodeQuery for NodeQueryRESTImpl {
-    async fn register_to_parent(&self, register: RegisterNode) -> Result<(), Error> {
-        trace!("Registering to parent or market...");
+    /// Post to the parent node, or the market if this node is at the top of the tree
+    async fn post_to_upper<T: Serialize + Sync>(&self, uri: &str, data: &T) -> Result<(), Error> {
         let upper_node_address = if self.node_situation.is_market().await {
             self.node_situation.get_market_node_address().await.ok_or(Error::NoURIToUpper)?
         } else {
             self.node_situation.get_parent_node_address().await.ok_or(Error::NoURIToUpper)?
         };
 
-        // Both the market and node APIs offer the same endpoint.
-        trace!("Registering to {}:{}", upper_node_address.0, upper_node_address.1);
-        self.post(&upper_node_address.0, &upper_node_address.1, "register", &register).await?;
+        // Both the market and node APIs offer the same endpoints.
+        trace!("Posting {} to {}:{}", uri, upper_node_address.0, upper_node_address.1);
+        self.post(&upper_node_address.0, &upper_node_address.1, uri, data).await?;
         Ok(())
     }
+}
+
+#[async_trait]
+impl NodeQuery for NodeQueryRESTImpl {
+    async fn register_to_parent(&self, register: RegisterNode) -> Result<(), Error> {
+        trace!("Registering to parent or market...");
+        self.post_to_upper("register", &register).await
+    }
+
+    async fn unregister_from_parent(&self, unregister: UnregisterNode) -> Result<(), Error> {
+        trace!("Unregistering from parent or market...");
+        self.post_to_upper("unregister", &unregister).await
+    }
 
     async fn request_neighbor_bid(&self,
                                   request: BidRequest,
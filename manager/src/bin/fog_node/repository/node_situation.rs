@@ -1,16 +1,27 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::net::IpAddr;
 
 use async_trait::async_trait;
 use tokio::sync::RwLock;
 
+use manager::helper::snapshot::{Persisted, Snapshot};
+use manager::model::domain::liveness::Liveness;
 use manager::model::dto::node::NodeSituationData::{MarketConnected, NodeConnected};
-use manager::model::dto::node::{NodeDescription, NodeSituationData};
+use manager::model::dto::node::{FallbackParent, NodeDescription, NodeSituationData};
 use manager::model::NodeId;
 
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Snapshot(#[from] manager::helper::snapshot::Error),
+}
+
 #[async_trait]
 pub trait NodeSituation: Debug + Sync + Send {
-    async fn register(&self, id: NodeId, description: NodeDescription);
+    async fn register(&self, id: NodeId, description: NodeDescription) -> Result<(), Error>;
+    /// Forget about a child, returning whether it was known
+    async fn unregister(&self, id: &NodeId) -> Result<bool, Error>;
     /// Get a node: children, parent
     async fn get_fog_node_neighbor(&self, id: &NodeId) -> Option<NodeDescription>;
     async fn get_my_id(&self) -> NodeId;
@@ -20,10 +31,21 @@ pub trait NodeSituation: Debug + Sync + Send {
     /// network)
     async fn is_market(&self) -> bool;
     async fn get_parent_node_address(&self) -> Option<(IpAddr, u16)>;
+    /// Replace the parent by the first fallback parent, the former one becoming the last fallback.
+    /// Return the new parent, if any.
+    async fn failover_parent(&self) -> Option<NodeId>;
+    /// Replace the parent, that left the network, by its own parent. Return whether this node
+    /// had a parent to replace.
+    async fn set_parent(&self, id: NodeId, description: NodeDescription) -> bool;
     async fn get_market_node_address(&self) -> Option<(IpAddr, u16)>;
     /// Return iter over both the parent and the children node...
     /// Aka all the nodes interesting that can accommodate a function
     async fn get_neighbors(&self) -> Vec<NodeId>;
+    /// Same as [NodeSituation::get_neighbors], without the ones detected as [Liveness::Dead]
+    async fn get_live_neighbors(&self) -> Vec<NodeId>;
+    /// Liveness of a neighbor, [Liveness::Alive] until detected otherwise
+    async fn get_liveness(&self, id: &NodeId) -> Liveness;
+    async fn set_liveness(&self, id: NodeId, liveness: Liveness);
     /// Get the public ip associated with this server
     async fn get_my_public_ip(&self) -> IpAddr;
     /// Get the public port associated with this server
@@ -32,34 +54,61 @@ pub trait NodeSituation: Debug + Sync + Send {
 
 #[derive(Debug)]
 pub struct NodeSituationHashSetImpl {
+    /// The configuration of the node, except for the children
     database: RwLock<NodeSituationData>,
+    /// The registered children, saved since they are not part of the configuration
+    children: Persisted<HashMap<NodeId, NodeDescription>>,
+    liveness: RwLock<HashMap<NodeId, Liveness>>,
+}
+
+/// Take the children out of the configuration
+fn take_children(situation: &mut NodeSituationData) -> HashMap<NodeId, NodeDescription> {
+    match situation {
+        MarketConnected { children, .. } | NodeConnected { children, .. } => {
+            std::mem::take(children)
+        }
+    }
 }
 
 impl NodeSituationHashSetImpl {
-    pub fn new(situation: NodeSituationData) -> Self { Self { database: RwLock::new(situation) } }
+    pub fn new(mut situation: NodeSituationData) -> Self {
+        Self { children: Persisted::new(take_children(&mut situation)),
+               database: RwLock::new(situation),
+               liveness: RwLock::new(HashMap::new()), }
+    }
+
+    /// The children come from the [Snapshot] if any were saved, from the configuration
+    /// otherwise
+    pub async fn load(mut situation: NodeSituationData, snapshot: Snapshot) -> Result<Self, Error> {
+        Ok(Self { children: Persisted::load_or(snapshot, take_children(&mut situation)).await?,
+                  database: RwLock::new(situation),
+                  liveness: RwLock::new(HashMap::new()), })
+    }
 }
 
 #[async_trait]
 impl NodeSituation for NodeSituationHashSetImpl {
-    async fn register(&self, id: NodeId, description: NodeDescription) {
-        match &mut *self.database.write().await {
-            MarketConnected { children, .. } | NodeConnected { children, .. } => {
-                children.insert(id, description);
-            }
-        }
+    async fn register(&self, id: NodeId, description: NodeDescription) -> Result<(), Error> {
+        self.children.write(|children| children.insert(id, description)).await?;
+        Ok(())
+    }
+
+    async fn unregister(&self, id: &NodeId) -> Result<bool, Error> {
+        self.liveness.write().await.remove(id);
+        Ok(self.children.write(|children| children.remove(id).is_some()).await?)
     }
 
     async fn get_fog_node_neighbor(&self, id: &NodeId) -> Option<NodeDescription> {
+        if let Some(child) = self.children.read().await.get(id) {
+            return Some(child.clone());
+        }
         match &*self.database.read().await {
-            MarketConnected { children, .. } => children.get(id).cloned(),
-            NodeConnected { children, parent_node_ip, parent_node_port, parent_id, .. } => {
-                let ret = children.get(id).cloned();
-                if ret.is_none() && parent_id == id {
-                    return Some(NodeDescription { ip:   *parent_node_ip,
-                                                  port: *parent_node_port, });
-                }
-                ret
+            NodeConnected { parent_node_ip, parent_node_port, parent_id, .. }
+                if parent_id == id =>
+            {
+                Some(NodeDescription { ip: *parent_node_ip, port: *parent_node_port })
             }
+            _ => None,
         }
     }
 
@@ -95,6 +144,46 @@ impl NodeSituation for NodeSituationHashSetImpl {
         }
     }
 
+    async fn failover_parent(&self) -> Option<NodeId> {
+        let new_parent = match &mut *self.database.write().await {
+            NodeConnected { parent_id, parent_node_ip, parent_node_port, fallback_parents, .. } => {
+                if fallback_parents.is_empty() {
+                    return None;
+                }
+                let fallback = fallback_parents.remove(0);
+                let former = FallbackParent { parent_id:
+                                                  std::mem::replace(parent_id, fallback.parent_id),
+                                              parent_node_ip:
+                                                  std::mem::replace(parent_node_ip,
+                                                                    fallback.parent_node_ip),
+                                              parent_node_port:
+                                                  std::mem::replace(parent_node_port,
+                                                                    fallback.parent_node_port), };
+                fallback_parents.push(former);
+                parent_id.clone()
+            }
+            MarketConnected { .. } => return None,
+        };
+        // Give a fresh start to a former parent that was once detected as dead
+        self.liveness.write().await.remove(&new_parent);
+        Some(new_parent)
+    }
+
+    async fn set_parent(&self, id: NodeId, description: NodeDescription) -> bool {
+        let former = match &mut *self.database.write().await {
+            NodeConnected { parent_id, parent_node_ip, parent_node_port, .. } => {
+                *parent_node_ip = description.ip;
+                *parent_node_port = description.port;
+                std::mem::replace(parent_id, id.clone())
+            }
+            MarketConnected { .. } => return false,
+        };
+        let mut liveness = self.liveness.write().await;
+        liveness.remove(&former);
+        liveness.remove(&id);
+        true
+    }
+
     async fn get_market_node_address(&self) -> Option<(IpAddr, u16)> {
         match &*self.database.read().await {
             MarketConnected { market_ip, market_port, .. } => Some((*market_ip, *market_port)),
@@ -103,14 +192,32 @@ impl NodeSituation for NodeSituationHashSetImpl {
     }
 
     async fn get_neighbors(&self) -> Vec<NodeId> {
+        let children = self.children.read().await;
         match &*self.database.read().await {
-            MarketConnected { children, .. } => children.keys().cloned().collect(),
-            NodeConnected { parent_id, children, .. } => {
+            MarketConnected { .. } => children.keys().cloned().collect(),
+            NodeConnected { parent_id, .. } => {
                 vec![parent_id.clone()].into_iter().chain(children.keys().cloned()).collect()
             }
         }
     }
 
+    async fn get_live_neighbors(&self) -> Vec<NodeId> {
+        let liveness = self.liveness.read().await;
+        self.get_neighbors()
+            .await
+            .into_iter()
+            .filter(|id| liveness.get(id) != Some(&Liveness::Dead))
+            .collect()
+    }
+
+    async fn get_liveness(&self, id: &NodeId) -> Liveness {
+        self.liveness.read().await.get(id).copied().unwrap_or_default()
+    }
+
+    async fn set_liveness(&self, id: NodeId, liveness: Liveness) {
+        self.liveness.write().await.insert(id, liveness);
+    }
+
     async fn get_my_public_ip(&self) -> IpAddr {
         *match &*self.database.read().await {
             MarketConnected { my_public_ip, .. } | NodeConnected { my_public_ip, .. } => {
@@ -0,0 +1,200 @@
+use std::fmt::Debug;
+
+use uom::si::f64::{Information, Ratio};
+use uom::si::information::gigabyte;
+
+use manager::helper::uom::cpu_ratio::cpu;
+use manager::model::domain::pricing::Pricing;
+use manager::model::domain::sla::Sla;
+
+/// The resources of the node the [Sla] is priced on
+#[derive(Debug, Clone, Copy)]
+pub struct NodeResources {
+    /// Including the resources reserved by pending bids
+    pub used_ram:      Information,
+    pub used_cpu:      Ratio,
+    pub available_ram: Information,
+    pub available_cpu: Ratio,
+}
+
+pub trait PricingStrategy: Debug + Sync + Send {
+    /// Compute the bid to host the [Sla] on the node
+    fn price(&self, sla: &Sla, node: &NodeResources) -> f64;
+
+    /// The strategy implemented, with its parameters
+    fn pricing(&self) -> Pricing;
+}
+
+/// Price of the resources asked by the [Sla], per GB of memory and per CPU
+fn linear_price(sla: &Sla, memory_price: f64, cpu_price: f64) -> f64 {
+    sla.memory.get::<gigabyte>() * memory_price + sla.cpu.get::<cpu>() * cpu_price
+}
+
+#[derive(Debug)]
+pub struct UtilizationPricing;
+
+impl UtilizationPricing {
+    pub fn new() -> Self { Self {} }
+}
+
+impl PricingStrategy for UtilizationPricing {
+    fn price(&self, sla: &Sla, node: &NodeResources) -> f64 {
+        let cpu_left = node.available_cpu - node.used_cpu;
+        let ram_left = node.available_ram - node.used_ram;
+
+        let price = sla.memory / ram_left
+                    * (Information::new::<gigabyte>(1.0) / node.available_ram)
+                    + sla.cpu / cpu_left * (Ratio::new::<cpu>(1.0) / node.available_cpu);
+
+        price.into()
+    }
+
+    fn pricing(&self) -> Pricing { Pricing::Utilization }
+}
+
+#[derive(Debug)]
+pub struct LinearPricing {
+    memory_price: f64,
+    cpu_price:    f64,
+}
+
+impl LinearPricing {
+    pub fn new(memory_price: f64, cpu_price: f64) -> Self { Self { memory_price, cpu_price } }
+}
+
+impl PricingStrategy for LinearPricing {
+    fn price(&self, sla: &Sla, _node: &NodeResources) -> f64 {
+        linear_price(sla, self.memory_price, self.cpu_price)
+    }
+
+    fn pricing(&self) -> Pricing {
+        Pricing::Linear { memory_price: self.memory_price, cpu_price: self.cpu_price }
+    }
+}
+
+#[derive(Debug)]
+pub struct SurgePricing {
+    memory_price: f64,
+    cpu_price:    f64,
+    threshold:    f64,
+    surge_factor: f64,
+}
+
+impl SurgePricing {
+    pub fn new(memory_price: f64, cpu_price: f64, threshold: f64, surge_factor: f64) -> Self {
+        Self { memory_price, cpu_price, threshold, surge_factor }
+    }
+}
+
+impl PricingStrategy for SurgePricing {
+    fn price(&self, sla: &Sla, node: &NodeResources) -> f64 {
+        let ram_utilization: f64 = ((node.used_ram + sla.memory) / node.available_ram).into();
+        let cpu_utilization: f64 = ((node.used_cpu + sla.cpu) / node.available_cpu).into();
+        let utilization = ram_utilization.max(cpu_utilization).min(1.0);
+
+        let surge = if utilization > self.threshold {
+            let over = (utilization - self.threshold) / (1.0 - self.threshold);
+            1.0 + self.surge_factor * over * over
+        } else {
+            1.0
+        };
+
+        linear_price(sla, self.memory_price, self.cpu_price) * surge
+    }
+
+    fn pricing(&self) -> Pricing {
+        Pricing::Surge { memory_price: self.memory_price,
+                         cpu_price:    self.cpu_price,
+                         threshold:    self.threshold,
+                         surge_factor: self.surge_factor, }
+    }
+}
+
+#[derive(Debug)]
+pub struct CostPlusMarginPricing {
+    fixed_cost:  f64,
+    memory_cost: f64,
+    cpu_cost:    f64,
+    margin:      f64,
+}
+
+impl CostPlusMarginPricing {
+    pub fn new(fixed_cost: f64, memory_cost: f64, cpu_cost: f64, margin: f64) -> Self {
+        Self { fixed_cost, memory_cost, cpu_cost, margin }
+    }
+}
+
+impl PricingStrategy for CostPlusMarginPricing {
+    fn price(&self, sla: &Sla, _node: &NodeResources) -> f64 {
+        (self.fixed_cost + linear_price(sla, self.memory_cost, self.cpu_cost)) * (1.0 + self.margin)
+    }
+
+    fn pricing(&self) -> Pricing {
+        Pricing::CostPlusMargin { fixed_cost:  self.fixed_cost,
+                                  memory_cost: self.memory_cost,
+                                  cpu_cost:    self.cpu_cost,
+                                  margin:      self.margin, }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::f64::Time;
+    use uom::si::time::second;
+
+    use super::*;
+
+    fn sla(memory_gb: f64, cpus: f64) -> Sla {
+        Sla { storage:              Information::new::<gigabyte>(0.0),
+              memory:               Information::new::<gigabyte>(memory_gb),
+              cpu:                  Ratio::new::<cpu>(cpus),
+              latency_max:          Time::new::<second>(1.0),
+              data_input_max_size:  Information::new::<gigabyte>(0.0),
+              data_output_max_size: Information::new::<gigabyte>(0.0),
+              max_time_before_hot:  Time::new::<second>(0.0),
+              reevaluation_period:  Time::new::<second>(0.0),
+              function_image:       "image".to_string(),
+              function_live_name:   None,
+              placement:            None, }
+    }
+
+    fn node(used_gb: f64, used_cpus: f64) -> NodeResources {
+        NodeResources { used_ram:      Information::new::<gigabyte>(used_gb),
+                        used_cpu:      Ratio::new::<cpu>(used_cpus),
+                        available_ram: Information::new::<gigabyte>(10.0),
+                        available_cpu: Ratio::new::<cpu>(10.0), }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_linear() {
+        let pricing = LinearPricing::new(2.0, 3.0);
+        assert_close(pricing.price(&sla(1.0, 0.5), &node(0.0, 0.0)), 3.5);
+        assert_close(pricing.price(&sla(1.0, 0.5), &node(9.0, 9.0)), 3.5);
+    }
+
+    #[test]
+    fn test_surge() {
+        let pricing = SurgePricing::new(2.0, 3.0, 0.5, 4.0);
+        assert_close(pricing.price(&sla(1.0, 0.5), &node(0.0, 0.0)), 3.5);
+        // Memory utilization at 0.75 once placed: half way from the threshold to full
+        assert_close(pricing.price(&sla(1.0, 0.5), &node(6.5, 0.0)), 3.5 * 2.0);
+        assert_close(pricing.price(&sla(1.0, 0.5), &node(9.0, 0.0)), 3.5 * 5.0);
+    }
+
+    #[test]
+    fn test_cost_plus_margin() {
+        let pricing = CostPlusMarginPricing::new(1.0, 2.0, 3.0, 0.2);
+        assert_close(pricing.price(&sla(1.0, 0.5), &node(0.0, 0.0)), 4.5 * 1.2);
+    }
+
+    #[test]
+    fn test_utilization_grows_with_usage() {
+        let pricing = UtilizationPricing::new();
+        assert!(pricing.price(&sla(1.0, 0.5), &node(0.0, 0.0))
+                < pricing.price(&sla(1.0, 0.5), &node(5.0, 5.0)));
+    }
+}
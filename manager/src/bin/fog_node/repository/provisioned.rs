@@ -2,33 +2,54 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 
 use async_trait::async_trait;
-use tokio::sync::RwLock;
 
+use manager::helper::snapshot::{Persisted, Snapshot};
 use manager::model::dto::faas::ProvisionedRecord;
 use manager::model::BidId;
 
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Snapshot(#[from] manager::helper::snapshot::Error),
+}
+
 #[async_trait]
 pub trait Provisioned: Debug + Sync + Send {
-    async fn insert(&self, id: BidId, record: ProvisionedRecord);
+    async fn insert(&self, id: BidId, record: ProvisionedRecord) -> Result<(), Error>;
     async fn get(&self, id: &BidId) -> Option<ProvisionedRecord>;
+    async fn remove(&self, id: &BidId) -> Result<Option<ProvisionedRecord>, Error>;
+    async fn get_all(&self) -> Vec<(BidId, ProvisionedRecord)>;
 }
 
 #[derive(Debug)]
 pub struct ProvisionedHashMapImpl {
-    database: RwLock<HashMap<BidId, ProvisionedRecord>>,
+    database: Persisted<HashMap<BidId, ProvisionedRecord>>,
 }
 
 impl ProvisionedHashMapImpl {
-    pub fn new() -> Self { Self { database: RwLock::new(HashMap::new()) } }
+    pub fn new() -> Self { Self { database: Persisted::new(HashMap::new()) } }
+
+    pub async fn load(snapshot: Snapshot) -> Result<Self, Error> {
+        Ok(Self { database: Persisted::load(snapshot).await? })
+    }
 }
 
 #[async_trait]
 impl Provisioned for ProvisionedHashMapImpl {
-    async fn insert(&self, id: BidId, bid: ProvisionedRecord) {
-        self.database.write().await.insert(id, bid);
+    async fn insert(&self, id: BidId, bid: ProvisionedRecord) -> Result<(), Error> {
+        self.database.write(|database| database.insert(id, bid)).await?;
+        Ok(())
     }
 
     async fn get(&self, id: &BidId) -> Option<ProvisionedRecord> {
         self.database.read().await.get(id).cloned()
     }
+
+    async fn remove(&self, id: &BidId) -> Result<Option<ProvisionedRecord>, Error> {
+        Ok(self.database.write(|database| database.remove(id)).await?)
+    }
+
+    async fn get_all(&self) -> Vec<(BidId, ProvisionedRecord)> {
+        self.database.read().await.iter().map(|(id, record)| (id.clone(), record.clone())).collect()
+    }
 }
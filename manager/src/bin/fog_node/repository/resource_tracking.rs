@@ -2,14 +2,20 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use crate::prom_metrics::{CPU_AVAILABLE_GAUGE, CPU_USED_GAUGE, MEMORY_AVAILABLE_GAUGE,
+use crate::prom_metrics::{CPU_ALLOCATABLE_GAUGE, CPU_AVAILABLE_GAUGE, CPU_USAGE_GAUGE,
+                          CPU_USED_GAUGE, FUNCTION_CPU_USAGE_GAUGE, FUNCTION_MEMORY_USAGE_GAUGE,
+                          MEMORY_ALLOCATABLE_GAUGE, MEMORY_AVAILABLE_GAUGE, MEMORY_USAGE_GAUGE,
                           MEMORY_USED_GAUGE};
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use uom::si::f64::{Information, Ratio};
 use uom::si::information::byte;
 use uom::si::ratio::part_per_billion;
 
+use manager::helper::snapshot::{Persisted, Snapshot};
+use manager::helper::uom::{information, ratio};
+
 use crate::repository::k8s::K8s;
 use crate::repository::resource_tracking::Error::NonExistentName;
 
@@ -19,8 +25,30 @@ pub enum Error {
     NonExistentName,
     #[error(transparent)]
     K8S(#[from] crate::repository::k8s::Error),
-    #[error("The particular metric name was not found")]
-    MetricsNotFound,
+    #[error(transparent)]
+    Snapshot(#[from] manager::helper::snapshot::Error),
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Resources {
+    #[serde_as(as = "information::Helper")]
+    memory: Information,
+    #[serde_as(as = "ratio::Helper")]
+    cpu:    Ratio,
+}
+
+impl From<Resources> for (Information, Ratio) {
+    fn from(Resources { memory, cpu }: Resources) -> Self { (memory, cpu) }
+}
+
+/// The resources used by the provisioned functions and reserved by the pending bids
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Usage {
+    /// Kept for the nodes that disappear, so their functions are still accounted for if they
+    /// come back
+    used:     HashMap<String, Resources>,
+    reserved: HashMap<String, Resources>,
 }
 
 /// Behaviour of the routing
@@ -30,60 +58,64 @@ pub trait ResourceTracking: Debug + Sync + Send {
     async fn update_used(&self, name: String, memory: Information, cpu: Ratio)
                          -> Result<(), Error>;
 
+    /// Update the resources of a node given its name that are reserved by pending bids
+    async fn update_reserved(&self,
+                             name: String,
+                             memory: Information,
+                             cpu: Ratio)
+                             -> Result<(), Error>;
+
+    /// Get the reserved (memory, cpu), i.e., held by bids that are not yet accepted.
+    async fn get_reserved(&self, name: &'_ str) -> Result<(Information, Ratio), Error>;
+
     /// Get the used (memory, cpu).
     async fn get_used(&self, name: &'_ str) -> Result<(Information, Ratio), Error>;
 
     /// Get the available (memory, cpu).
-    /// This value is the total available resources at the startup;
+    /// This value is the total available resources as of the last reconciliation;
     /// it needs to be put in perspective with the usage values.
     async fn get_available(&self, name: &'_ str) -> Result<(Information, Ratio), Error>;
 
     /// Get all the detected nodes connected
-    fn get_nodes(&self) -> &Vec<String>;
+    async fn get_nodes(&self) -> Vec<String>;
+
+    /// Refresh the available resources and the nodes from k8s.
+    /// The consumption of the provisioned functions is taken off the usage of the nodes, as it
+    /// is already accounted for by the used resources.
+    async fn reconcile(&self) -> Result<(), Error>;
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct ResourceTrackingImpl {
+    k8s:                 Arc<dyn K8s>,
     resources_available: RwLock<HashMap<String, (Information, Ratio)>>,
-    resources_used:      RwLock<HashMap<String, (Information, Ratio)>>,
-    nodes:               Vec<String>,
+    /// Saved, so that the functions provisioned before a restart are not counted twice; the
+    /// available resources are always fetched from k8s
+    usage:               Persisted<Usage>,
+    nodes:               RwLock<Vec<String>>,
 }
 
 impl ResourceTrackingImpl {
     pub async fn new(k8s: Arc<dyn K8s>) -> Result<Self, Error> {
-        let aggregated_metrics = k8s.get_k8s_metrics().await?;
-
-        let resources_available: Result<HashMap<_, _>, Error> = aggregated_metrics
-            .iter()
-            .map(|(name, metrics)| -> Result<(String, (Information, Ratio)), Error> {
-                let allocatable = metrics.allocatable.as_ref().ok_or(Error::MetricsNotFound)?;
-                let used = metrics.usage.as_ref().ok_or(Error::MetricsNotFound)?;
-                let free_cpu = allocatable.cpu - used.cpu;
-                let free_ram = allocatable.memory - used.memory;
-                Ok((name.clone(), (free_ram, free_cpu)))
-            })
-            .collect();
-        let resources_available = resources_available?;
-
-        let resources_used: HashMap<_, _> = aggregated_metrics.iter()
-                                                              .map(|(name, _)| {
-                                                                  (name.clone(),
-                                   (Information::new::<byte>(0.0),
-                                    Ratio::new::<part_per_billion>(0.0)))
-                                                              })
-                                                              .collect();
-        let resources_used = RwLock::new(resources_used);
-
-        let nodes = resources_available.keys().cloned().collect();
+        Self::with_usage(k8s, Persisted::new(Usage::default())).await
+    }
 
-        let resources_available = RwLock::new(resources_available);
+    pub async fn load(k8s: Arc<dyn K8s>, snapshot: Snapshot) -> Result<Self, Error> {
+        Self::with_usage(k8s, Persisted::load(snapshot).await?).await
+    }
 
-        Ok(Self { resources_available, resources_used, nodes })
+    async fn with_usage(k8s: Arc<dyn K8s>, usage: Persisted<Usage>) -> Result<Self, Error> {
+        let tracking = Self { k8s,
+                              resources_available: RwLock::new(HashMap::new()),
+                              usage,
+                              nodes: RwLock::new(Vec::new()) };
+        tracking.reconcile().await?;
+        Ok(tracking)
     }
 
     /// Check if the key exists in all storages
     async fn key_exists(&self, name: &str) -> Result<(), Error> {
-        if self.resources_used.read().await.contains_key(name)
+        if self.usage.read().await.used.contains_key(name)
            && self.resources_available.read().await.contains_key(name)
         {
             return Ok(());
@@ -94,7 +126,7 @@ impl ResourceTrackingImpl {
     /// Update the Prometheus metrics
     async fn update_metrics(&self, name: &'_ str) -> Result<(), Error> {
         let (used_mem, used_cpu) =
-            *self.resources_used.read().await.get(name).ok_or(Error::NonExistentName)?;
+            (*self.usage.read().await.used.get(name).ok_or(Error::NonExistentName)?).into();
 
         let (avail_mem, avail_cpu) =
             *self.resources_available.read().await.get(name).ok_or(Error::NonExistentName)?;
@@ -116,15 +148,38 @@ impl ResourceTracking for ResourceTrackingImpl {
                          cpu: Ratio)
                          -> Result<(), Error> {
         let _ = self.key_exists(&name).await?;
-        self.resources_used.write().await.insert(name.clone(), (memory, cpu));
+        self.usage
+            .write(|usage| usage.used.insert(name.clone(), Resources { memory, cpu }))
+            .await?;
         let _ = self.update_metrics(&name).await?;
         Ok(())
     }
 
+    async fn update_reserved(&self,
+                             name: String,
+                             memory: Information,
+                             cpu: Ratio)
+                             -> Result<(), Error> {
+        let _ = self.key_exists(&name).await?;
+        self.usage.write(|usage| usage.reserved.insert(name, Resources { memory, cpu })).await?;
+        Ok(())
+    }
+
+    async fn get_reserved(&self, name: &'_ str) -> Result<(Information, Ratio), Error> {
+        let _ = self.key_exists(name).await?;
+        self.usage
+            .read()
+            .await
+            .reserved
+            .get(name)
+            .map(|reserved| (*reserved).into())
+            .ok_or(Error::NonExistentName)
+    }
+
     async fn get_used(&self, name: &'_ str) -> Result<(Information, Ratio), Error> {
         let _ = self.key_exists(name).await?;
         let _ = self.update_metrics(name).await?;
-        Ok(*self.resources_used.read().await.get(name).unwrap())
+        Ok((*self.usage.read().await.used.get(name).unwrap()).into())
     }
 
     async fn get_available(&self, name: &'_ str) -> Result<(Information, Ratio), Error> {
@@ -133,5 +188,124 @@ impl ResourceTracking for ResourceTrackingImpl {
         Ok(*self.resources_available.read().await.get(name).unwrap())
     }
 
-    fn get_nodes(&self) -> &Vec<String> { &self.nodes }
+    async fn get_nodes(&self) -> Vec<String> { self.nodes.read().await.clone() }
+
+    async fn reconcile(&self) -> Result<(), Error> {
+        let aggregated_metrics = self.k8s.get_k8s_metrics().await?;
+        let functions_usage = self.k8s.get_k8s_functions_usage().await?;
+        let zero = (Information::new::<byte>(0.0), Ratio::new::<part_per_billion>(0.0));
+        let none = Resources { memory: zero.0, cpu: zero.1 };
+
+        let mut available = HashMap::new();
+        for (name, metrics) in aggregated_metrics.iter() {
+            let (allocatable, usage) = match (&metrics.allocatable, &metrics.usage) {
+                (Some(allocatable), Some(usage)) => (allocatable, usage),
+                _ => {
+                    warn!("Incomplete metrics for the node {}, skipping it", name);
+                    continue;
+                }
+            };
+            MEMORY_ALLOCATABLE_GAUGE.with_label_values(&[name]).set(allocatable.memory.value);
+            MEMORY_USAGE_GAUGE.with_label_values(&[name]).set(usage.memory.value);
+            CPU_ALLOCATABLE_GAUGE.with_label_values(&[name]).set(allocatable.cpu.value);
+            CPU_USAGE_GAUGE.with_label_values(&[name]).set(usage.cpu.value);
+
+            let (functions_mem, functions_cpu) =
+                functions_usage.get(name)
+                               .into_iter()
+                               .flatten()
+                               .fold(zero, |(mem, cpu), (function, usage)| {
+                                   FUNCTION_MEMORY_USAGE_GAUGE.with_label_values(&[function, name])
+                                                              .set(usage.memory.value);
+                                   FUNCTION_CPU_USAGE_GAUGE.with_label_values(&[function, name])
+                                                           .set(usage.cpu.value);
+                                   (mem + usage.memory, cpu + usage.cpu)
+                               });
+
+            let free_ram = allocatable.memory - (usage.memory - functions_mem);
+            let free_cpu = allocatable.cpu - (usage.cpu - functions_cpu);
+            available.insert(name.clone(), (free_ram, free_cpu));
+        }
+
+        let mut nodes = available.keys().cloned().collect::<Vec<_>>();
+        nodes.sort();
+
+        self.usage
+            .write(|usage| {
+                for name in nodes.iter() {
+                    usage.used.entry(name.clone()).or_insert(none);
+                    usage.reserved.entry(name.clone()).or_insert(none);
+                }
+            })
+            .await?;
+
+        {
+            let mut known_nodes = self.nodes.write().await;
+            for name in nodes.iter().filter(|name| !known_nodes.contains(name)) {
+                info!("Detected the k8s node {}", name);
+            }
+            for name in known_nodes.iter().filter(|name| !nodes.contains(name)) {
+                warn!("The k8s node {} is gone", name);
+            }
+            *known_nodes = nodes.clone();
+        }
+        *self.resources_available.write().await = available;
+
+        for name in nodes.iter() {
+            self.update_metrics(name).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use manager::model::dto::k8s::{Allocatable, Metrics, Usage as K8sUsage};
+    use uom::si::information::mebibyte;
+    use uom::si::ratio::ratio;
+
+    use super::*;
+
+    /// A node running a single function
+    #[derive(Debug)]
+    struct FakeK8s;
+
+    #[async_trait]
+    impl K8s for FakeK8s {
+        async fn get_k8s_metrics(
+            &self)
+            -> Result<HashMap<String, Metrics>, crate::repository::k8s::Error> {
+            Ok(HashMap::from([("node".to_string(),
+                               Metrics { allocatable: Some(Allocatable { cpu:    Ratio::new::<ratio>(4.0),
+                                                                         memory: Information::new::<mebibyte>(1000.0), }),
+                                         usage:       Some(K8sUsage { cpu:    Ratio::new::<ratio>(1.5),
+                                                                      memory: Information::new::<mebibyte>(300.0), }), }),
+                              ("incomplete".to_string(), Metrics { allocatable: None, usage: None })]))
+        }
+
+        async fn get_k8s_functions_usage(
+            &self)
+            -> Result<HashMap<String, HashMap<String, K8sUsage>>, crate::repository::k8s::Error>
+        {
+            Ok(HashMap::from([("node".to_string(),
+                               HashMap::from([("function".to_string(),
+                                               K8sUsage { cpu:    Ratio::new::<ratio>(1.0),
+                                                          memory:
+                                                              Information::new::<mebibyte>(200.0), })]))]))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_leaves_out_the_usage_of_the_functions() {
+        let tracking = ResourceTrackingImpl::new(Arc::new(FakeK8s)).await.unwrap();
+
+        assert_eq!(tracking.get_nodes().await, vec!["node".to_string()]);
+        let (memory, cpu) = tracking.get_available("node").await.unwrap();
+        assert!((memory.get::<mebibyte>() - 900.0).abs() < 1e-6);
+        assert!((cpu.get::<ratio>() - 3.5).abs() < 1e-6);
+        let (memory, cpu) = tracking.get_used("node").await.unwrap();
+        assert_eq!((memory.get::<mebibyte>(), cpu.get::<ratio>()), (0.0, 0.0));
+        assert!(matches!(tracking.get_available("incomplete").await, Err(Error::NonExistentName)));
+    }
 }
@@ -3,10 +3,11 @@ use std::net::IpAddr;
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use reqwest::StatusCode;
+use reqwest::{Method, StatusCode};
 use serde::Serialize;
 
-use manager::model::domain::routing::Packet;
+use manager::model::domain::routing::{FunctionRoutingStack, Packet};
+use manager::model::dto::routing::RoutedResponse;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -21,12 +22,13 @@ pub enum Error {
 /// Behaviour of the routing
 #[async_trait]
 pub trait Routing: Debug + Sync + Send {
-    /// Forward to the url to be handled by the routing service of the node
+    /// Forward to the url to be handled by the routing service of the node.
+    /// The response is relayed whatever its status.
     async fn forward_to_routing(&self,
                                 ip: &IpAddr,
                                 port: &u16,
                                 packet: &Packet)
-                                -> Result<Bytes, Error>;
+                                -> Result<RoutedResponse, Error>;
 
     /// Forward to the url to be handled by arbitrary route
     async fn forward_to_url<'a, 'b, T>(&self,
@@ -36,6 +38,20 @@ pub trait Routing: Debug + Sync + Send {
                                        data: &'a T)
                                        -> Result<Bytes, Error>
         where T: Serialize + Send + Sync;
+
+    /// Register the route on the node, passing the stack to its routing service
+    async fn register_route(&self,
+                            ip: &IpAddr,
+                            port: &u16,
+                            stack: &FunctionRoutingStack)
+                            -> Result<(), Error>;
+
+    /// Unregister the route on the node, passing the stack to its routing service
+    async fn unregister_route(&self,
+                              ip: &IpAddr,
+                              port: &u16,
+                              stack: &FunctionRoutingStack)
+                              -> Result<(), Error>;
 }
 
 #[derive(Debug, Default)]
@@ -56,6 +72,24 @@ impl RoutingImpl {
                                           res.text().await.unwrap()))
         }
     }
+
+    async fn send_route(&self,
+                        method: Method,
+                        ip: &IpAddr,
+                        port: &u16,
+                        stack: &FunctionRoutingStack)
+                        -> Result<(), Error> {
+        let url = format!("http://{}:{}/api/routing", ip, port);
+        trace!("Sending route ({}) to {}", method, &url);
+        let client = reqwest::Client::new();
+        let res = client.request(method, &url).json(stack).send().await?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::ForwardingResponse(url, res.status(), res.text().await.unwrap_or_default()))
+        }
+    }
 }
 
 #[async_trait]
@@ -64,10 +98,12 @@ impl Routing for RoutingImpl {
                                 ip: &IpAddr,
                                 port: &u16,
                                 packet: &Packet)
-                                -> Result<Bytes, Error> {
+                                -> Result<RoutedResponse, Error> {
         let url = format!("http://{}:{}/api/routing", ip, port);
         trace!("Posting to routing on: {}", &url);
-        self.forward_to(&packet, &url).await
+        let client = reqwest::Client::new();
+        let res = client.post(&url).json(&packet).send().await?;
+        Ok(RoutedResponse::from_response(res).await?)
     }
 
     async fn forward_to_url<'a, 'b, T>(&self,
@@ -82,4 +118,20 @@ impl Routing for RoutingImpl {
         trace!("Posting (forward) to {}", &url);
         self.forward_to(data, &url).await
     }
+
+    async fn register_route(&self,
+                            ip: &IpAddr,
+                            port: &u16,
+                            stack: &FunctionRoutingStack)
+                            -> Result<(), Error> {
+        self.send_route(Method::PUT, ip, port, stack).await
+    }
+
+    async fn unregister_route(&self,
+                              ip: &IpAddr,
+                              port: &u16,
+                              stack: &FunctionRoutingStack)
+                              -> Result<(), Error> {
+        self.send_route(Method::DELETE, ip, port, stack).await
+    }
 }
@@ -1,9 +1,9 @@
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
-use manager::helper::uom::cpu_ratio::cpu;
+use tokio::sync::Mutex;
 use uom::si::f64::{Information, Ratio};
-use uom::si::information::gigabyte;
 
 use crate::prom_metrics::BID_GAUGE;
 use manager::model::domain::sla::Sla;
@@ -11,6 +11,7 @@ use manager::model::dto::auction::BidRecord;
 use manager::model::BidId;
 
 use crate::repository::auction::Auction as AuctionRepository;
+use crate::repository::pricing::{NodeResources, PricingStrategy};
 use crate::repository::resource_tracking::ResourceTracking;
 
 #[derive(thiserror::Error, Debug)]
@@ -21,55 +22,108 @@ pub enum Error {
     Unsatisfiable,
     #[error(transparent)]
     ResourceTracking(#[from] crate::repository::resource_tracking::Error),
+    #[error(transparent)]
+    AuctionRepository(#[from] crate::repository::auction::Error),
+    #[error("Failed to release the reservations of {} expired bids: {0:?}", .0.len())]
+    ExpiredBids(Vec<(BidId, Error)>),
 }
 
 #[async_trait]
 pub trait Auction: Send + Sync {
     /// Bid on the [Sla] and return the price.
+    /// The resources are reserved until the bid is validated or expires.
     async fn bid_on(&self, sla: Sla) -> Result<(BidId, BidRecord), Error>;
 
     /// Promote the bid to a full fledged provisioned function in the database.
     async fn validate_bid(&self, id: &BidId) -> Result<BidRecord, Error>;
+
+    /// Undo [Auction::validate_bid] when the function could not be provisioned: the resources
+    /// are reserved again and the bid can be validated anew until it expires.
+    async fn cancel_validation(&self, id: BidId, bid: BidRecord) -> Result<(), Error>;
+
+    /// Drop a pending bid and release its reservation.
+    async fn cancel_bid(&self, id: &BidId) -> Result<(), Error>;
+
+    /// Release the resources that were used by the provisioned bid.
+    async fn release_bid(&self, bid: &BidRecord) -> Result<(), Error>;
+
+    /// Forget the bids that were not validated in time and release their reservations.
+    async fn expire_bids(&self) -> Result<(), Error>;
 }
 
 pub struct AuctionImpl {
     resource_tracking: Arc<dyn ResourceTracking>,
     db:                Arc<dyn AuctionRepository>,
+    pricing:           Arc<dyn PricingStrategy>,
+    reservation_ttl:   Duration,
+    /// Held while checking and updating the resources, so concurrent bids cannot overcommit
+    resources_lock:    Mutex<()>,
 }
 
 impl AuctionImpl {
     pub async fn new(resource_tracking: Arc<dyn ResourceTracking>,
-                     db: Arc<dyn AuctionRepository>)
+                     db: Arc<dyn AuctionRepository>,
+                     pricing: Arc<dyn PricingStrategy>,
+                     reservation_ttl: Duration)
                      -> Self {
-        Self { resource_tracking, db }
+        Self { resource_tracking, db, pricing, reservation_ttl, resources_lock: Mutex::new(()) }
+    }
+
+    /// Add (or remove, with a negative sign) the resources of the [Sla] to the reservations of
+    /// the node
+    async fn reserve(&self, node: &str, sla: &Sla, sign: f64) -> Result<(), Error> {
+        let (reserved_mem, reserved_cpu) = self.resource_tracking.get_reserved(node).await?;
+        let reserved_mem = reserved_mem + sign * sla.memory;
+        let reserved_cpu = reserved_cpu + sign * sla.cpu;
+        self.resource_tracking
+            .update_reserved(node.to_string(), reserved_mem, reserved_cpu)
+            .await?;
+        Ok(())
     }
 
-    /// Get a suitable (free enough) node to potentially run the designated SLA
+    /// Add (or remove, with a negative sign) the resources of the [Sla] to the usage of the node
+    async fn use_resources(&self, node: &str, sla: &Sla, sign: f64) -> Result<(), Error> {
+        let (used_mem, used_cpu) = self.resource_tracking.get_used(node).await?;
+        let used_mem = used_mem + sign * sla.memory;
+        let used_cpu = used_cpu + sign * sla.cpu;
+        self.resource_tracking.update_used(node.to_string(), used_mem, used_cpu).await?;
+        Ok(())
+    }
+
+    /// Log the failure to undo a step of the validation of the bid [id]
+    fn log_rollback(id: &BidId, result: Result<(), Error>) {
+        if let Err(err) = result {
+            error!("Failed to roll back the validation of the bid {}: {}", id, err);
+        }
+    }
+
+    /// Get a suitable (free enough) node to potentially run the designated SLA.
+    /// The used resources include the ones reserved by pending bids.
     async fn get_a_node(&self,
                         sla: &Sla)
                         -> Result<(String, Information, Ratio, Information, Ratio), Error> {
-        for node in self.resource_tracking.get_nodes() {
-            let (used_ram, used_cpu) = self.resource_tracking.get_used(node).await?;
-            let (available_ram, available_cpu) = self.resource_tracking.get_available(node).await?;
+        for node in self.resource_tracking.get_nodes().await {
+            let (used_ram, used_cpu) = self.resource_tracking.get_used(&node).await?;
+            let (reserved_ram, reserved_cpu) = self.resource_tracking.get_reserved(&node).await?;
+            let used_ram = used_ram + reserved_ram;
+            let used_cpu = used_cpu + reserved_cpu;
+            let (available_ram, available_cpu) =
+                self.resource_tracking.get_available(&node).await?;
             if self.satisfiability_check(&used_ram, &used_cpu, &available_ram, &available_cpu, sla)
             {
-                return Ok((node.clone(), used_ram, used_cpu, available_ram, available_cpu));
+                return Ok((node, used_ram, used_cpu, available_ram, available_cpu));
             }
         }
         Err(Error::Unsatisfiable)
     }
 
-    /// Compute the bid value from the node environment
+    /// Compute the bid value from the node environment, using the pricing strategy
     async fn compute_bid(&self, sla: &Sla) -> Result<(String, f64), Error> {
         let (name, used_ram, used_cpu, available_ram, available_cpu) = self.get_a_node(sla).await?;
 
-        let cpu_left = available_cpu - used_cpu;
-        let ram_left = available_ram - used_ram;
-
-        let price = sla.memory / ram_left * (Information::new::<gigabyte>(1.0) / available_ram)
-                    + sla.cpu / cpu_left * (Ratio::new::<cpu>(1.0) / available_cpu);
-
-        let price: f64 = price.into();
+        let price =
+            self.pricing
+                .price(sla, &NodeResources { used_ram, used_cpu, available_ram, available_cpu });
 
         trace!("price on {:?} is {:?}", name, price);
 
@@ -94,9 +148,11 @@ impl AuctionImpl {
 #[async_trait]
 impl Auction for AuctionImpl {
     async fn bid_on(&self, sla: Sla) -> Result<(BidId, BidRecord), Error> {
+        let _lock = self.resources_lock.lock().await;
         let (node, bid) = self.compute_bid(&sla).await?;
-        let record = BidRecord { bid, sla, node };
-        let id = self.db.insert(record.to_owned()).await;
+        self.reserve(&node, &sla, 1.0).await?;
+        let record = BidRecord { bid, sla, node, pricing: self.pricing.pricing() };
+        let id = self.db.insert(record.to_owned(), Instant::now() + self.reservation_ttl).await?;
         BID_GAUGE.with_label_values(&[record.sla
                                             .function_live_name
                                             .as_ref()
@@ -107,15 +163,171 @@ impl Auction for AuctionImpl {
     }
 
     async fn validate_bid(&self, id: &BidId) -> Result<BidRecord, Error> {
-        let bid = self.db.get(id).await.ok_or_else(|| Error::BidIdNotFound(id.to_owned()))?.clone();
+        let _lock = self.resources_lock.lock().await;
+        let bid = self.db.get(id).await.ok_or_else(|| Error::BidIdNotFound(id.to_owned()))?;
 
-        self.db.remove(id).await;
-
-        let (used_mem, used_cpu) = self.resource_tracking.get_used(&bid.node).await?;
-        let used_mem = used_mem + bid.sla.memory;
-        let used_cpu = used_cpu + bid.sla.cpu;
-        self.resource_tracking.update_used(bid.node.clone(), used_mem, used_cpu).await?;
+        // The bid is removed last, so that it can be validated again if any step fails
+        self.reserve(&bid.node, &bid.sla, -1.0).await?;
+        if let Err(err) = self.use_resources(&bid.node, &bid.sla, 1.0).await {
+            Self::log_rollback(id, self.reserve(&bid.node, &bid.sla, 1.0).await);
+            return Err(err);
+        }
+        if let Err(err) = self.db.remove(id).await {
+            Self::log_rollback(id, self.use_resources(&bid.node, &bid.sla, -1.0).await);
+            Self::log_rollback(id, self.reserve(&bid.node, &bid.sla, 1.0).await);
+            return Err(err.into());
+        }
 
         Ok(bid)
     }
+
+    async fn cancel_validation(&self, id: BidId, bid: BidRecord) -> Result<(), Error> {
+        let _lock = self.resources_lock.lock().await;
+        self.use_resources(&bid.node, &bid.sla, -1.0).await?;
+        self.reserve(&bid.node, &bid.sla, 1.0).await?;
+        self.db.restore(id, bid, Instant::now() + self.reservation_ttl).await?;
+
+        Ok(())
+    }
+
+    async fn cancel_bid(&self, id: &BidId) -> Result<(), Error> {
+        let _lock = self.resources_lock.lock().await;
+        let bid = self.db.remove(id).await?.ok_or_else(|| Error::BidIdNotFound(id.to_owned()))?;
+        self.reserve(&bid.node, &bid.sla, -1.0).await?;
+        let _ = BID_GAUGE.remove_label_values(&[bid.sla
+                                                   .function_live_name
+                                                   .as_ref()
+                                                   .unwrap_or(&"unnamed".to_string()),
+                                                &id.to_string()]);
+        Ok(())
+    }
+
+    async fn release_bid(&self, bid: &BidRecord) -> Result<(), Error> {
+        let _lock = self.resources_lock.lock().await;
+        self.use_resources(&bid.node, &bid.sla, -1.0).await
+    }
+
+    async fn expire_bids(&self) -> Result<(), Error> {
+        let _lock = self.resources_lock.lock().await;
+        let mut errors = vec![];
+        for (id, bid) in self.db.remove_expired(Instant::now()).await? {
+            trace!("bid {} expired, releasing its reservation on {:?}", id, bid.node);
+            let _ = BID_GAUGE.remove_label_values(&[bid.sla
+                                                       .function_live_name
+                                                       .as_ref()
+                                                       .unwrap_or(&"unnamed".to_string()),
+                                                    &id.to_string()]);
+            if let Err(err) = self.reserve(&bid.node, &bid.sla, -1.0).await {
+                errors.push((id, err));
+            }
+        }
+        if !errors.is_empty() {
+            return Err(Error::ExpiredBids(errors));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use uom::si::f64::Time;
+    use uom::si::information::megabyte;
+    use uom::si::ratio::ratio;
+    use uom::si::time::second;
+
+    use crate::repository::pricing::LinearPricing;
+    use crate::repository::resource_tracking::Error as ResourceError;
+
+    use super::*;
+
+    /// A single node, failing to update its usage while [Resources::failing] is set
+    #[derive(Debug, Default)]
+    struct Resources {
+        used:     std::sync::Mutex<HashMap<String, (Information, Ratio)>>,
+        reserved: std::sync::Mutex<HashMap<String, (Information, Ratio)>>,
+        failing:  AtomicBool,
+    }
+
+    #[async_trait]
+    impl ResourceTracking for Resources {
+        async fn update_used(&self,
+                             name: String,
+                             memory: Information,
+                             cpu: Ratio)
+                             -> Result<(), ResourceError> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(ResourceError::NonExistentName);
+            }
+            self.used.lock().unwrap().insert(name, (memory, cpu));
+            Ok(())
+        }
+
+        async fn update_reserved(&self,
+                                 name: String,
+                                 memory: Information,
+                                 cpu: Ratio)
+                                 -> Result<(), ResourceError> {
+            self.reserved.lock().unwrap().insert(name, (memory, cpu));
+            Ok(())
+        }
+
+        async fn get_reserved(&self, name: &'_ str) -> Result<(Information, Ratio), ResourceError> {
+            Ok(self.reserved.lock().unwrap().get(name).copied().unwrap_or_default())
+        }
+
+        async fn get_used(&self, name: &'_ str) -> Result<(Information, Ratio), ResourceError> {
+            Ok(self.used.lock().unwrap().get(name).copied().unwrap_or_default())
+        }
+
+        async fn get_available(&self,
+                               _name: &'_ str)
+                               -> Result<(Information, Ratio), ResourceError> {
+            Ok((Information::new::<megabyte>(1000.0), Ratio::new::<ratio>(1.0)))
+        }
+
+        async fn get_nodes(&self) -> Vec<String> { vec!["node".to_string()] }
+
+        async fn reconcile(&self) -> Result<(), ResourceError> { Ok(()) }
+    }
+
+    fn sla() -> Sla {
+        Sla { storage:              Information::new::<megabyte>(0.0),
+              memory:               Information::new::<megabyte>(100.0),
+              cpu:                  Ratio::new::<ratio>(0.1),
+              latency_max:          Time::new::<second>(1.0),
+              data_input_max_size:  Information::new::<megabyte>(0.0),
+              data_output_max_size: Information::new::<megabyte>(0.0),
+              max_time_before_hot:  Time::new::<second>(0.0),
+              reevaluation_period:  Time::new::<second>(0.0),
+              function_image:       "image".to_string(),
+              function_live_name:   None,
+              placement:            None, }
+    }
+
+    #[tokio::test]
+    async fn test_failed_validation_keeps_the_bid_and_its_reservation() {
+        let resources = Arc::new(Resources::default());
+        let auction = AuctionImpl::new(resources.clone(),
+                                       Arc::new(crate::repository::auction::AuctionImpl::new()),
+                                       Arc::new(LinearPricing::new(1.0, 1.0)),
+                                       Duration::from_secs(60)).await;
+        let (id, _) = auction.bid_on(sla()).await.unwrap();
+        let reserved = resources.get_reserved("node").await.unwrap();
+        assert_eq!(reserved.0, sla().memory);
+
+        resources.failing.store(true, Ordering::SeqCst);
+        assert!(matches!(auction.validate_bid(&id).await, Err(Error::ResourceTracking(_))));
+        assert!(auction.db.get(&id).await.is_some());
+        assert_eq!(resources.get_reserved("node").await.unwrap(), reserved);
+        assert_eq!(resources.get_used("node").await.unwrap().0, Information::default());
+
+        resources.failing.store(false, Ordering::SeqCst);
+        auction.validate_bid(&id).await.unwrap();
+        assert!(auction.db.get(&id).await.is_none());
+        assert_eq!(resources.get_reserved("node").await.unwrap().0, Information::default());
+        assert_eq!(resources.get_used("node").await.unwrap().0, sla().memory);
+    }
 }
@@ -1,11 +1,13 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use uuid::Uuid;
 
 use manager::model::dto::auction::BidRecord;
 use manager::model::dto::faas::ProvisionedRecord;
-use manager::model::BidId;
+use manager::model::{BidId, NodeId};
 use manager::openfaas::models::{FunctionDefinition, Limits};
 use manager::openfaas::{DefaultApi, DefaultApiClient};
 
@@ -15,6 +17,10 @@ use crate::repository::provisioned::Provisioned as ProvisionedRepository;
 pub enum Error {
     #[error(transparent)]
     OpenFaaS(#[from] manager::openfaas::Error<String>),
+    #[error("The function of the bid {0} is not provisioned here")]
+    NotProvisioned(BidId),
+    #[error(transparent)]
+    Provisioned(#[from] crate::repository::provisioned::Error),
 }
 
 #[async_trait]
@@ -23,19 +29,45 @@ pub trait FaaSBackend: Debug + Sync + Send {
     /// Return the function's name
     async fn provision_function(&self, id: BidId, bid: BidRecord) -> Result<String, Error>;
     async fn get_provisioned_function(&self, id: &BidId) -> Option<ProvisionedRecord>;
+    async fn get_provisioned_functions(&self) -> Vec<(BidId, ProvisionedRecord)>;
+    /// Remove the function provisioned for the bid
+    /// Return the record of the removed function
+    async fn remove_function(&self, id: &BidId) -> Result<ProvisionedRecord, Error>;
+    /// Align the provisioned functions with the ones deployed, typically after a restart.
+    /// The deployed functions that have a record are adopted; the ones that this node
+    /// provisioned from a bid but have no record anymore are deleted.
+    /// Return the records of the functions that are not deployed anymore, after forgetting them.
+    async fn reconcile(&self) -> Result<Vec<ProvisionedRecord>, Error>;
+}
+
+/// Label of the functions holding the node that provisioned them, as several nodes may share
+/// the same OpenFaaS gateway
+const PROVISIONED_BY_LABEL: &str = "provisioned-by";
+
+/// Whether the function was provisioned from a bid, i.e., its name ends with the bid id
+fn is_provisioned_from_bid(function_name: &str) -> bool {
+    let uuid_len = Uuid::nil().to_string().len();
+    function_name.len()
+                 .checked_sub(uuid_len + 1)
+                 .and_then(|at| function_name.get(at..))
+                 .map(|suffix| suffix.starts_with('-') && Uuid::parse_str(&suffix[1..]).is_ok())
+                 .unwrap_or(false)
 }
 
 #[derive(Debug)]
 pub struct OpenFaaSBackend {
     client:                Arc<DefaultApiClient>,
     provisioned_functions: Arc<dyn ProvisionedRepository>,
+    /// This node, that the functions it provisions are labeled with
+    node:                  NodeId,
 }
 
 impl OpenFaaSBackend {
     pub fn new(client: Arc<DefaultApiClient>,
-               provisioned_functions: Arc<dyn ProvisionedRepository>)
+               provisioned_functions: Arc<dyn ProvisionedRepository>,
+               node: NodeId)
                -> Self {
-        Self { client, provisioned_functions }
+        Self { client, provisioned_functions, node }
     }
 }
 
@@ -46,17 +78,27 @@ impl FaaSBackend for OpenFaaSBackend {
                             + "-"
                             + id.to_string().as_str();
 
+        let labels = HashMap::from([(PROVISIONED_BY_LABEL.to_string(), self.node.to_string())]);
         let definition = FunctionDefinition { image: bid.sla.function_image.to_owned(),
                                               service: function_name.to_owned(),
                                               limits: Some(Limits { memory: bid.sla.memory,
                                                                     cpu:    bid.sla.cpu, }),
+                                              labels: Some(labels),
                                               ..Default::default() };
 
-        self.client.system_functions_post(definition).await?;
-
-        self.provisioned_functions
-            .insert(id, ProvisionedRecord { bid, function_name: function_name.to_owned() })
-            .await;
+        let record = ProvisionedRecord { bid, function_name: function_name.to_owned() };
+        let deployed = async {
+            self.client.system_functions_post(definition).await?;
+            self.provisioned_functions.insert(id, record).await?;
+            Ok::<(), Error>(())
+        };
+        if let Err(err) = deployed.await {
+            // The function may have been partially deployed, it must not outlive the failure
+            if let Err(err) = self.client.system_functions_delete(&function_name).await {
+                debug!("Nothing to clean up after failing to deploy {}: {}", function_name, err);
+            }
+            return Err(err);
+        }
 
         Ok(function_name)
     }
@@ -64,4 +106,75 @@ impl FaaSBackend for OpenFaaSBackend {
     async fn get_provisioned_function(&self, id: &BidId) -> Option<ProvisionedRecord> {
         self.provisioned_functions.get(id).await
     }
+
+    async fn get_provisioned_functions(&self) -> Vec<(BidId, ProvisionedRecord)> {
+        self.provisioned_functions.get_all().await
+    }
+
+    async fn remove_function(&self, id: &BidId) -> Result<ProvisionedRecord, Error> {
+        let record = self.provisioned_functions
+                         .get(id)
+                         .await
+                         .ok_or_else(|| Error::NotProvisioned(id.to_owned()))?;
+
+        self.client.system_functions_delete(&record.function_name).await?;
+
+        self.provisioned_functions.remove(id).await?;
+
+        Ok(record)
+    }
+
+    async fn reconcile(&self) -> Result<Vec<ProvisionedRecord>, Error> {
+        let node = self.node.to_string();
+        let mut deployed = HashSet::new();
+        let mut mine = HashSet::new();
+        for function in self.client.system_functions_get().await? {
+            if function.labels.get(PROVISIONED_BY_LABEL) == Some(&node) {
+                mine.insert(function.name.clone());
+            }
+            deployed.insert(function.name);
+        }
+        let provisioned = self.provisioned_functions.get_all().await;
+
+        let mut missing = vec![];
+        for (id, record) in provisioned.iter() {
+            if deployed.contains(&record.function_name) {
+                debug!("Adopted the function {}", record.function_name);
+                continue;
+            }
+            warn!("The function {} is not deployed anymore, forgetting it", record.function_name);
+            if let Some(record) = self.provisioned_functions.remove(id).await? {
+                missing.push(record);
+            }
+        }
+
+        let known =
+            provisioned.iter().map(|(_, record)| &record.function_name).collect::<HashSet<_>>();
+        for name in mine.iter()
+                        .filter(|name| !known.contains(name))
+                        .filter(|name| is_provisioned_from_bid(name))
+        {
+            warn!("The function {} has no record, deleting it", name);
+            if let Err(err) = self.client.system_functions_delete(name).await {
+                error!("Failed to delete the function {}: {}", name, err);
+            }
+        }
+
+        Ok(missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_functions_named_after_a_bid_are_provisioned_from_bids() {
+        let id = Uuid::new_v4();
+        assert!(is_provisioned_from_bid(&format!("echo-{}", id)));
+        assert!(is_provisioned_from_bid(&format!("-{}", id)));
+        assert!(!is_provisioned_from_bid(&format!("echo{}", id)));
+        assert!(!is_provisioned_from_bid("echo"));
+        assert!(!is_provisioned_from_bid("nodeinfo"));
+    }
 }
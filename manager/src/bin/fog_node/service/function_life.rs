@@ -1,11 +1,19 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use futures::future::{join, join_all};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 use uom::fmt::DisplayStyle::Abbreviation;
 use uom::si::f64::Time;
+use uom::si::time::second;
 
+use manager::model::domain::placement::Placement;
 use manager::model::domain::sla::Sla;
-use manager::model::view::auction::{BidProposal, BidProposals, BidRequest};
+use manager::model::view::auction::{BidFailure, BidFailureReason, BidProposal, BidProposals,
+                                    BidRequest};
 use manager::model::{BidId, NodeId};
 
 use crate::service::auction::Auction;
@@ -19,231 +27,676 @@ pub enum Error {
     Auction(#[from] crate::service::auction::Error),
     #[error(transparent)]
     FaaS(#[from] crate::service::faas::Error),
-    #[error(transparent)]
-    NodeQuery(#[from] crate::repository::node_query::Error),
-    #[error("Cannot get latency of node {0}")]
-    CannotGetLatency(NodeId),
-    #[error("No candidates were found or returned an Ok result")]
-    NoCandidatesRetained,
 }
 
+/// Share of its own time budget a node gives to its neighbors to answer, the rest is left for
+/// itself and the transport of the answer
+const SUBTREE_TIMEOUT_RATIO: f64 = 0.8;
+
 #[async_trait]
 pub trait FunctionLife: Send + Sync {
     /// Declares and bid on  the new function
     /// Will save the function in the database for later use
     ///
-    /// [from] is the last node that passed the request
+    /// [from] is the last node that passed the request, [timeout] the time left to answer.
+    /// The neighbors that fail to answer are reported alongside the bids that did arrive.
     async fn bid_on_new_function_and_transmit(&self,
                                               sla: Sla,
                                               from: NodeId,
-                                              accumulated_latency: Time)
+                                              accumulated_latency: Time,
+                                              timeout: Option<Time>)
                                               -> Result<BidProposals, Error>;
 
-    async fn validate_bid_and_provision_function(&self, id: BidId) -> Result<(), Error>;
+    /// Validate the bid and provision its function under the identity [function].
+    /// If the function cannot be provisioned, the validation is compensated: the bid is restored
+    /// as it was, to be validated again or to expire. Validating a bid whose function is already
+    /// provisioned does nothing, so that the market can retry safely.
+    async fn validate_bid_and_provision_function(&self,
+                                                 id: BidId,
+                                                 function: BidId)
+                                                 -> Result<(), Error>;
+
+    /// Drop the bid without provisioning anything, releasing its reservation
+    async fn cancel_bid(&self, id: BidId) -> Result<(), Error>;
+
+    /// Remove the function provisioned under the identity [id] and release its resources
+    async fn remove_function(&self, id: BidId) -> Result<(), Error>;
+
+    /// Reconcile the provisioned functions with the FaaS backend, releasing the resources of
+    /// the ones that are gone
+    async fn reconcile_functions(&self) -> Result<(), Error>;
 }
 
-#[cfg(not(feature = "bottom_up_placement"))]
-pub use auction_placement::*;
+pub struct FunctionLifeImpl {
+    function:         Arc<dyn FaaSBackend>,
+    auction:          Arc<dyn Auction>,
+    node_situation:   Arc<dyn NodeSituation>,
+    neighbor_monitor: Arc<dyn NeighborMonitor>,
+    node_query:       Arc<dyn NodeQuery>,
+    /// Used unless the [Sla] asks for another one
+    placement:        Placement,
+    /// Time to answer a bid request, unless the requester gave one
+    bid_timeout:      Duration,
+    /// Held per function while provisioning it, so that a retried validation waits for the
+    /// ongoing one without holding back the other functions
+    provisioning:     Mutex<HashMap<BidId, Arc<Mutex<()>>>>,
+}
 
-#[cfg(not(feature = "bottom_up_placement"))]
-mod auction_placement {
-    use super::*;
+impl FunctionLifeImpl {
+    pub fn new(function: Arc<dyn FaaSBackend>,
+               auction: Arc<dyn Auction>,
+               node_situation: Arc<dyn NodeSituation>,
+               neighbor_monitor: Arc<dyn NeighborMonitor>,
+               node_query: Arc<dyn NodeQuery>,
+               placement: Placement,
+               bid_timeout: Duration)
+               -> Self {
+        debug!("Built using FunctionLifeImpl service, placing with {:?}", placement);
+        Self { function,
+               auction,
+               node_situation,
+               neighbor_monitor,
+               node_query,
+               placement,
+               bid_timeout,
+               provisioning: Mutex::new(HashMap::new()) }
+    }
 
-    use futures::future::{join3, try_join_all};
-
-    pub struct FunctionLifeImpl {
-        function:         Arc<dyn FaaSBackend>,
-        auction:          Arc<dyn Auction>,
-        node_situation:   Arc<dyn NodeSituation>,
-        neighbor_monitor: Arc<dyn NeighborMonitor>,
-        node_query:       Arc<dyn NodeQuery>,
-    }
-
-    impl FunctionLifeImpl {
-        pub fn new(function: Arc<dyn FaaSBackend>,
-                   auction: Arc<dyn Auction>,
-                   node_situation: Arc<dyn NodeSituation>,
-                   neighbor_monitor: Arc<dyn NeighborMonitor>,
-                   node_query: Arc<dyn NodeQuery>)
-                   -> Self {
-            debug!("Built using FunctionLifeImpl service");
-            Self { function, auction, node_situation, neighbor_monitor, node_query }
-        }
-
-        /// Follow up the [Sla] to the neighbors, and ignore the path where it came from.
-        async fn follow_up_to_neighbors(&self,
-                                        sla: Sla,
-                                        from: NodeId,
-                                        accumulated_latency: Time)
-                                        -> Result<BidProposals, Error> {
-            let mut promises = vec![];
-
-            for neighbor in self.node_situation.get_neighbors().await {
-                if neighbor == from {
+    /// Get the neighbors that can be reached within the latency budget of the [Sla], ignoring
+    /// the path where it came from, sorted by increasing latency.
+    /// The p95 of the recent latencies is used, so that a jittery link is not chosen on the
+    /// basis of its good moments.
+    /// The neighbors whose latency is unknown are reported as failures, the dead ones are skipped.
+    async fn get_neighbors_in_reach(&self,
+                                    sla: &Sla,
+                                    from: &NodeId,
+                                    accumulated_latency: Time)
+                                    -> (Vec<(NodeId, Time)>, Vec<BidFailure>) {
+        let mut neighbors = vec![];
+        let mut failures = vec![];
+
+        for neighbor in self.node_situation.get_live_neighbors().await {
+            if &neighbor == from {
+                continue;
+            }
+            let latency_outbound = match self.neighbor_monitor.get_latency_to(&neighbor).await {
+                Some(summary) => summary.p95,
+                None => {
+                    warn!("Cannot get latency of node {}, skipping it", neighbor);
+                    failures.push(BidFailure { node_id: neighbor,
+                                               reason:  BidFailureReason::UnknownLatency, });
                     continue;
                 }
-                let latency_outbound =
-                    self.neighbor_monitor
-                        .get_latency_to_avg(&neighbor)
-                        .await
-                        .ok_or_else(|| Error::CannotGetLatency(neighbor.clone()))?;
-                if latency_outbound + accumulated_latency > sla.latency_max {
-                    trace!("Skipping neighbor {} because latency is too high ({}).",
-                           neighbor,
-                           latency_outbound.into_format_args(uom::si::time::millisecond,
-                                                             Abbreviation));
-                    continue;
+            };
+            if latency_outbound + accumulated_latency > sla.latency_max {
+                trace!("Skipping neighbor {} because latency is too high ({}).",
+                       neighbor,
+                       latency_outbound.into_format_args(uom::si::time::millisecond, Abbreviation));
+                continue;
+            }
+            neighbors.push((neighbor, latency_outbound));
+        }
+
+        neighbors.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        (neighbors, failures)
+    }
+
+    /// Ask a neighbor for its bids, and the ones of the nodes it asks in turn, waiting at most
+    /// [timeout]
+    async fn request_neighbor_bids(&self,
+                                   sla: &Sla,
+                                   neighbor: &NodeId,
+                                   latency_outbound: Time,
+                                   accumulated_latency: Time,
+                                   timeout: Duration)
+                                   -> Result<BidProposals, BidFailure> {
+        let request = BidRequest { sla:                 sla.clone(),
+                                   node_origin:         self.node_situation.get_my_id().await,
+                                   accumulated_latency: accumulated_latency + latency_outbound,
+                                   timeout:
+                                       Some(Time::new::<second>(timeout.as_secs_f64())), };
+
+        let reason = match tokio::time::timeout(timeout,
+                                                self.node_query
+                                                    .request_neighbor_bid(request,
+                                                                          neighbor.clone())).await
+        {
+            Ok(Ok(proposals)) => return Ok(proposals),
+            Ok(Err(err)) => BidFailureReason::Error(err.to_string()),
+            Err(_) => BidFailureReason::Timeout,
+        };
+
+        warn!("Failed to get the bids of {}: {:?}", neighbor, reason);
+        Err(BidFailure { node_id: neighbor.clone(), reason })
+    }
+
+    /// Bid on the [Sla] from this node, if it can host it
+    async fn bid_myself(&self, sla: &Sla) -> Option<BidProposal> {
+        match self.auction.bid_on(sla.clone()).await {
+            Ok((id, record)) => {
+                Some(BidProposal { node_id: self.node_situation.get_my_id().await,
+                                   id,
+                                   bid: record.bid,
+                                   pricing: record.pricing })
+            }
+            Err(err) => {
+                trace!("Not bidding myself: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Bid and ask all the [neighbors] to bid, concurrently
+    async fn place_auction(&self,
+                           sla: Sla,
+                           neighbors: Vec<(NodeId, Time)>,
+                           accumulated_latency: Time,
+                           timeout: Duration)
+                           -> BidProposals {
+        let promises = neighbors.iter().map(|(neighbor, latency)| {
+                                           self.request_neighbor_bids(&sla,
+                                                                      neighbor,
+                                                                      *latency,
+                                                                      accumulated_latency,
+                                                                      timeout)
+                                       });
+
+        let (my_bid, answers) = join(self.bid_myself(&sla), join_all(promises)).await;
+
+        let mut proposals = BidProposals { bids: vec![], failures: vec![] };
+        for answer in answers {
+            match answer {
+                Ok(answer) => {
+                    proposals.bids.extend(answer.bids);
+                    proposals.failures.extend(answer.failures);
                 }
+                Err(failure) => proposals.failures.push(failure),
+            }
+        }
+        proposals.bids.extend(my_bid);
 
-                promises.push(self.node_query
-                                  .request_neighbor_bid(BidRequest { sla:
-                                                                         sla.clone(),
-                                                                     node_origin:
-                                                                         self.node_situation
-                                                                             .get_my_id()
-                                                                             .await,
-                                                                     accumulated_latency:
-                                                                         accumulated_latency
-                                                                         + latency_outbound, },
-                                                        neighbor.clone()));
+        proposals
+    }
+
+    /// Ask the [neighbors] one by one, and keep the first bid returned. They share the
+    /// [timeout]: the ones that are left once it ran out are reported as timed out
+    async fn first_neighbor_bid(&self,
+                                sla: &Sla,
+                                neighbors: &[(NodeId, Time)],
+                                accumulated_latency: Time,
+                                timeout: Duration)
+                                -> BidProposals {
+        let deadline = Instant::now() + timeout;
+        let mut failures = vec![];
+        for (index, (neighbor, latency)) in neighbors.iter().enumerate() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                for (neighbor, _) in &neighbors[index..] {
+                    failures.push(BidFailure { node_id: neighbor.clone(),
+                                               reason:  BidFailureReason::Timeout, });
+                }
+                break;
             }
+            match self.request_neighbor_bids(sla, neighbor, *latency, accumulated_latency, left)
+                      .await
+            {
+                Ok(mut answer) => {
+                    failures.append(&mut answer.failures);
+                    if let Some(bid) = answer.bids.pop() {
+                        return BidProposals { bids: vec![bid], failures };
+                    }
+                }
+                Err(failure) => failures.push(failure),
+            }
+        }
+        BidProposals { bids: vec![], failures }
+    }
 
-            Ok(BidProposals { bids: try_join_all(promises).await?
-                                                          .into_iter()
-                                                          .flat_map(|proposals: BidProposals| {
-                                                              proposals.bids
-                                                          })
-                                                          .collect(), })
+    async fn place_bottom_up(&self,
+                             sla: Sla,
+                             neighbors: Vec<(NodeId, Time)>,
+                             accumulated_latency: Time,
+                             timeout: Duration)
+                             -> BidProposals {
+        let mut proposals =
+            self.first_neighbor_bid(&sla, &neighbors, accumulated_latency, timeout).await;
+        if proposals.bids.is_empty() {
+            proposals.bids.extend(self.bid_myself(&sla).await);
         }
+        proposals
     }
 
-    #[async_trait]
-    impl FunctionLife for FunctionLifeImpl {
-        async fn bid_on_new_function_and_transmit(&self,
-                                                  sla: Sla,
-                                                  from: NodeId,
-                                                  accumulated_latency: Time)
-                                                  -> Result<BidProposals, Error> {
-            let (my_id, result_bid, proposals) =
-                join3(self.node_situation.get_my_id(),
-                      self.auction.bid_on(sla.clone()),
-                      self.follow_up_to_neighbors(sla, from, accumulated_latency)).await;
+    async fn place_top_down(&self,
+                            sla: Sla,
+                            neighbors: Vec<(NodeId, Time)>,
+                            accumulated_latency: Time,
+                            timeout: Duration)
+                            -> BidProposals {
+        let parent = self.node_situation.get_parent_id().await;
+        let parent = neighbors.into_iter()
+                              .filter(|(neighbor, _)| Some(neighbor) == parent.as_ref())
+                              .collect::<Vec<_>>();
 
-            let (bid, bid_record) = result_bid?;
-            let mut proposals = proposals?;
+        let mut proposals =
+            self.first_neighbor_bid(&sla, &parent, accumulated_latency, timeout).await;
+        if proposals.bids.is_empty() {
+            proposals.bids.extend(self.bid_myself(&sla).await);
+        }
+        proposals
+    }
 
-            proposals.bids
-                     .push(BidProposal { node_id: my_id, id: bid, bid: bid_record.bid });
+    async fn place_latency_greedy(&self,
+                                  sla: Sla,
+                                  neighbors: Vec<(NodeId, Time)>,
+                                  accumulated_latency: Time,
+                                  timeout: Duration)
+                                  -> BidProposals {
+        match self.bid_myself(&sla).await {
+            Some(bid) => BidProposals { bids: vec![bid], failures: vec![] },
+            None => self.first_neighbor_bid(&sla, &neighbors, accumulated_latency, timeout).await,
+        }
+    }
 
-            Ok(proposals)
+    /// Validate the bid and provision its function, restoring the bid if the function cannot
+    /// be provisioned
+    async fn provision(&self, id: &BidId, function: BidId) -> Result<(), Error> {
+        if self.function.get_provisioned_function(&function).await.is_some() {
+            trace!("The function {} of the bid {} is already provisioned", function, id);
+            return Ok(());
         }
 
-        async fn validate_bid_and_provision_function(&self, id: BidId) -> Result<(), Error> {
-            let record = self.auction.validate_bid(&id).await?;
-            self.function.provision_function(id, record).await?;
-            Ok(())
+        let record = self.auction.validate_bid(id).await?;
+        if let Err(err) = self.function.provision_function(function, record.clone()).await {
+            warn!("Failed to provision the function of the bid {}, rolling back: {}", id, err);
+            if let Err(err) = self.auction.cancel_validation(id.clone(), record).await {
+                error!("Failed to roll back the validation of the bid {}: {}", id, err);
+            }
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl FunctionLife for FunctionLifeImpl {
+    async fn bid_on_new_function_and_transmit(&self,
+                                              sla: Sla,
+                                              from: NodeId,
+                                              accumulated_latency: Time,
+                                              timeout: Option<Time>)
+                                              -> Result<BidProposals, Error> {
+        let placement = sla.placement.clone().unwrap_or_else(|| self.placement.clone());
+        let timeout =
+            timeout.map(|timeout| Duration::from_secs_f64(timeout.get::<second>().max(0.0)))
+                   .unwrap_or(self.bid_timeout)
+                   .mul_f64(SUBTREE_TIMEOUT_RATIO);
+        let (neighbors, failures) =
+            self.get_neighbors_in_reach(&sla, &from, accumulated_latency).await;
+
+        let mut proposals = match placement {
+            Placement::Auction => {
+                self.place_auction(sla, neighbors, accumulated_latency, timeout).await
+            }
+            Placement::BottomUp => {
+                self.place_bottom_up(sla, neighbors, accumulated_latency, timeout).await
+            }
+            Placement::TopDown => {
+                self.place_top_down(sla, neighbors, accumulated_latency, timeout).await
+            }
+            Placement::LatencyGreedy => {
+                self.place_latency_greedy(sla, neighbors, accumulated_latency, timeout).await
+            }
+            Placement::BoundedFanout { k } => {
+                // The neighbors come sorted by increasing latency, so these are the k nearest
+                let neighbors = neighbors.into_iter().take(k.get()).collect();
+                self.place_auction(sla, neighbors, accumulated_latency, timeout).await
+            }
+        };
+        proposals.failures.extend(failures);
+
+        Ok(proposals)
+    }
+
+    async fn validate_bid_and_provision_function(&self,
+                                                 id: BidId,
+                                                 function: BidId)
+                                                 -> Result<(), Error> {
+        let lock = self.provisioning.lock().await.entry(function.clone()).or_default().clone();
+        let result = {
+            let _guard = lock.lock().await;
+            self.provision(&id, function.clone()).await
+        };
+        let mut locks = self.provisioning.lock().await;
+        // Only the map and this validation hold the lock: nobody else is waiting on it
+        if Arc::strong_count(&lock) == 2 {
+            locks.remove(&function);
+        }
+        result
+    }
+
+    async fn cancel_bid(&self, id: BidId) -> Result<(), Error> {
+        self.auction.cancel_bid(&id).await?;
+        Ok(())
+    }
+
+    async fn remove_function(&self, id: BidId) -> Result<(), Error> {
+        let record = self.function.remove_function(&id).await?;
+        self.auction.release_bid(&record.bid).await?;
+        Ok(())
+    }
+
+    async fn reconcile_functions(&self) -> Result<(), Error> {
+        for record in self.function.reconcile().await? {
+            self.auction.release_bid(&record.bid).await?;
         }
+        Ok(())
     }
 }
 
-#[cfg(feature = "bottom_up_placement")]
-pub use bottom_up_placement::*;
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use manager::model::domain::latency_stats::LatencySummary;
+    use manager::model::domain::liveness::Liveness;
+    use manager::model::domain::pricing::Pricing;
+    use manager::model::dto::auction::BidRecord;
+    use manager::model::dto::faas::ProvisionedRecord;
+    use manager::model::dto::node::NodeDescription;
+    use manager::model::view::node::{RegisterNode, UnregisterNode};
+    use uom::si::f64::{Information, Ratio};
+    use uom::si::information::megabyte;
+    use uom::si::ratio::ratio;
+    use uuid::Uuid;
 
-#[cfg(feature = "bottom_up_placement")]
-mod bottom_up_placement {
     use super::*;
-    use if_chain::if_chain;
-
-    pub struct FunctionLifeBottomUpImpl {
-        function:         Arc<dyn FaaSBackend>,
-        auction:          Arc<dyn Auction>,
-        node_situation:   Arc<dyn NodeSituation>,
-        neighbor_monitor: Arc<dyn NeighborMonitor>,
-        node_query:       Arc<dyn NodeQuery>,
-    }
-
-    impl FunctionLifeBottomUpImpl {
-        pub fn new(function: Arc<dyn FaaSBackend>,
-                   auction: Arc<dyn Auction>,
-                   node_situation: Arc<dyn NodeSituation>,
-                   neighbor_monitor: Arc<dyn NeighborMonitor>,
-                   node_query: Arc<dyn NodeQuery>)
-                   -> Self {
-            debug!("Built using FunctionLifeBottomUpImpl service");
-            Self { function, auction, node_situation, neighbor_monitor, node_query }
-        }
-
-        /// Follow up the [Sla] to the neighbors, and ignore the path where it came from.
-        async fn follow_up_to_neighbors(&self,
-                                        sla: Sla,
-                                        from: NodeId,
-                                        accumulated_latency: Time)
-                                        -> Result<BidProposals, Error> {
-            for neighbor in self.node_situation.get_neighbors().await {
-                if neighbor == from {
-                    continue;
-                }
-                let latency_outbound =
-                    self.neighbor_monitor
-                        .get_latency_to_avg(&neighbor)
-                        .await
-                        .ok_or_else(|| Error::CannotGetLatency(neighbor.clone()))?;
-                if latency_outbound + accumulated_latency > sla.latency_max {
-                    trace!("Skipping neighbor {} because latency is too high ({}).",
-                           neighbor,
-                           latency_outbound.into_format_args(uom::si::time::millisecond,
-                                                             Abbreviation));
-                    continue;
-                }
 
-                let bid = self.node_query
-                              .request_neighbor_bid(BidRequest { sla:                 sla.clone(),
-                                                                 node_origin:
-                                                                     self.node_situation
-                                                                         .get_my_id()
-                                                                         .await,
-                                                                 accumulated_latency:
-                                                                     accumulated_latency
-                                                                     + latency_outbound, },
-                                                    neighbor.clone())
-                              .await?;
-                if !bid.bids.is_empty() {
-                    return Ok(bid);
-                }
+    /// Holds the pending bids and the provisioned functions, failing to provision while
+    /// [Fake::failing] is set
+    #[derive(Debug, Default)]
+    struct Fake {
+        bids:        std::sync::Mutex<HashMap<BidId, BidRecord>>,
+        provisioned: std::sync::Mutex<HashSet<BidId>>,
+        failing:     std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl Auction for Fake {
+        async fn bid_on(&self,
+                        _sla: Sla)
+                        -> Result<(BidId, BidRecord), crate::service::auction::Error> {
+            Err(crate::service::auction::Error::Unsatisfiable)
+        }
+
+        async fn validate_bid(&self,
+                              id: &BidId)
+                              -> Result<BidRecord, crate::service::auction::Error> {
+            self.bids
+                .lock()
+                .unwrap()
+                .remove(id)
+                .ok_or_else(|| crate::service::auction::Error::BidIdNotFound(id.clone()))
+        }
+
+        async fn cancel_validation(&self,
+                                   id: BidId,
+                                   bid: BidRecord)
+                                   -> Result<(), crate::service::auction::Error> {
+            self.bids.lock().unwrap().insert(id, bid);
+            Ok(())
+        }
+
+        async fn cancel_bid(&self, id: &BidId) -> Result<(), crate::service::auction::Error> {
+            self.bids.lock().unwrap().remove(id);
+            Ok(())
+        }
+
+        async fn release_bid(&self,
+                             _bid: &BidRecord)
+                             -> Result<(), crate::service::auction::Error> {
+            Ok(())
+        }
+
+        async fn expire_bids(&self) -> Result<(), crate::service::auction::Error> { Ok(()) }
+    }
+
+    #[async_trait]
+    impl FaaSBackend for Fake {
+        async fn provision_function(&self,
+                                    id: BidId,
+                                    _bid: BidRecord)
+                                    -> Result<String, crate::service::faas::Error> {
+            if self.failing.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(crate::service::faas::Error::NotProvisioned(id));
             }
+            self.provisioned.lock().unwrap().insert(id.clone());
+            Ok(id.to_string())
+        }
+
+        async fn get_provisioned_function(&self, id: &BidId) -> Option<ProvisionedRecord> {
+            self.provisioned
+                .lock()
+                .unwrap()
+                .contains(id)
+                .then(|| ProvisionedRecord { bid: record(), function_name: id.to_string() })
+        }
+
+        async fn get_provisioned_functions(&self) -> Vec<(BidId, ProvisionedRecord)> { vec![] }
 
-            Err(Error::NoCandidatesRetained)
+        async fn remove_function(&self,
+                                 id: &BidId)
+                                 -> Result<ProvisionedRecord, crate::service::faas::Error> {
+            Err(crate::service::faas::Error::NotProvisioned(id.clone()))
+        }
+
+        async fn reconcile(&self) -> Result<Vec<ProvisionedRecord>, crate::service::faas::Error> {
+            Ok(vec![])
         }
     }
 
+    /// A node without any neighbor
+    #[derive(Debug)]
+    struct Alone;
+
     #[async_trait]
-    impl FunctionLife for FunctionLifeBottomUpImpl {
-        /// Here the operation will be sequential, first looking to place on a bottom node, or a
-        /// child at least, and only then to consider itself as a candidate
-        async fn bid_on_new_function_and_transmit(&self,
-                                                  sla: Sla,
-                                                  from: NodeId,
-                                                  accumulated_latency: Time)
-                                                  -> Result<BidProposals, Error> {
-            let bid = if_chain!(
-                if let Ok(mut follow_up) = self.follow_up_to_neighbors(sla.clone(), from, accumulated_latency).await;
-                if let Some(bid) = follow_up.bids.pop();
-                then{
-                    bid
-                } else {
-                    let (id, record) = self.auction.bid_on(sla.clone()).await?;
-                    BidProposal{node_id: self.node_situation.get_my_id().await,
-                    id, bid: record.bid}
-                }
-            );
+    impl NodeSituation for Alone {
+        async fn register(&self,
+                          _id: NodeId,
+                          _description: NodeDescription)
+                          -> Result<(), crate::repository::node_situation::Error> {
+            Ok(())
+        }
 
-            Ok(BidProposals { bids: vec![bid] })
+        async fn unregister(&self,
+                            _id: &NodeId)
+                            -> Result<bool, crate::repository::node_situation::Error> {
+            Ok(false)
         }
 
-        async fn validate_bid_and_provision_function(&self, id: BidId) -> Result<(), Error> {
-            let record = self.auction.validate_bid(&id).await?;
-            self.function.provision_function(id, record).await?;
+        async fn get_fog_node_neighbor(&self, _id: &NodeId) -> Option<NodeDescription> { None }
+
+        async fn get_my_id(&self) -> NodeId { NodeId::from(Uuid::nil()) }
+
+        async fn get_parent_id(&self) -> Option<NodeId> { None }
+
+        async fn get_my_tags(&self) -> Vec<String> { vec![] }
+
+        async fn is_market(&self) -> bool { false }
+
+        async fn get_parent_node_address(&self) -> Option<(IpAddr, u16)> { None }
+
+        async fn failover_parent(&self) -> Option<NodeId> { None }
+
+        async fn set_parent(&self, _id: NodeId, _description: NodeDescription) -> bool { false }
+
+        async fn get_market_node_address(&self) -> Option<(IpAddr, u16)> { None }
+
+        async fn get_neighbors(&self) -> Vec<NodeId> { vec![] }
+
+        async fn get_live_neighbors(&self) -> Vec<NodeId> { vec![] }
+
+        async fn get_liveness(&self, _id: &NodeId) -> Liveness { Liveness::Alive }
+
+        async fn set_liveness(&self, _id: NodeId, _liveness: Liveness) {}
+
+        async fn get_my_public_ip(&self) -> IpAddr { IpAddr::V4(Ipv4Addr::LOCALHOST) }
+
+        async fn get_my_public_port(&self) -> u16 { 0 }
+    }
+
+    #[async_trait]
+    impl NeighborMonitor for Alone {
+        async fn ping_neighbors_rtt(&self) -> Result<(), crate::service::neighbor_monitor::Error> {
             Ok(())
         }
+
+        async fn get_latency_to(&self, _id: &NodeId) -> Option<LatencySummary> { None }
+
+        async fn get_latency_from(&self, _id: &NodeId) -> Option<LatencySummary> { None }
+
+        async fn is_neighbor(&self, _id: &NodeId) -> bool { false }
+    }
+
+    #[async_trait]
+    impl NodeQuery for Alone {
+        async fn register_to_parent(&self,
+                                    _register: RegisterNode)
+                                    -> Result<(), crate::repository::node_query::Error> {
+            Err(crate::repository::node_query::Error::NoURIToUpper)
+        }
+
+        async fn unregister_from_parent(&self,
+                                        _unregister: UnregisterNode)
+                                        -> Result<(), crate::repository::node_query::Error>
+        {
+            Err(crate::repository::node_query::Error::NoURIToUpper)
+        }
+
+        async fn request_neighbor_bid(
+            &self,
+            _request: BidRequest,
+            node: NodeId)
+            -> Result<BidProposals, crate::repository::node_query::Error> {
+            Err(crate::repository::node_query::Error::NodeIdNotFound(node))
+        }
+    }
+
+    /// Neighbors that never answer
+    #[derive(Debug)]
+    struct Stalled;
+
+    #[async_trait]
+    impl NodeQuery for Stalled {
+        async fn register_to_parent(&self,
+                                    _register: RegisterNode)
+                                    -> Result<(), crate::repository::node_query::Error> {
+            Err(crate::repository::node_query::Error::NoURIToUpper)
+        }
+
+        async fn unregister_from_parent(&self,
+                                        _unregister: UnregisterNode)
+                                        -> Result<(), crate::repository::node_query::Error>
+        {
+            Err(crate::repository::node_query::Error::NoURIToUpper)
+        }
+
+        async fn request_neighbor_bid(
+            &self,
+            _request: BidRequest,
+            _node: NodeId)
+            -> Result<BidProposals, crate::repository::node_query::Error> {
+            futures::future::pending().await
+        }
+    }
+
+    fn record() -> BidRecord {
+        let sla = Sla { storage:              Information::new::<megabyte>(0.0),
+                        memory:               Information::new::<megabyte>(100.0),
+                        cpu:                  Ratio::new::<ratio>(0.1),
+                        latency_max:          Time::new::<second>(1.0),
+                        data_input_max_size:  Information::new::<megabyte>(0.0),
+                        data_output_max_size: Information::new::<megabyte>(0.0),
+                        max_time_before_hot:  Time::new::<second>(0.0),
+                        reevaluation_period:  Time::new::<second>(0.0),
+                        function_image:       "image".to_string(),
+                        function_live_name:   None,
+                        placement:            None, };
+        BidRecord { bid: 1.0, sla, node: "node".to_string(), pricing: Pricing::default() }
+    }
+
+    /// A node holding the bid [id]
+    fn function_life(fake: &Arc<Fake>, id: &BidId) -> FunctionLifeImpl {
+        fake.bids.lock().unwrap().insert(id.clone(), record());
+        FunctionLifeImpl::new(fake.clone(),
+                              fake.clone(),
+                              Arc::new(Alone),
+                              Arc::new(Alone),
+                              Arc::new(Alone),
+                              Placement::Auction,
+                              Duration::from_secs(1))
+    }
+
+    #[tokio::test]
+    async fn test_failed_provisioning_restores_the_bid() {
+        let fake = Arc::new(Fake::default());
+        let (id, function) = (BidId::from(Uuid::new_v4()), BidId::from(Uuid::new_v4()));
+        let life = function_life(&fake, &id);
+
+        fake.failing.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert!(matches!(life.validate_bid_and_provision_function(id.clone(), function.clone())
+                             .await,
+                         Err(Error::FaaS(_))));
+        assert!(fake.bids.lock().unwrap().contains_key(&id));
+        assert!(fake.provisioned.lock().unwrap().is_empty());
+
+        fake.failing.store(false, std::sync::atomic::Ordering::SeqCst);
+        life.validate_bid_and_provision_function(id.clone(), function.clone()).await.unwrap();
+        assert!(fake.bids.lock().unwrap().is_empty());
+        assert!(fake.provisioned.lock().unwrap().contains(&function));
+        assert!(life.provisioning.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retried_validation_does_nothing() {
+        let fake = Arc::new(Fake::default());
+        let (id, function) = (BidId::from(Uuid::new_v4()), BidId::from(Uuid::new_v4()));
+        let life = function_life(&fake, &id);
+
+        let (first, retry) =
+            join(life.validate_bid_and_provision_function(id.clone(), function.clone()),
+                 life.validate_bid_and_provision_function(id.clone(), function.clone())).await;
+        first.unwrap();
+        retry.unwrap();
+        assert_eq!(*fake.provisioned.lock().unwrap(), HashSet::from([function]));
+        assert!(life.provisioning.lock().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_neighbors_share_the_timeout() {
+        let fake = Arc::new(Fake::default());
+        let life = FunctionLifeImpl::new(fake.clone(),
+                                         fake,
+                                         Arc::new(Alone),
+                                         Arc::new(Alone),
+                                         Arc::new(Stalled),
+                                         Placement::BottomUp,
+                                         Duration::from_secs(1));
+        let neighbors: Vec<_> =
+            (0..2).map(|_| (NodeId::from(Uuid::new_v4()), Time::new::<second>(0.01))).collect();
+        let timeout = Duration::from_secs(1);
+
+        let start = Instant::now();
+        let proposals =
+            life.first_neighbor_bid(&record().sla, &neighbors, Time::new::<second>(0.0), timeout)
+                .await;
+        assert!(start.elapsed() <= timeout);
+        assert!(proposals.bids.is_empty());
+        let failed: Vec<_> =
+            proposals.failures.iter().map(|failure| failure.node_id.clone()).collect();
+        assert_eq!(failed, neighbors.into_iter().map(|(id, _)| id).collect::<Vec<_>>());
+        assert!(proposals.failures
+                         .iter()
+                         .all(|failure| matches!(failure.reason, BidFailureReason::Timeout)));
     }
 }
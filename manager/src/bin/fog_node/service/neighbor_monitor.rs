@@ -1,9 +1,16 @@
 use crate::repository::latency_estimation::LatencyEstimation;
+use crate::service::node_life::NodeLife;
+use crate::{NodeSituation, Router};
 use async_trait::async_trait;
+use manager::model::domain::latency_stats::LatencySummary;
+use manager::model::domain::liveness::{FailureThresholds, Liveness, LivenessTracker};
+use manager::model::domain::routing::Packet;
+use manager::model::view::node::PostLiveness;
 use manager::model::NodeId;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
-use uom::si::f64::Time;
+use tokio::sync::Mutex;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -13,34 +20,131 @@ pub enum Error {
 
 #[async_trait]
 pub trait NeighborMonitor: Debug + Sync + Send {
+    /// Ping the neighbors, updating their latencies and their liveness
     async fn ping_neighbors_rtt(&self) -> Result<(), Error>;
-    async fn get_latency_to_avg(&self, id: &NodeId) -> Option<Time>;
-    async fn get_latency_from_avg(&self, id: &NodeId) -> Option<Time>;
+    /// Statistics of the latency to the neighbor, over the recent pings
+    async fn get_latency_to(&self, id: &NodeId) -> Option<LatencySummary>;
+    /// Statistics of the latency from the neighbor, over the recent pings
+    async fn get_latency_from(&self, id: &NodeId) -> Option<LatencySummary>;
+    /// Whether the node is the parent or one of the children
+    async fn is_neighbor(&self, id: &NodeId) -> bool;
 }
 
 #[derive(Debug)]
 pub struct NeighborMonitorImpl {
     rtt_estimation: Arc<dyn LatencyEstimation>,
+    node_situation: Arc<dyn NodeSituation>,
+    router:         Arc<dyn Router>,
+    node_life:      Arc<dyn NodeLife>,
+    thresholds:     FailureThresholds,
+    trackers:       Mutex<HashMap<NodeId, LivenessTracker>>,
 }
 
 impl NeighborMonitorImpl {
-    pub fn new(latency_estimation: Arc<dyn LatencyEstimation>) -> Self {
-        Self { rtt_estimation: latency_estimation }
+    pub fn new(latency_estimation: Arc<dyn LatencyEstimation>,
+               node_situation: Arc<dyn NodeSituation>,
+               router: Arc<dyn Router>,
+               node_life: Arc<dyn NodeLife>,
+               thresholds: FailureThresholds)
+               -> Self {
+        Self { rtt_estimation: latency_estimation,
+               node_situation,
+               router,
+               node_life,
+               thresholds,
+               trackers: Mutex::new(HashMap::new()) }
+    }
+
+    /// Feed the failure detector with the outcome of the pings, and propagate the changes.
+    /// The death of the parent triggers the failover to a fallback parent, before reporting it
+    /// through the new parent.
+    async fn update_liveness(&self, failed: &[NodeId]) {
+        let parent = self.node_situation.get_parent_id().await;
+        let mut changes = vec![];
+        {
+            let mut trackers = self.trackers.lock().await;
+            for neighbor in self.node_situation.get_neighbors().await {
+                let answered = !failed.contains(&neighbor);
+                if let Some(liveness) =
+                    trackers.entry(neighbor.clone()).or_default().record(answered, &self.thresholds)
+                {
+                    changes.push((neighbor, liveness));
+                }
+            }
+        }
+
+        for (neighbor, liveness) in changes {
+            match liveness {
+                Liveness::Alive => info!("Neighbor {} is alive again", neighbor),
+                Liveness::Suspect => warn!("Neighbor {} is suspected to be down", neighbor),
+                Liveness::Dead => warn!("Neighbor {} is considered dead", neighbor),
+            }
+            self.node_situation.set_liveness(neighbor.clone(), liveness).await;
+            if liveness == Liveness::Dead && parent.as_ref() == Some(&neighbor) {
+                match self.node_life.failover_parent().await {
+                    Ok(new_parent) => {
+                        self.trackers.lock().await.remove(&new_parent);
+                    }
+                    Err(err) => warn!("Failed to replace the dead parent {}: {}", neighbor, err),
+                }
+            }
+            self.report_liveness(neighbor, liveness).await;
+        }
+    }
+
+    async fn report_liveness(&self, node_id: NodeId, liveness: Liveness) {
+        let report =
+            PostLiveness { reporter: self.node_situation.get_my_id().await, node_id, liveness };
+        let data = match serde_json::value::to_raw_value(&report) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("Failed to serialize the liveness report: {}", err);
+                return;
+            }
+        };
+        if let Err(err) =
+            self.router
+                .forward(&Packet::Market { resource_uri: "liveness".to_string(),
+                                           data:         &data, })
+                .await
+        {
+            warn!("Failed to report the liveness of {} to the market: {}", report.node_id, err);
+        }
     }
 }
 
 #[async_trait]
 impl NeighborMonitor for NeighborMonitorImpl {
     async fn ping_neighbors_rtt(&self) -> Result<(), Error> {
-        self.rtt_estimation.latency_to_neighbors().await?;
+        let result = self.rtt_estimation.latency_to_neighbors().await;
+        let (failed, forgotten) = match &result {
+            Ok(()) => (vec![], vec![]),
+            Err(crate::repository::latency_estimation::Error::FailedPing(_, _, list)) => {
+                (list.unreachable().cloned().collect(), list.forgotten().cloned().collect())
+            }
+        };
+        self.update_liveness(&failed).await;
+
+        if let Some(parent) = self.node_situation.get_parent_id().await {
+            if forgotten.contains(&parent) {
+                warn!("The parent {} forgot about this node, registering again", parent);
+                let node_life = self.node_life.clone();
+                tokio::spawn(async move { node_life.register().await });
+            }
+        }
+        result?;
         Ok(())
     }
 
-    async fn get_latency_to_avg(&self, id: &NodeId) -> Option<Time> {
-        self.rtt_estimation.get_latency_to_avg(id).await
+    async fn get_latency_to(&self, id: &NodeId) -> Option<LatencySummary> {
+        self.rtt_estimation.get_latency_to(id).await
+    }
+
+    async fn get_latency_from(&self, id: &NodeId) -> Option<LatencySummary> {
+        self.rtt_estimation.get_latency_from(id).await
     }
 
-    async fn get_latency_from_avg(&self, id: &NodeId) -> Option<Time> {
-        self.rtt_estimation.get_latency_from_avg(id).await
+    async fn is_neighbor(&self, id: &NodeId) -> bool {
+        self.node_situation.get_neighbors().await.contains(id)
     }
 }
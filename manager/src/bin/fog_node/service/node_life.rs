@@ -1,24 +1,46 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
 use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use tokio::sync::RwLock;
 
+use manager::model::domain::liveness::Liveness;
 use manager::model::domain::routing::Packet;
 use manager::model::dto::node::NodeDescription;
-use manager::model::view::node::RegisterNode;
+use manager::model::view::node::{HeartbeatAck, NeighborNode, NodeSummary, PostHeartbeat,
+                                 RegisterNode, RegistrationState, ResourceSummary, UnregisterNode};
+use manager::model::NodeId;
 
+use crate::repository::latency_estimation::LatencyEstimation;
+use crate::repository::resource_tracking::ResourceTracking;
+use crate::service::faas::FaaSBackend;
 use crate::{NodeQuery, NodeSituation, Router};
 
+/// Delay before retrying a failed registration, doubled at every failure
+const REGISTRATION_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const REGISTRATION_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("A node tried to register here, but I am not her parent")]
     NotTheParent,
+    #[error("A node tried to unregister from here, but I am not her parent: {0}")]
+    NotAChild(NodeId),
     #[error("This node has no parent (probably it is the market/root node)")]
     ParentDoesntExist,
+    #[error("No fallback parent is configured to replace the parent")]
+    NoFallbackParent,
     #[error(transparent)]
     NodeQuery(#[from] crate::repository::node_query::Error),
     #[error(transparent)]
+    NodeSituation(#[from] crate::repository::node_situation::Error),
+    #[error(transparent)]
     Routing(#[from] crate::service::routing::Error),
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
     #[error("Trying to register/pass a register message for a market node,but it should not \
              happen since the market node is always on top of the tree network.")]
     CannotRegisterMarketOnRegularNode,
@@ -26,12 +48,32 @@ pub enum Error {
 
 /// Service to manage the behaviour of the routing
 #[async_trait]
-pub trait NodeLife: Send + Sync {
+pub trait NodeLife: Debug + Send + Sync {
     /// Register locally the child node, but also send the packet towards the market to register it
     /// there, also.
     async fn register_child_node(&self, register: RegisterNode) -> Result<(), Error>;
+    /// Send the packet towards the market to unregister the child node there, then forget about
+    /// it locally.
+    async fn unregister_child_node(&self, unregister: UnregisterNode) -> Result<(), Error>;
+    /// Replace the parent, that left the network, as told by the market
+    async fn change_parent(&self, parent: NeighborNode) -> Result<(), Error>;
+    /// Take the child of a child that left the network, as told by the market
+    async fn adopt_child(&self, child: NeighborNode) -> Result<(), Error>;
+    /// Leave the network, the market relocating the functions hosted here
+    async fn unregister(&self) -> Result<(), Error>;
     /// Initialize the negotiating process to get connected to the parent node
     async fn init_registration(&self, my_ip: IpAddr, my_port: u16) -> Result<(), Error>;
+    /// Register to the parent node, retrying with an exponential backoff until it succeeds.
+    /// Does nothing if a registration is already in progress.
+    async fn register(&self);
+    async fn get_registration_state(&self) -> RegistrationState;
+    /// Replace the (dead) parent by the next fallback parent and register under it; the market
+    /// then moves the whole subtree of the node under the new parent. A registration already in
+    /// progress goes to the new parent at its next attempt instead.
+    /// Return the new parent.
+    async fn failover_parent(&self) -> Result<NodeId, Error>;
+    /// Send a heartbeat with a summary of the state of the node to the market
+    async fn send_heartbeat(&self) -> Result<HeartbeatAck, Error>;
 }
 
 #[derive(Debug)]
@@ -39,14 +81,91 @@ pub struct NodeLifeImpl {
     router:         Arc<dyn Router>,
     node_situation: Arc<dyn NodeSituation>,
     node_query:     Arc<dyn NodeQuery>,
+    function:       Arc<dyn FaaSBackend>,
+    latency:        Arc<dyn LatencyEstimation>,
+    resources:      Arc<dyn ResourceTracking>,
+    registration:   RwLock<RegistrationState>,
 }
 
 impl NodeLifeImpl {
     pub fn new(router: Arc<dyn Router>,
                node_situation: Arc<dyn NodeSituation>,
-               node_query: Arc<dyn NodeQuery>)
+               node_query: Arc<dyn NodeQuery>,
+               function: Arc<dyn FaaSBackend>,
+               latency: Arc<dyn LatencyEstimation>,
+               resources: Arc<dyn ResourceTracking>)
                -> Self {
-        Self { router, node_situation, node_query }
+        Self { router,
+               node_situation,
+               node_query,
+               function,
+               latency,
+               resources,
+               registration: RwLock::new(RegistrationState::NotRegistered) }
+    }
+
+    async fn get_summary(&self) -> NodeSummary {
+        let mut children = vec![];
+        let mut dead_neighbors = vec![];
+        let mut latencies = HashMap::new();
+        let parent = self.node_situation.get_parent_id().await;
+        for neighbor in self.node_situation.get_neighbors().await {
+            if self.node_situation.get_liveness(&neighbor).await == Liveness::Dead {
+                dead_neighbors.push(neighbor.clone());
+            }
+            if let Some(latency) = self.latency.get_latency_to(&neighbor).await {
+                latencies.insert(neighbor.clone(), latency.ewma);
+            }
+            if parent.as_ref() != Some(&neighbor) {
+                children.push(neighbor);
+            }
+        }
+        let provisioned_functions =
+            self.function.get_provisioned_functions().await.into_iter().map(|(id, _)| id).collect();
+        NodeSummary { children,
+                      dead_neighbors,
+                      provisioned_functions,
+                      latencies,
+                      resources: self.get_resource_summary().await }
+    }
+
+    /// Sum the resources of all the nodes of the cluster, if they can all be retrieved
+    async fn get_resource_summary(&self) -> Option<ResourceSummary> {
+        let mut summary: Option<ResourceSummary> = None;
+        for name in self.resources.get_nodes().await {
+            let (used_memory, used_cpu) = self.resources.get_used(&name).await.ok()?;
+            let (reserved_memory, reserved_cpu) = self.resources.get_reserved(&name).await.ok()?;
+            let (available_memory, available_cpu) =
+                self.resources.get_available(&name).await.ok()?;
+            let node = ResourceSummary { used_memory,
+                                         used_cpu,
+                                         reserved_memory,
+                                         reserved_cpu,
+                                         available_memory,
+                                         available_cpu };
+            summary = Some(summary.map_or(node, |summary| summary + node));
+        }
+        summary
+    }
+
+    /// Mark the registration as in progress, unless it already is; only the caller for which
+    /// this returns true may then register
+    async fn start_registering(&self) -> bool {
+        let mut registration = self.registration.write().await;
+        if *registration == RegistrationState::Registering {
+            return false;
+        }
+        *registration = RegistrationState::Registering;
+        true
+    }
+
+    /// Register once, after [start_registering]; the registration stays in progress on failure
+    async fn try_register(&self) -> Result<(), Error> {
+        self.init_registration(self.node_situation.get_my_public_ip().await,
+                               self.node_situation.get_my_public_port().await)
+            .await?;
+        *self.registration.write().await = RegistrationState::Registered;
+        Ok(())
     }
 }
 
@@ -62,7 +181,7 @@ impl NodeLife for NodeLifeImpl {
 
                 self.node_situation
                     .register(node_id.clone(), NodeDescription { ip: *ip, port: *port })
-                    .await;
+                    .await?;
             }
             RegisterNode::MarketNode { .. } => {
                 return Err(Error::CannotRegisterMarketOnRegularNode)
@@ -77,6 +196,49 @@ impl NodeLife for NodeLifeImpl {
         Ok(())
     }
 
+    async fn unregister_child_node(&self, unregister: UnregisterNode) -> Result<(), Error> {
+        trace!("Unregistering child node");
+        let is_child =
+            self.node_situation.get_parent_id().await.as_ref() != Some(&unregister.node_id)
+            && self.node_situation.get_fog_node_neighbor(&unregister.node_id).await.is_some();
+        if !is_child {
+            return Err(Error::NotAChild(unregister.node_id));
+        }
+
+        // The child stays known until the market removed it, since it may still route through here
+        self.router
+            .forward(&Packet::Market { resource_uri: "unregister".to_string(),
+                                       data:
+                                           &serde_json::value::to_raw_value(&unregister).unwrap(), })
+            .await?;
+        self.node_situation.unregister(&unregister.node_id).await?;
+        Ok(())
+    }
+
+    async fn change_parent(&self, parent: NeighborNode) -> Result<(), Error> {
+        info!("The parent left the network, replaced by {}", parent.node_id);
+        let description = NodeDescription { ip: parent.ip, port: parent.port };
+        if !self.node_situation.set_parent(parent.node_id, description).await {
+            return Err(Error::ParentDoesntExist);
+        }
+        Ok(())
+    }
+
+    async fn adopt_child(&self, child: NeighborNode) -> Result<(), Error> {
+        info!("Adopting the child {} of a child that left the network", child.node_id);
+        self.node_situation
+            .register(child.node_id, NodeDescription { ip: child.ip, port: child.port })
+            .await?;
+        Ok(())
+    }
+
+    async fn unregister(&self) -> Result<(), Error> {
+        let unregister = UnregisterNode { node_id: self.node_situation.get_my_id().await };
+        self.node_query.unregister_from_parent(unregister).await?;
+        *self.registration.write().await = RegistrationState::NotRegistered;
+        Ok(())
+    }
+
     async fn init_registration(&self, ip: IpAddr, port: u16) -> Result<(), Error> {
         trace!("Init registration");
         let register = if self.node_situation.is_market().await {
@@ -97,4 +259,48 @@ impl NodeLife for NodeLifeImpl {
         self.node_query.register_to_parent(register).await?;
         Ok(())
     }
+
+    async fn failover_parent(&self) -> Result<NodeId, Error> {
+        let new_parent =
+            self.node_situation.failover_parent().await.ok_or(Error::NoFallbackParent)?;
+        info!("Failing over to the parent {}", new_parent);
+        if !self.start_registering().await {
+            // The registration in progress reads the parent again at its next attempt
+            info!("A registration is already in progress, it will go to {}", new_parent);
+            return Ok(new_parent);
+        }
+        if let Err(err) = self.try_register().await {
+            *self.registration.write().await = RegistrationState::NotRegistered;
+            return Err(err);
+        }
+        Ok(new_parent)
+    }
+
+    async fn register(&self) {
+        if !self.start_registering().await {
+            return;
+        }
+
+        let mut backoff = REGISTRATION_INITIAL_BACKOFF;
+        while let Err(err) = self.try_register().await {
+            warn!("Failed to register to the parent, retrying in {}s: {}", backoff.as_secs(), err);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(REGISTRATION_MAX_BACKOFF);
+        }
+        info!("Registered to the parent and the market");
+    }
+
+    async fn get_registration_state(&self) -> RegistrationState { *self.registration.read().await }
+
+    async fn send_heartbeat(&self) -> Result<HeartbeatAck, Error> {
+        let heartbeat = PostHeartbeat { node_id: self.node_situation.get_my_id().await,
+                                        summary: self.get_summary().await, };
+        let response =
+            self.router
+                .forward(&Packet::Market { resource_uri: "heartbeat".to_string(),
+                                           data:
+                                               &serde_json::value::to_raw_value(&heartbeat)?, })
+                .await?;
+        Ok(serde_json::from_slice(&response.body)?)
+    }
 }
@@ -4,8 +4,10 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use bytes::Bytes;
 
-use manager::model::domain::routing::{FunctionRoutingStack, Packet};
-use manager::model::dto::routing::Direction;
+use manager::model::domain::liveness::Liveness;
+use manager::model::domain::routing::{FunctionRoutingStack, InvocationMode, Packet};
+use manager::model::dto::node::NodeDescription;
+use manager::model::dto::routing::{Direction, RoutedResponse};
 use manager::model::{BidId, NodeId};
 use manager::openfaas::DefaultApi;
 
@@ -18,8 +20,12 @@ use crate::NodeSituation;
 pub enum Error {
     #[error(transparent)]
     Routing(#[from] crate::repository::routing::Error),
+    #[error(transparent)]
+    FaaSRoutingTable(#[from] crate::repository::faas_routing_table::Error),
     #[error("The next node doesn't exist: {0}")]
     NextNodeDoesntExist(NodeId),
+    #[error("The next node is considered dead: {0}")]
+    NextNodeDead(NodeId),
     #[error("The routing stack was not correct to be utilized")]
     MalformedRoutingStack,
     #[error("The bid id / function id is not known: {0}")]
@@ -28,6 +34,8 @@ pub enum Error {
     Serialization(#[from] serde_json::Error),
     #[error(transparent)]
     OpenFaas(#[from] manager::openfaas::Error<String>),
+    #[error("The request failed with status {0}: {1}")]
+    ForwardingStatus(u16, String),
 }
 
 /// Service to manage the behaviour of the routing
@@ -36,8 +44,19 @@ pub trait Router: Debug + Send + Sync {
     /// Register a new route, from a [RoutingStack], making the follow up requests left to do in the
     /// chain
     async fn register_function_route(&self, stack: FunctionRoutingStack) -> Result<(), Error>;
-    /// Forward payloads to a neighbour node
-    async fn forward(&self, packet: &Packet) -> Result<Bytes, Error>;
+    /// Unregister a route, from a [RoutingStack], following the same path as the registration
+    async fn unregister_function_route(&self, stack: FunctionRoutingStack) -> Result<(), Error>;
+    /// Forward payloads to a neighbour node.
+    /// Only the responses of the functions are relayed whatever their status, the other ones
+    /// fail on error statuses.
+    async fn forward(&self, packet: &Packet) -> Result<RoutedResponse, Error>;
+}
+
+/// What to do with the route on every node of a [FunctionRoutingStack]
+#[derive(Debug, Clone, Copy)]
+enum RouteAction {
+    Register,
+    Unregister,
 }
 
 #[derive(Debug)]
@@ -62,130 +81,115 @@ impl<R> RouterImpl<R> where R: RoutingRepository
         Self { faas_routing_table, node_situation, routing, faas, faas_api }
     }
 
-    async fn forward_register_to_node(&self,
-                                      to: &NodeId,
-                                      stack: FunctionRoutingStack)
-                                      -> Result<Bytes, Error> {
+    async fn forward_route_to_node(&self,
+                                   to: &NodeId,
+                                   stack: &FunctionRoutingStack,
+                                   action: RouteAction)
+                                   -> Result<(), Error> {
         let next = self.node_situation
                        .get_fog_node_neighbor(to)
                        .await
                        .ok_or_else(|| Error::NextNodeDoesntExist(to.to_owned()))?;
-        self.routing
-            .forward_to_url(&next.ip, &next.port, "register", &stack)
-            .await
-            .map_err(Error::from)
+        match action {
+            RouteAction::Register => self.routing.register_route(&next.ip, &next.port, stack).await,
+            RouteAction::Unregister => {
+                self.routing.unregister_route(&next.ip, &next.port, stack).await
+            }
+        }.map_err(Error::from)
     }
-}
 
-#[async_trait]
-impl<R> Router for RouterImpl<R> where R: RoutingRepository
-{
-    async fn register_function_route(&self, mut stack: FunctionRoutingStack) -> Result<(), Error> {
-        // At least 1 more stop
-        if stack.route_to_first.len() > 1
-           && stack.route_to_first.starts_with(&[self.node_situation.get_my_id().await])
-        {
-            stack.route_to_first.pop();
-            let next = stack.route_to_first.last().ok_or(Error::MalformedRoutingStack)?.to_owned();
-            self.forward_register_to_node(&next, stack).await?;
-            return Ok(());
+    /// Get the next node to forward a packet to, failing fast if it is known to be dead
+    async fn get_live_next_node(&self, next: &NodeId) -> Result<NodeDescription, Error> {
+        if self.node_situation.get_liveness(next).await == Liveness::Dead {
+            return Err(Error::NextNodeDead(next.to_owned()));
         }
+        self.node_situation
+            .get_fog_node_neighbor(next)
+            .await
+            .ok_or_else(|| Error::NextNodeDoesntExist(next.to_owned()))
+    }
 
-        // No more stop, we need to start registering routes
-        if stack.route_to_first.is_empty() {
-            if stack.route_to_first.len() == 1
-               && stack.route_to_first.starts_with(&[self.node_situation.get_my_id().await])
-            {
-                let my_id = self.node_situation.get_my_id().await;
-                stack.route_to_first.pop();
-                let mid = stack.routes
-                               .iter()
-                               .position(|node| node == &my_id)
-                               .ok_or(Error::MalformedRoutingStack)?;
-
-                // left: from, right: to
-                let (left, right) = stack.routes.split_at(mid);
-
-                self.faas_routing_table
-                    .update(stack.function.to_owned(), Direction::NextNode(right[0].to_owned()))
-                    .await;
+    /// Fail on the error statuses relayed from the next nodes
+    fn ensure_success(response: RoutedResponse) -> Result<RoutedResponse, Error> {
+        if response.is_success() {
+            Ok(response)
+        } else {
+            Err(Error::ForwardingStatus(response.status,
+                                        String::from_utf8_lossy(&response.body).to_string()))
+        }
+    }
 
-                if !left.is_empty() {
-                    let next = &left[0];
-                    self.forward_register_to_node(next,
-                                                  FunctionRoutingStack { function:
-                                                                             stack.function
-                                                                                  .to_owned(),
-                                                                         route_to_first: vec![],
-                                                                         routes:
-                                                                             left.to_vec(), })
-                        .await?;
-                }
+    /// Walk the [FunctionRoutingStack] up to the first node of the route, and then apply the
+    /// action on every node of the route
+    async fn walk_function_route(&self,
+                                 mut stack: FunctionRoutingStack,
+                                 action: RouteAction)
+                                 -> Result<(), Error> {
+        let my_id = self.node_situation.get_my_id().await;
 
-                if right.len() > 1 {
-                    let next = &right[1];
-                    self.forward_register_to_node(next,
-                                                  FunctionRoutingStack { function:
-                                                                             stack.function
-                                                                                  .to_owned(),
-                                                                         route_to_first: vec![],
-                                                                         routes:
-                                                                             right.to_vec(), })
-                        .await?;
-                }
+        // Still on the way to the first node of the route
+        if let Some(current) = stack.route_to_first.pop() {
+            if current != my_id {
+                return Err(Error::MalformedRoutingStack);
+            }
 
-                return Ok(());
+            if let Some(next) = stack.route_to_first.last().cloned() {
+                return self.forward_route_to_node(&next, &stack, action).await;
             }
+        }
 
-            if stack.routes.len() > 1 {
-                let next;
-                if stack.routes.starts_with(&[self.node_situation.get_my_id().await]) {
-                    stack.routes.remove(0);
-                    next = Some(&stack.routes[0]);
-                } else if stack.routes.ends_with(&[self.node_situation.get_my_id().await]) {
-                    stack.routes.remove(stack.routes.len() - 1);
-                    next = Some(&stack.routes[stack.routes.len() - 1]);
-                } else {
-                    next = None;
-                }
+        // I am the first node of the route, apply and pass the rest of the route along
+        if stack.routes.first() != Some(&my_id) {
+            return Err(Error::MalformedRoutingStack);
+        }
+        stack.routes.remove(0);
 
-                if let Some(next) = next {
-                    self.forward_register_to_node(
-                        next,
-                        FunctionRoutingStack {
-                            function:       stack.function.to_owned(),
-                            route_to_first: vec![],
-                            routes:         stack.routes.to_vec(),
-                        },
-                    )
+        let next = stack.routes.first().cloned();
+        let destination = stack.destination.as_ref();
+        match (action, &next) {
+            (RouteAction::Register, Some(next)) => {
+                trace!("Routing {} towards {}", stack.function, next);
+                self.faas_routing_table
+                    .update(stack.function.to_owned(),
+                            destination.cloned(),
+                            Direction::NextNode(next.to_owned()))
                     .await?;
-                    return Ok(());
-                }
             }
-
-            if stack.routes.len() == 1 {
-                let last_node = stack.routes.pop().unwrap();
-                return if last_node == self.node_situation.get_my_id().await {
-                    trace!("Routing table is complete, I am the arrival point");
-                    self.faas_routing_table.update(stack.function, Direction::CurrentNode).await;
-                    Ok(())
-                } else {
-                    trace!("Routing table is complete, I am the departure point");
-                    self.faas_routing_table
-                        .update(stack.function, Direction::NextNode(last_node))
-                        .await;
-                    Ok(())
-                };
+            (RouteAction::Register, None) => {
+                trace!("Routing table is complete, I am the arrival point");
+                self.faas_routing_table
+                    .update(stack.function.to_owned(), destination.cloned(), Direction::CurrentNode)
+                    .await?;
             }
+            (RouteAction::Unregister, _) => {
+                trace!("Removing route to {}", stack.function);
+                self.faas_routing_table.remove(&stack.function, destination).await?;
+            }
+        }
+
+        if let Some(next) = next {
+            self.forward_route_to_node(&next, &stack, action).await?;
         }
-        Err(Error::MalformedRoutingStack)
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<R> Router for RouterImpl<R> where R: RoutingRepository
+{
+    async fn register_function_route(&self, stack: FunctionRoutingStack) -> Result<(), Error> {
+        self.walk_function_route(stack, RouteAction::Register).await
     }
 
-    async fn forward(&self, packet: &Packet) -> Result<Bytes, Error> {
+    async fn unregister_function_route(&self, stack: FunctionRoutingStack) -> Result<(), Error> {
+        self.walk_function_route(stack, RouteAction::Unregister).await
+    }
+
+    async fn forward(&self, packet: &Packet) -> Result<RoutedResponse, Error> {
         match packet {
-            Packet::FaaSFunction { to, data: payload } => {
+            Packet::FaaSFunction { to, mode, data: payload } => {
                 let node_to = self.faas_routing_table
-                                  .get(to)
+                                  .get(to, None)
                                   .await
                                   .ok_or_else(|| Error::UnknownBidId(to.to_owned()))?;
 
@@ -193,14 +197,12 @@ impl<R> Router for RouterImpl<R> where R: RoutingRepository
                     // TODO: optimization: is it possible to send the packet directly to the node?
                     // w/o redoing the same structure, what impact?
                     Direction::NextNode(next) => {
-                        let next = self.node_situation
-                                       .get_fog_node_neighbor(&next)
-                                       .await
-                                       .ok_or_else(|| Error::NextNodeDoesntExist(next.to_owned()))?;
+                        let next = self.get_live_next_node(&next).await?;
                         Ok(self.routing
                                .forward_to_routing(&next.ip,
                                                    &next.port,
                                                    &Packet::FaaSFunction { to:   to.to_owned(),
+                                                                           mode: *mode,
                                                                            data: payload, })
                                .await?)
                     }
@@ -209,13 +211,20 @@ impl<R> Router for RouterImpl<R> where R: RoutingRepository
                                          .get_provisioned_function(to)
                                          .await
                                          .ok_or_else(|| Error::UnknownBidId(to.to_owned()))?;
-                        self.faas_api
-                            .async_function_name_post(&record.function_name,
-                                                      serde_json::to_string(payload)?)
-                            .await
-                            .map_err(Error::from)?;
-                        // TODO check if that doesn't cause any harm
-                        Ok(Bytes::new())
+                        let payload = serde_json::to_string(payload)?;
+                        match mode {
+                            InvocationMode::Async => {
+                                self.faas_api
+                                    .async_function_name_post(&record.function_name, payload)
+                                    .await?;
+                                Ok(RoutedResponse::ok(Bytes::new()))
+                            }
+                            InvocationMode::Sync => {
+                                Ok(self.faas_api
+                                       .function_name_post(&record.function_name, payload)
+                                       .await?)
+                            }
+                        }
                     }
                 }
             }
@@ -231,32 +240,61 @@ impl<R> Router for RouterImpl<R> where R: RoutingRepository
                     let my_ip = self.node_situation.get_my_public_ip().await;
                     let my_port = self.node_situation.get_my_public_port().await;
 
-                    Ok(self.routing.forward_to_url(&my_ip, &my_port, resource_uri, data).await?)
+                    Ok(RoutedResponse::ok(self.routing
+                                              .forward_to_url(&my_ip, &my_port, resource_uri, data)
+                                              .await?))
                 } else {
                     let next = route_to.last().unwrap();
-                    let next = self.node_situation
-                                   .get_fog_node_neighbor(next)
-                                   .await
-                                   .ok_or_else(|| Error::NextNodeDoesntExist(next.to_owned()))?;
-                    Ok(self.routing
-                           .forward_to_routing(&next.ip,
-                                               &next.port,
-                                               &Packet::FogNode { route_to_stack: route_to,
-                                                                  resource_uri:
-                                                                      resource_uri.to_owned(),
-                                                                  data })
-                           .await?)
+                    let next = self.get_live_next_node(next).await?;
+                    Self::ensure_success(self.routing
+                                             .forward_to_routing(&next.ip,
+                                                                 &next.port,
+                                                                 &Packet::FogNode { route_to_stack:
+                                                                                        route_to,
+                                                                                    resource_uri:
+                                                                                        resource_uri.to_owned(),
+                                                                                    data })
+                                             .await?)
+                }
+            }
+            Packet::FunctionResponse { from, to, resource_uri, data } => {
+                let node_to = self.faas_routing_table
+                                  .get(from, Some(to))
+                                  .await
+                                  .ok_or_else(|| Error::UnknownBidId(from.to_owned()))?;
+
+                match node_to {
+                    Direction::NextNode(next) => {
+                        let next = self.get_live_next_node(&next).await?;
+                        Self::ensure_success(self.routing
+                                                 .forward_to_routing(&next.ip, &next.port, packet)
+                                                 .await?)
+                    }
+                    Direction::CurrentNode => {
+                        let my_ip = self.node_situation.get_my_public_ip().await;
+                        let my_port = self.node_situation.get_my_public_port().await;
+                        Ok(RoutedResponse::ok(self.routing
+                                                  .forward_to_url(&my_ip,
+                                                                  &my_port,
+                                                                  resource_uri,
+                                                                  data)
+                                                  .await?))
+                    }
                 }
             }
             Packet::Market { resource_uri, data } => {
                 if self.node_situation.is_market().await {
                     trace!("Transmitting market packet to market: {:?}", packet);
                     let (ip, port) = self.node_situation.get_market_node_address().await.unwrap();
-                    Ok(self.routing.forward_to_url(&ip, &port, resource_uri, data).await?)
+                    Ok(RoutedResponse::ok(self.routing
+                                              .forward_to_url(&ip, &port, resource_uri, data)
+                                              .await?))
                 } else {
                     trace!("Transmitting market packet to other node: {:?}", packet);
                     let (ip, port) = self.node_situation.get_parent_node_address().await.unwrap();
-                    Ok(self.routing.forward_to_routing(&ip, &port, packet).await?)
+                    Self::ensure_success(self.routing
+                                             .forward_to_routing(&ip, &port, packet)
+                                             .await?)
                 }
             }
         }
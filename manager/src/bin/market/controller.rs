@@ -1,14 +1,20 @@
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
+use uom::si::f64::Time;
+use uom::si::time::second;
 
 use manager::model::domain::auction::AuctionResult;
-use manager::model::view::auction::AcceptedBid;
-use manager::model::view::node::{GetFogNodes, RegisterNode};
+use manager::model::view::auction::{AcceptedBid, BidProposals, ProvisioningFailure};
+use manager::model::view::ledger::{Balances, Statement};
+use manager::model::view::node::{GetFogNodes, HeartbeatAck, PostHeartbeat, PostLiveness,
+                                 RegisterNode, UnregisterNode};
 use manager::model::view::sla::PutSla;
-use manager::model::NodeId;
+use manager::model::view::topology::Topology;
+use manager::model::{BidId, NodeId};
 
 #[derive(thiserror::Error, Debug)]
 pub enum ControllerError {
@@ -19,34 +25,513 @@ pub enum ControllerError {
     #[error(transparent)]
     FaaS(#[from] crate::service::faas::Error),
     #[error(transparent)]
+    Routing(#[from] crate::service::routing::Error),
+    #[error("Every bidder failed to provision the function: {0:?}")]
+    ProvisioningFailed(Vec<ProvisioningFailure>),
+    #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
 /// Register a SLA and starts the auctioning process, can take a while.
+/// The function goes to the best bidder able to provision it, the ones that failed before are
+/// reported in the [AcceptedBid].
+/// Once the function is provisioned, establish the routes from the sources and to the
+/// destinations, and schedule its re-auctioning every reevaluation period of the SLA.
 // TODO define "a while"; set a timeout
 pub async fn start_auction(payload: PutSla,
                            auction_service: &Arc<dyn crate::service::auction::Auction>,
-                           faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>)
+                           faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                           router_service: &Arc<dyn crate::service::routing::Router>,
+                           billing_service: &Arc<dyn crate::service::billing::Billing>)
                            -> Result<AcceptedBid, ControllerError> {
     trace!("put sla: {:?}", payload);
 
-    let proposals = auction_service.call_for_bids(payload.target_node, payload.sla).await?;
+    let proposals =
+        auction_service.call_for_bids(payload.target_node.clone(), payload.sla.clone()).await?;
+
+    let accepted = provision_on_best_bidder(payload,
+                                            proposals,
+                                            None,
+                                            auction_service,
+                                            faas_service,
+                                            router_service,
+                                            billing_service).await?;
+
+    schedule_reevaluation(accepted.function.clone(),
+                          accepted.sla.sla.reevaluation_period,
+                          auction_service.clone(),
+                          faas_service.clone(),
+                          router_service.clone(),
+                          billing_service.clone());
+
+    Ok(accepted)
+}
 
-    let AuctionResult { chosen_bid } = auction_service.do_auction(&proposals).await?;
+/// Auction the function among the [proposals] and provision it on the winner. Should the winner
+/// fail to provision it, the auction is run again without it, so that the price is cleared
+/// among the remaining bidders as the mechanism dictates, until one of them succeeds.
+/// For a function that is already provisioned as [current], the new instance keeps its identity
+/// and takes over its routes; if the node hosting [current] wins, the bid it won is cancelled
+/// and [current] is returned as is.
+async fn provision_on_best_bidder(sla: PutSla,
+                                  proposals: BidProposals,
+                                  current: Option<&AcceptedBid>,
+                                  auction_service: &Arc<dyn crate::service::auction::Auction>,
+                                  faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                                  router_service: &Arc<dyn crate::service::routing::Router>,
+                                  billing_service: &Arc<dyn crate::service::billing::Billing>)
+                                  -> Result<AcceptedBid, ControllerError> {
+    let mut remaining = proposals.clone();
+    let mut failed_attempts = Vec::new();
+    loop {
+        let AuctionResult { chosen_bid, mechanism } =
+            match auction_service.do_auction(&remaining).await {
+                Ok(result) => result,
+                Err(err) if failed_attempts.is_empty() => return Err(err.into()),
+                Err(_) => return Err(ControllerError::ProvisioningFailed(failed_attempts)),
+            };
 
-    let accepted = AcceptedBid { chosen: chosen_bid, proposals };
+        if let Some(current) =
+            current.filter(|current| current.chosen.bid.node_id == chosen_bid.bid.node_id)
+        {
+            trace!("function {} stays on {}", current.function, current.chosen.bid.node_id);
+            if let Err(err) = faas_service.cancel_bid(&chosen_bid.bid).await {
+                warn!("failed to cancel the bid {}: {:?}", chosen_bid.bid.id, err);
+            }
+            return Ok(current.clone());
+        }
 
+        let function = current.map(|current| current.function.clone())
+                              .unwrap_or_else(|| chosen_bid.bid.id.clone());
+        let accepted = AcceptedBid { function,
+                                     chosen: chosen_bid,
+                                     proposals: proposals.clone(),
+                                     sla: sla.clone(),
+                                     mechanism,
+                                     failed_attempts: failed_attempts.clone() };
+
+        match provision_and_route(&accepted,
+                                  current,
+                                  faas_service,
+                                  router_service,
+                                  billing_service).await
+        {
+            Ok(()) => return Ok(accepted),
+            Err(err) => {
+                warn!("node {} failed to provision the function, trying the next bidder: {:?}",
+                      accepted.chosen.bid.node_id, err);
+                remaining.bids.retain(|bid| bid.id != accepted.chosen.bid.id);
+                failed_attempts.push(ProvisioningFailure { chosen: accepted.chosen,
+                                                           reason: err.to_string(), });
+            }
+        }
+    }
+}
+
+/// Establish the routes from the sources to the function [accepted] and from it to the
+/// destinations
+async fn register_routes(accepted: &AcceptedBid,
+                         router_service: &Arc<dyn crate::service::routing::Router>)
+                         -> Result<(), crate::service::routing::Error> {
+    router_service.register_function_routes(accepted.function.clone(),
+                                            accepted.chosen.bid.node_id.clone(),
+                                            accepted.sla.request_sources.clone(),
+                                            accepted.sla.request_destinations.clone())
+                  .await
+}
+
+/// Tear down the routes established by [register_routes]
+async fn unregister_routes(accepted: &AcceptedBid,
+                           router_service: &Arc<dyn crate::service::routing::Router>)
+                           -> Result<(), crate::service::routing::Error> {
+    router_service.unregister_function_routes(accepted.function.clone(),
+                                              accepted.chosen.bid.node_id.clone(),
+                                              accepted.sla.request_sources.clone(),
+                                              accepted.sla.request_destinations.clone())
+                  .await
+}
+
+/// Provision the function of [accepted] and establish its routes, as a whole: if the routes
+/// cannot be established, the ones that were are torn down and the function is deprovisioned.
+/// The routes of the [current] instance, sharing the same identity, are torn down beforehand
+/// and established again on failure.
+/// Once both are done, the client starts being charged for the function.
+async fn provision_and_route(accepted: &AcceptedBid,
+                             current: Option<&AcceptedBid>,
+                             faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                             router_service: &Arc<dyn crate::service::routing::Router>,
+                             billing_service: &Arc<dyn crate::service::billing::Billing>)
+                             -> Result<(), ControllerError> {
     faas_service.provision_function(accepted.clone()).await?;
 
-    Ok(accepted)
+    let id = &accepted.function;
+    if let Some(current) = current {
+        if let Err(err) = unregister_routes(current, router_service).await {
+            warn!("failed to tear down the routes of {} to {}: {:?}",
+                  id, current.chosen.bid.node_id, err);
+        }
+    }
+
+    if let Err(err) = register_routes(accepted, router_service).await {
+        warn!("failed to establish the routes of {}, rolling back: {:?}", id, err);
+        if let Err(err) = unregister_routes(accepted, router_service).await {
+            warn!("failed to tear down the routes of {}: {:?}", id, err);
+        }
+        if let Err(err) = faas_service.remove_function(accepted).await {
+            error!("failed to deprovision the function {}: {:?}", id, err);
+        }
+        if let Some(current) = current {
+            if let Err(err) = register_routes(current, router_service).await {
+                error!("failed to restore the routes of {} to {}: {:?}",
+                       id, current.chosen.bid.node_id, err);
+            }
+        }
+        return Err(err.into());
+    }
+
+    if let Err(err) = billing_service.open_contract(accepted).await {
+        error!("failed to open the contract of the function {}: {:?}", id, err);
+    }
+
+    Ok(())
+}
+
+/// Stop charging for the function instance [id], which is not provisioned anymore
+async fn close_contract(id: &BidId, billing_service: &Arc<dyn crate::service::billing::Billing>) {
+    if let Err(err) = billing_service.close_contract(id).await {
+        error!("failed to close the contract of the function {}: {:?}", id, err);
+    }
+}
+
+/// Re-auction the function [id] every [period], for as long as it is provisioned.
+/// A period of zero or less disables the reevaluation.
+pub fn schedule_reevaluation(id: BidId,
+                             period: Time,
+                             auction_service: Arc<dyn crate::service::auction::Auction>,
+                             faas_service: Arc<dyn crate::service::faas::FogNodeFaaS>,
+                             router_service: Arc<dyn crate::service::routing::Router>,
+                             billing_service: Arc<dyn crate::service::billing::Billing>) {
+    let period = period.get::<second>();
+    if !period.is_finite() || period <= 0.0 {
+        trace!("no reevaluation scheduled for {}", id);
+        return;
+    }
+    let period = Duration::from_secs_f64(period);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(period).await;
+            match reevaluate_function(&id,
+                                      &auction_service,
+                                      &faas_service,
+                                      &router_service,
+                                      &billing_service).await
+            {
+                Ok(()) => (),
+                Err(ControllerError::FaaS(crate::service::faas::Error::FunctionNotFound(_))) => {
+                    trace!("function {} is not provisioned anymore, stopping its reevaluation", id);
+                    break;
+                }
+                Err(err) => warn!("failed to reevaluate the function {}: {:?}", id, err),
+            }
+        }
+    });
 }
 
-/// Register a new node in the network
+/// Re-auction a provisioned function. If another node wins, the function is provisioned on it,
+/// falling back on the next bidders should it fail, the routes are switched to the new
+/// instance and then the old one is deprovisioned. The function keeps its id.
+pub async fn reevaluate_function(id: &BidId,
+                                 auction_service: &Arc<dyn crate::service::auction::Auction>,
+                                 faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                                 router_service: &Arc<dyn crate::service::routing::Router>,
+                                 billing_service: &Arc<dyn crate::service::billing::Billing>)
+                                 -> Result<(), ControllerError> {
+    trace!("reevaluating function: {:?}", id);
+
+    let current = faas_service.get_function(id).await?;
+
+    let proposals = auction_service.call_for_bids(current.sla.target_node.clone(),
+                                                  current.sla.sla.clone())
+                                   .await?;
+
+    let accepted = provision_on_best_bidder(current.sla.clone(),
+                                            proposals,
+                                            Some(&current),
+                                            auction_service,
+                                            faas_service,
+                                            router_service,
+                                            billing_service).await?;
+    if accepted.chosen.bid.id == current.chosen.bid.id {
+        return Ok(());
+    }
+
+    retire_function(&current, true, faas_service, billing_service).await?;
+    info!("function {} moved from {} to {}",
+          id, current.chosen.bid.node_id, accepted.chosen.bid.node_id);
+    Ok(())
+}
+
+/// Once the routes are switched to another instance, deprovision the [current] one and stop
+/// charging for it. The node hosting it is asked to deprovision it only if [reachable];
+/// otherwise, its record is only forgotten.
+async fn retire_function(current: &AcceptedBid,
+                         reachable: bool,
+                         faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                         billing_service: &Arc<dyn crate::service::billing::Billing>)
+                         -> Result<(), ControllerError> {
+    close_contract(&current.chosen.bid.id, billing_service).await;
+    if reachable {
+        faas_service.remove_function(current).await?;
+    } else {
+        faas_service.forget_function(current).await?;
+    }
+    Ok(())
+}
+
+/// Auction again a function hosted on a node leaving the network, among the other nodes, and
+/// provision it on the best bidder able to, the routes being switched to it. The [current]
+/// instance is left provisioned.
+async fn relocate_function(current: &AcceptedBid,
+                           leaving: &NodeId,
+                           auction_service: &Arc<dyn crate::service::auction::Auction>,
+                           faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                           router_service: &Arc<dyn crate::service::routing::Router>,
+                           billing_service: &Arc<dyn crate::service::billing::Billing>)
+                           -> Result<AcceptedBid, ControllerError> {
+    let mut proposals = auction_service.call_for_bids(current.sla.target_node.clone(),
+                                                      current.sla.sla.clone())
+                                       .await?;
+    proposals.bids.retain(|bid| &bid.node_id != leaving);
+
+    provision_on_best_bidder(current.sla.clone(),
+                             proposals,
+                             Some(current),
+                             auction_service,
+                             faas_service,
+                             router_service,
+                             billing_service).await
+}
+
+/// Remove a provisioned function: tear down its routes, deprovision it from the node hosting it,
+/// forget about its record and stop charging for it.
+pub async fn remove_function(id: BidId,
+                             faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                             router_service: &Arc<dyn crate::service::routing::Router>,
+                             billing_service: &Arc<dyn crate::service::billing::Billing>)
+                             -> Result<(), ControllerError> {
+    trace!("remove function: {:?}", id);
+
+    let accepted = faas_service.get_function(&id).await?;
+
+    unregister_routes(&accepted, router_service).await?;
+    retire_function(&accepted, true, faas_service, billing_service).await
+}
+
+/// Get the functions whose routes pass by the [subtree], i.e., hosted, called from or calling
+/// into one of its nodes
+async fn functions_routed_through(subtree: &[NodeId],
+                                  faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>)
+                                  -> Vec<AcceptedBid> {
+    faas_service.get_functions()
+                .await
+                .into_values()
+                .flatten()
+                .filter(|accepted| {
+                    subtree.contains(&accepted.chosen.bid.node_id)
+                    || accepted.sla
+                               .request_sources
+                               .iter()
+                               .chain(accepted.sla.request_destinations.iter())
+                               .any(|end| subtree.contains(end))
+                })
+                .collect()
+}
+
+/// Register a new node in the network.
+/// If the node is re-attached under another parent, the routes of the functions hosted, called
+/// from or calling into its subtree are torn down along the former paths, then established
+/// again along the new ones.
 pub async fn register_node(payload: RegisterNode,
-                           fog_net: &Arc<dyn crate::service::fog_node_network::FogNodeNetwork>)
+                           fog_net: &Arc<dyn crate::service::fog_node_network::FogNodeNetwork>,
+                           faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                           router_service: &Arc<dyn crate::service::routing::Router>)
                            -> Result<(), ControllerError> {
     trace!("registering new node: {:?}", payload);
-    fog_net.register_node(payload).await?;
+    let node_id = match &payload {
+        RegisterNode::MarketNode { node_id, .. } | RegisterNode::Node { node_id, .. } => {
+            node_id.clone()
+        }
+    };
+    let affected = if fog_net.is_moving(&payload).await {
+        functions_routed_through(&fog_net.get_subtree(&node_id).await, faas_service).await
+    } else {
+        vec![]
+    };
+    for accepted in affected.iter() {
+        if let Err(err) = unregister_routes(accepted, router_service).await {
+            warn!("failed to tear down the former routes of {}: {:?}", accepted.function, err);
+        }
+    }
+
+    let registered = fog_net.register_node(payload).await;
+    if let Ok(Some(former_parent)) = &registered {
+        info!("node {} moved away from {}, recomputing the routes", node_id, former_parent);
+    }
+    // The routes are established again either way, along the former paths if the move failed
+    for accepted in affected.iter() {
+        if let Err(err) = register_routes(accepted, router_service).await {
+            warn!("failed to recompute the routes of {}: {:?}", accepted.function, err);
+        }
+    }
+    registered?;
+    Ok(())
+}
+
+/// Unregister a node leaving the network.
+/// The functions it hosts are auctioned again among the other nodes; the ones that cannot be
+/// relocated are removed. Its children are attached to its parent.
+pub async fn unregister_node(payload: UnregisterNode,
+                             fog_net: &Arc<dyn crate::service::fog_node_network::FogNodeNetwork>,
+                             auction_service: &Arc<dyn crate::service::auction::Auction>,
+                             faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                             router_service: &Arc<dyn crate::service::routing::Router>,
+                             billing_service: &Arc<dyn crate::service::billing::Billing>)
+                             -> Result<(), ControllerError> {
+    info!("node {} is leaving the network", payload.node_id);
+    remove_node(&payload.node_id,
+                true,
+                fog_net,
+                auction_service,
+                faas_service,
+                router_service,
+                billing_service).await
+}
+
+/// Remove [node] from the network, once checked that it can be.
+/// The functions it hosts are relocated first; then the routes passing by its subtree are torn
+/// down, and established again once its children are attached to its parent.
+async fn remove_node(node: &NodeId,
+                     reachable: bool,
+                     fog_net: &Arc<dyn crate::service::fog_node_network::FogNodeNetwork>,
+                     auction_service: &Arc<dyn crate::service::auction::Auction>,
+                     faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                     router_service: &Arc<dyn crate::service::routing::Router>,
+                     billing_service: &Arc<dyn crate::service::billing::Billing>)
+                     -> Result<(), ControllerError> {
+    fog_net.check_removable(node).await?;
+    relocate_hosted_functions(node,
+                              reachable,
+                              auction_service,
+                              faas_service,
+                              router_service,
+                              billing_service).await;
+
+    let affected = functions_routed_through(&fog_net.get_subtree(node).await, faas_service).await;
+    for accepted in affected.iter() {
+        if let Err(err) = unregister_routes(accepted, router_service).await {
+            warn!("failed to tear down the former routes of {}: {:?}", accepted.function, err);
+        }
+    }
+    let unregistered = fog_net.unregister_node(node).await;
+    // The routes are established again either way, along the former paths if the removal failed
+    for accepted in affected.iter() {
+        if let Err(err) = register_routes(accepted, router_service).await {
+            warn!("failed to recompute the routes of {}: {:?}", accepted.function, err);
+        }
+    }
+    unregistered?;
+    Ok(())
+}
+
+/// Relocate the functions hosted on [node], before it is removed from the network. The ones that
+/// cannot be relocated are removed.
+/// The former instances are deprovisioned only if [node] is [reachable]; otherwise, their
+/// records are only forgotten.
+async fn relocate_hosted_functions(node: &NodeId,
+                                   reachable: bool,
+                                   auction_service: &Arc<dyn crate::service::auction::Auction>,
+                                   faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                                   router_service: &Arc<dyn crate::service::routing::Router>,
+                                   billing_service: &Arc<dyn crate::service::billing::Billing>) {
+    let hosted = faas_service.get_functions().await.remove(node).unwrap_or_default();
+    for current in hosted {
+        let id = current.function.clone();
+        match relocate_function(&current,
+                                node,
+                                auction_service,
+                                faas_service,
+                                router_service,
+                                billing_service).await
+        {
+            Ok(accepted) => {
+                info!("function {} relocated from {} to {}", id, node, accepted.chosen.bid.node_id)
+            }
+            Err(err) => {
+                error!("failed to relocate the function {}, removing it: {:?}", id, err);
+                if let Err(err) = unregister_routes(&current, router_service).await {
+                    warn!("failed to tear down the routes of {}: {:?}", id, err);
+                }
+            }
+        }
+
+        if let Err(err) = retire_function(&current, reachable, faas_service, billing_service).await
+        {
+            warn!("failed to remove the function {} from {}: {:?}", id, node, err);
+        }
+    }
+}
+
+/// Record the heartbeat of a node. A node unknown to the market is told to register again.
+pub async fn heartbeat(payload: PostHeartbeat,
+                       fog_net: &Arc<dyn crate::service::fog_node_network::FogNodeNetwork>)
+                       -> Result<HeartbeatAck, ControllerError> {
+    trace!("heartbeat from {}: {:?}", payload.node_id, payload.summary);
+    match fog_net.heartbeat(&payload.node_id, payload.summary).await {
+        Ok(()) => Ok(HeartbeatAck { forgotten: false }),
+        Err(crate::service::fog_node_network::Error::NodeNotFound(_)) => {
+            warn!("heartbeat from the unknown node {}", payload.node_id);
+            Ok(HeartbeatAck { forgotten: true })
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Evict the nodes that have not sent any heartbeat for too long, relocating the functions they
+/// were hosting
+pub async fn evict_stale_nodes(fog_net: &Arc<dyn crate::service::fog_node_network::FogNodeNetwork>,
+                               auction_service: &Arc<dyn crate::service::auction::Auction>,
+                               faas_service: &Arc<dyn crate::service::faas::FogNodeFaaS>,
+                               router_service: &Arc<dyn crate::service::routing::Router>,
+                               billing_service: &Arc<dyn crate::service::billing::Billing>) {
+    let expired = match fog_net.check_heartbeats().await {
+        Ok(expired) => expired,
+        Err(err) => {
+            error!("failed to check the heartbeats: {:?}", err);
+            return;
+        }
+    };
+    for node in expired {
+        warn!("node {} has been silent for too long, evicting it", node);
+        if let Err(err) = remove_node(&node,
+                                      false,
+                                      fog_net,
+                                      auction_service,
+                                      faas_service,
+                                      router_service,
+                                      billing_service).await
+        {
+            error!("failed to evict the node {}: {:?}", node, err);
+        }
+    }
+}
+
+/// Record the liveness of a node reported by one of its neighbors
+pub async fn update_liveness(payload: PostLiveness,
+                             fog_net: &Arc<dyn crate::service::fog_node_network::FogNodeNetwork>)
+                             -> Result<(), ControllerError> {
+    info!("node {} reported node {} as {:?}", payload.reporter, payload.node_id, payload.liveness);
+    fog_net.update_liveness(&payload.node_id, payload.liveness).await?;
     Ok(())
 }
 
@@ -62,3 +547,407 @@ pub async fn get_fog(fog_node_network: &Arc<dyn crate::service::fog_node_network
                      -> Result<Vec<GetFogNodes>> {
     Ok(fog_node_network.get_nodes().await.into_iter().map(|val| val.into()).collect())
 }
+
+pub async fn get_topology(fog_node_network: &Arc<dyn crate::service::fog_node_network::FogNodeNetwork>)
+                          -> Result<Topology> {
+    Ok(fog_node_network.get_topology().await?)
+}
+
+/// Get the balances of all the nodes and clients, up to now
+pub async fn get_balances(billing_service: &Arc<dyn crate::service::billing::Billing>)
+                          -> Result<Balances> {
+    Ok(billing_service.get_balances().await?)
+}
+
+/// Get the contracts of the node, and what it earned from them up to now
+pub async fn get_node_statement(node: NodeId,
+                                billing_service: &Arc<dyn crate::service::billing::Billing>)
+                                -> Result<Statement> {
+    Ok(billing_service.get_node_statement(&node).await?)
+}
+
+/// Get the contracts of the client, and what it was charged for them up to now
+pub async fn get_client_statement(client: String,
+                                  billing_service: &Arc<dyn crate::service::billing::Billing>)
+                                  -> Result<Statement> {
+    Ok(billing_service.get_client_statement(&client).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use async_trait::async_trait;
+    use tokio::sync::Mutex;
+    use uom::si::f64::{Information, Ratio};
+    use uom::si::information::megabyte;
+    use uom::si::ratio::ratio;
+    use uuid::Uuid;
+
+    use manager::helper::snapshot::Snapshot;
+    use manager::model::domain::heartbeat::HeartbeatThresholds;
+    use manager::model::domain::pricing::Pricing;
+    use manager::model::domain::routing::FunctionRoutingStack;
+    use manager::model::domain::sla::Sla;
+    use manager::model::view::auction::BidProposal;
+
+    use crate::repository::auction::FirstPriceAuction;
+    use crate::repository::fog_node::{FogNode, FogNodeImpl};
+    use crate::repository::ledger::LedgerImpl;
+    use crate::repository::node_communication::{Error as CommunicationError, NodeCommunication};
+    use crate::service::auction::{Auction, AuctionImpl};
+    use crate::service::billing::{Billing, BillingImpl};
+    use crate::service::faas::{Error as FaaSError, FogNodeFaaS, FogNodeFaaSImpl};
+    use crate::service::fog_node_network::{FogNodeNetwork, FogNodeNetworkHashTreeImpl};
+    use crate::service::routing::{Router, RouterImpl};
+
+    use super::*;
+
+    /// Nodes bidding as told, and provisioning the functions unless they are told to fail
+    #[derive(Debug, Default)]
+    struct FakeNodes {
+        bids:      Mutex<Vec<BidProposal>>,
+        failing:   Mutex<HashSet<NodeId>>,
+        functions: Mutex<HashMap<NodeId, HashSet<BidId>>>,
+        cancelled: Mutex<Vec<BidId>>,
+        routes:    Mutex<HashSet<Vec<NodeId>>>,
+        /// The nodes told about a new neighbor, along with the neighbor
+        neighbors: Mutex<Vec<(NodeId, NodeId)>>,
+    }
+
+    impl FakeNodes {
+        async fn hosts(&self, node: &NodeId) -> HashSet<BidId> {
+            self.functions.lock().await.get(node).cloned().unwrap_or_default()
+        }
+    }
+
+    #[async_trait]
+    impl NodeCommunication for FakeNodes {
+        async fn request_bids_from_node(&self,
+                                        _to: NodeId,
+                                        _sla: Sla)
+                                        -> Result<BidProposals, CommunicationError> {
+            Ok(BidProposals { bids: self.bids.lock().await.clone(), failures: vec![] })
+        }
+
+        async fn take_offer(&self,
+                            to: NodeId,
+                            _bid: &BidProposal,
+                            function: &BidId)
+                            -> Result<(), CommunicationError> {
+            if self.failing.lock().await.contains(&to) {
+                return Err(CommunicationError::NodeIpNotFound(to));
+            }
+            self.functions.lock().await.entry(to).or_default().insert(function.clone());
+            Ok(())
+        }
+
+        async fn cancel_bid(&self,
+                            _to: NodeId,
+                            bid: &BidProposal)
+                            -> Result<(), CommunicationError> {
+            self.cancelled.lock().await.push(bid.id.clone());
+            Ok(())
+        }
+
+        async fn establish_route(&self,
+                                 stack: FunctionRoutingStack)
+                                 -> Result<(), CommunicationError> {
+            self.routes.lock().await.insert(stack.routes);
+            Ok(())
+        }
+
+        async fn remove_route(&self,
+                              stack: FunctionRoutingStack)
+                              -> Result<(), CommunicationError> {
+            self.routes.lock().await.remove(&stack.routes);
+            Ok(())
+        }
+
+        async fn remove_function(&self, to: NodeId, id: &BidId) -> Result<(), CommunicationError> {
+            let removed = self.functions
+                              .lock()
+                              .await
+                              .get_mut(&to)
+                              .is_some_and(|functions| functions.remove(id));
+            if removed {
+                Ok(())
+            } else {
+                Err(CommunicationError::NodeIdNotFound(to))
+            }
+        }
+
+        async fn change_parent(&self,
+                               to: NodeId,
+                               parent: &NodeId)
+                               -> Result<(), CommunicationError> {
+            self.neighbors.lock().await.push((to, parent.clone()));
+            Ok(())
+        }
+
+        async fn adopt_child(&self, to: NodeId, child: &NodeId) -> Result<(), CommunicationError> {
+            self.neighbors.lock().await.push((to, child.clone()));
+            Ok(())
+        }
+    }
+
+    struct Market {
+        nodes:   Arc<FakeNodes>,
+        fog_net: Arc<dyn FogNodeNetwork>,
+        auction: Arc<dyn Auction>,
+        faas:    Arc<dyn FogNodeFaaS>,
+        router:  Arc<dyn Router>,
+        billing: Arc<dyn Billing>,
+    }
+
+    /// A market over the tree root -> (a, b)
+    async fn market() -> (Market, [NodeId; 3]) {
+        let ids: [NodeId; 3] = std::array::from_fn(|_| NodeId::from(Uuid::new_v4()));
+        let [root, a, b] = ids.clone();
+        let market = market_over(FogNodeImpl::new());
+        market.register(RegisterNode::MarketNode { node_id: root.clone(),
+                                                   ip:      IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                                   port:    3000,
+                                                   tags:    vec![], })
+              .await;
+        for child in [a, b] {
+            market.register(RegisterNode::Node { parent:  root.clone(),
+                                                 node_id: child,
+                                                 ip:      IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                                 port:    3001,
+                                                 tags:    vec![], })
+                  .await;
+        }
+        (market, ids)
+    }
+
+    /// A market over the nodes of [fog_node]
+    fn market_over(fog_node: FogNodeImpl) -> Market {
+        let fog_node = Arc::new(fog_node);
+        let nodes = Arc::new(FakeNodes::default());
+        Market { nodes:   nodes.clone(),
+                 fog_net: Arc::new(FogNodeNetworkHashTreeImpl::new(fog_node.clone(),
+                                                                   nodes.clone(),
+                                                                   HeartbeatThresholds::default())),
+                 auction: Arc::new(AuctionImpl::new(Arc::new(FirstPriceAuction::new()),
+                                                    nodes.clone())),
+                 faas:    Arc::new(FogNodeFaaSImpl::new(fog_node.clone(), nodes.clone())),
+                 router:  Arc::new(RouterImpl::new(fog_node, nodes)),
+                 billing: Arc::new(BillingImpl::new(Arc::new(LedgerImpl::new()),
+                                                    Time::new::<second>(60.0))), }
+    }
+
+    fn put_sla(target_node: &NodeId) -> PutSla {
+        let sla = Sla { storage:              Information::new::<megabyte>(0.0),
+                        memory:               Information::new::<megabyte>(64.0),
+                        cpu:                  Ratio::new::<ratio>(0.1),
+                        latency_max:          Time::new::<second>(1.0),
+                        data_input_max_size:  Information::new::<megabyte>(1.0),
+                        data_output_max_size: Information::new::<megabyte>(1.0),
+                        max_time_before_hot:  Time::new::<second>(10.0),
+                        reevaluation_period:  Time::new::<second>(0.0),
+                        function_image:       "echo".to_string(),
+                        function_live_name:   None,
+                        placement:            None, };
+        PutSla { sla,
+                 target_node: target_node.clone(),
+                 request_sources: vec![target_node.clone()],
+                 request_destinations: vec![],
+                 client: "client".to_string() }
+    }
+
+    fn bid(node: &NodeId, price: f64) -> BidProposal {
+        BidProposal { node_id: node.clone(),
+                      id:      BidId::from(Uuid::new_v4()),
+                      bid:     price,
+                      pricing: Pricing::default(), }
+    }
+
+    impl Market {
+        async fn start_auction(&self, bids: Vec<BidProposal>, target: &NodeId) -> AcceptedBid {
+            *self.nodes.bids.lock().await = bids;
+            start_auction(put_sla(target), &self.auction, &self.faas, &self.router, &self.billing)
+                .await
+                .unwrap()
+        }
+
+        async fn register(&self, node: RegisterNode) {
+            register_node(node, &self.fog_net, &self.faas, &self.router).await.unwrap();
+        }
+
+        async fn unregister(&self, node: &NodeId) -> Result<(), ControllerError> {
+            unregister_node(UnregisterNode { node_id: node.clone() },
+                            &self.fog_net,
+                            &self.auction,
+                            &self.faas,
+                            &self.router,
+                            &self.billing).await
+        }
+
+        async fn reevaluate(&self, bids: Vec<BidProposal>, id: &BidId) {
+            *self.nodes.bids.lock().await = bids;
+            reevaluate_function(id, &self.auction, &self.faas, &self.router, &self.billing).await
+                                                                                         .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_moved_function_keeps_its_id() {
+        let (market, [_, a, b]) = market().await;
+        let accepted = market.start_auction(vec![bid(&a, 1.0), bid(&b, 2.0)], &a).await;
+        let id = accepted.function.clone();
+        assert_eq!(id, accepted.chosen.bid.id);
+
+        market.reevaluate(vec![bid(&a, 2.0), bid(&b, 1.0)], &id).await;
+        let moved = market.faas.get_function(&id).await.unwrap();
+        assert_eq!(moved.chosen.bid.node_id, b);
+        assert_ne!(moved.chosen.bid.id, id);
+        assert!(market.nodes.hosts(&a).await.is_empty());
+        assert_eq!(market.nodes.hosts(&b).await, HashSet::from([id.clone()]));
+        // The contract with a ended, and was settled
+        let contracts = market.billing.get_client_statement("client").await.unwrap().contracts;
+        assert_eq!(contracts.len(), 1);
+        assert_eq!(contracts[0].provider, b);
+        assert!(contracts[0].ended_at.is_none());
+
+        remove_function(id.clone(), &market.faas, &market.router, &market.billing).await.unwrap();
+        assert!(market.nodes.hosts(&b).await.is_empty());
+        assert!(market.faas.get_functions().await.into_values().flatten().next().is_none());
+        let contracts = market.billing.get_client_statement("client").await.unwrap().contracts;
+        assert!(contracts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_function_being_moved_is_not_removed() {
+        let (market, [_, a, b]) = market().await;
+        let accepted = market.start_auction(vec![bid(&a, 1.0)], &a).await;
+        let id = accepted.function.clone();
+        // The new instance is provisioned, the former one is not retired yet
+        let mut moved = accepted.clone();
+        moved.chosen.bid = bid(&b, 1.0);
+        market.faas.provision_function(moved).await.unwrap();
+
+        assert!(matches!(market.faas.get_function(&id).await,
+                         Err(FaaSError::FunctionBeingMoved(_))));
+        let removed =
+            remove_function(id.clone(), &market.faas, &market.router, &market.billing).await;
+        assert!(matches!(removed, Err(ControllerError::FaaS(FaaSError::FunctionBeingMoved(_)))));
+        assert_eq!(market.nodes.hosts(&a).await, HashSet::from([id.clone()]));
+        assert_eq!(market.nodes.hosts(&b).await, HashSet::from([id]));
+    }
+
+    #[tokio::test]
+    async fn test_next_bidder_hosts_the_function_when_the_best_one_fails() {
+        let (market, [_, a, b]) = market().await;
+        market.nodes.failing.lock().await.insert(a.clone());
+        let accepted = market.start_auction(vec![bid(&a, 1.0), bid(&b, 2.0)], &a).await;
+
+        assert_eq!(accepted.chosen.bid.node_id, b);
+        assert_eq!(accepted.chosen.price, 2.0);
+        assert_eq!(accepted.failed_attempts.len(), 1);
+        assert_eq!(accepted.failed_attempts[0].chosen.bid.node_id, a);
+        assert!(market.nodes.hosts(&a).await.is_empty());
+        assert_eq!(market.nodes.hosts(&b).await, HashSet::from([accepted.function.clone()]));
+        let current = market.faas.get_function(&accepted.function).await.unwrap();
+        assert_eq!(current.chosen.bid.node_id, b);
+    }
+
+    #[tokio::test]
+    async fn test_bid_won_by_the_current_host_is_cancelled() {
+        let (market, [_, a, b]) = market().await;
+        let accepted = market.start_auction(vec![bid(&a, 1.0)], &a).await;
+
+        let won = bid(&a, 1.0);
+        market.reevaluate(vec![won.clone(), bid(&b, 2.0)], &accepted.function).await;
+        assert_eq!(*market.nodes.cancelled.lock().await, vec![won.id]);
+        let current = market.faas.get_function(&accepted.function).await.unwrap();
+        assert_eq!(current.chosen.bid.id, accepted.chosen.bid.id);
+    }
+
+    #[tokio::test]
+    async fn test_moved_node_is_routed_along_the_new_path() {
+        let (market, [root, a, b]) = market().await;
+        market.start_auction(vec![bid(&a, 1.0)], &b).await;
+        let routes = market.nodes.routes.lock().await.clone();
+        assert!(routes.iter().any(|route| route.contains(&root)));
+
+        let moved = RegisterNode::Node { parent:  a.clone(),
+                                         node_id: b.clone(),
+                                         ip:      IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                         port:    3001,
+                                         tags:    vec!["moved".to_string()], };
+        register_node(moved, &market.fog_net, &market.faas, &market.router).await.unwrap();
+
+        let routes = market.nodes.routes.lock().await.clone();
+        assert!(!routes.is_empty());
+        assert!(routes.iter().all(|route| !route.contains(&root)));
+        let (_, record) =
+            market.fog_net.get_nodes().await.into_iter().find(|(id, _)| *id == b).unwrap();
+        assert_eq!(record.port, Some(3001));
+        assert_eq!(record.tags, vec!["moved".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_root_cannot_leave() {
+        let (market, [root, ..]) = market().await;
+        let accepted = market.start_auction(vec![bid(&root, 1.0)], &root).await;
+
+        assert!(matches!(market.unregister(&root).await, Err(ControllerError::FogNodeNetwork(_))));
+        assert_eq!(market.nodes.hosts(&root).await, HashSet::from([accepted.function.clone()]));
+        let current = market.faas.get_function(&accepted.function).await.unwrap();
+        assert_eq!(current.chosen.bid.id, accepted.chosen.bid.id);
+    }
+
+    #[tokio::test]
+    async fn test_children_of_a_departed_node_are_attached_to_its_parent() {
+        let (market, [root, a, b]) = market().await;
+        let under_a = RegisterNode::Node { parent:  a.clone(),
+                                           node_id: b.clone(),
+                                           ip:      IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                           port:    3002,
+                                           tags:    vec![], };
+        register_node(under_a, &market.fog_net, &market.faas, &market.router).await.unwrap();
+        market.start_auction(vec![bid(&root, 1.0)], &b).await;
+        let routes = market.nodes.routes.lock().await.clone();
+        assert!(routes.iter().any(|route| route.contains(&a)));
+
+        market.unregister(&a).await.unwrap();
+        assert_eq!(*market.nodes.neighbors.lock().await,
+                   vec![(root.clone(), b.clone()), (b.clone(), root.clone())]);
+        let routes = market.nodes.routes.lock().await.clone();
+        assert!(!routes.is_empty());
+        assert!(routes.iter().all(|route| !route.contains(&a)));
+        assert!(market.fog_net.get_subtree(&root).await.contains(&b));
+    }
+
+    #[tokio::test]
+    async fn test_registered_nodes_are_restored() {
+        let path = std::env::temp_dir().join(format!("fog_nodes_{}.json", Uuid::new_v4()));
+        let market = market_over(FogNodeImpl::load(Snapshot::new(path.clone())).await.unwrap());
+        let root = NodeId::from(Uuid::new_v4());
+        market.register(RegisterNode::MarketNode { node_id: root.clone(),
+                                                   ip:      IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                                   port:    3000,
+                                                   tags:    vec![], })
+              .await;
+
+        let mut ids = vec![root];
+        for ii in 0..100 {
+            let id = NodeId::from(Uuid::new_v4());
+            let parent = ids[(ii * 7919) % ids.len()].clone();
+            market.register(RegisterNode::Node { parent,
+                                                 node_id: id.clone(),
+                                                 ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                                 port: 3001,
+                                                 tags: vec![] })
+                  .await;
+            ids.push(id);
+        }
+
+        let restored = FogNodeImpl::load(Snapshot::new(path.clone())).await.unwrap();
+        assert_eq!(restored.get_subtree(&ids[0]).await.len(), ids.len());
+        let _ = tokio::fs::remove_file(path.with_extension("journal")).await;
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
@@ -1,15 +1,19 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use rocket::response::content::{RawText, RawXml};
 use rocket::serde::json::Json;
-use rocket::{get, post, put, State};
+use rocket::{delete, get, post, put, State};
 use rocket_okapi::openapi;
 
-use manager::helper::handler::Resp;
+use manager::helper::handler::{Error, Resp};
 use manager::model::view::auction::AcceptedBid;
-use manager::model::view::node::{GetFogNodes, RegisterNode};
+use manager::model::view::ledger::{Balances, Statement};
+use manager::model::view::node::{GetFogNodes, HeartbeatAck, PostHeartbeat, PostLiveness,
+                                 RegisterNode, UnregisterNode};
 use manager::model::view::sla::PutSla;
-use manager::model::NodeId;
+use manager::model::view::topology::Topology;
+use manager::model::{BidId, NodeId};
 use manager::respond;
 
 use crate::controller;
@@ -20,20 +24,80 @@ use crate::controller;
 #[put("/function", data = "<payload>")]
 pub async fn put_function(payload: Json<PutSla>,
                           auction_service: &State<Arc<dyn crate::service::auction::Auction>>,
-                          faas_service: &State<Arc<dyn crate::service::faas::FogNodeFaaS>>)
+                          faas_service: &State<Arc<dyn crate::service::faas::FogNodeFaaS>>,
+                          router_service: &State<Arc<dyn crate::service::routing::Router>>,
+                          billing_service: &State<Arc<dyn crate::service::billing::Billing>>)
                           -> Resp<AcceptedBid> {
-    respond!(
-        controller::start_auction(payload.0, auction_service.inner(), faas_service.inner()).await
-    )
+    respond!(controller::start_auction(payload.0,
+                                       auction_service.inner(),
+                                       faas_service.inner(),
+                                       router_service.inner(),
+                                       billing_service.inner()).await)
+}
+
+/// Remove a provisioned function, releasing the resources on the node hosting it and tearing down
+/// the routes that were established
+#[openapi]
+#[delete("/function/<id>")]
+pub async fn delete_function(id: BidId,
+                             faas_service: &State<Arc<dyn crate::service::faas::FogNodeFaaS>>,
+                             router_service: &State<Arc<dyn crate::service::routing::Router>>,
+                             billing_service: &State<Arc<dyn crate::service::billing::Billing>>)
+                             -> Resp {
+    respond!(controller::remove_function(id,
+                                         faas_service.inner(),
+                                         router_service.inner(),
+                                         billing_service.inner()).await)
 }
 
 /// Register a new node in the network
 #[openapi]
 #[post("/register", data = "<payload>")]
 pub async fn post_register_node(payload: Json<RegisterNode>,
-                                node_net: &State<Arc<dyn crate::service::fog_node_network::FogNodeNetwork>>)
+                                node_net: &State<Arc<dyn crate::service::fog_node_network::FogNodeNetwork>>,
+                                faas_service: &State<Arc<dyn crate::service::faas::FogNodeFaaS>>,
+                                router_service: &State<Arc<dyn crate::service::routing::Router>>)
                                 -> Resp {
-    respond!(controller::register_node(payload.0, node_net.inner()).await)
+    respond!(controller::register_node(payload.0,
+                                       node_net.inner(),
+                                       faas_service.inner(),
+                                       router_service.inner()).await)
+}
+
+/// Unregister a node leaving the network, relocating the functions it hosts
+#[openapi]
+#[post("/unregister", data = "<payload>")]
+pub async fn post_unregister_node(payload: Json<UnregisterNode>,
+                                  node_net: &State<Arc<dyn crate::service::fog_node_network::FogNodeNetwork>>,
+                                  auction_service: &State<Arc<dyn crate::service::auction::Auction>>,
+                                  faas_service: &State<Arc<dyn crate::service::faas::FogNodeFaaS>>,
+                                  router_service: &State<Arc<dyn crate::service::routing::Router>>,
+                                  billing_service: &State<Arc<dyn crate::service::billing::Billing>>)
+                                  -> Resp {
+    respond!(controller::unregister_node(payload.0,
+                                         node_net.inner(),
+                                         auction_service.inner(),
+                                         faas_service.inner(),
+                                         router_service.inner(),
+                                         billing_service.inner()).await)
+}
+
+/// Update the liveness of a node, as detected by one of its neighbors
+#[openapi]
+#[post("/liveness", data = "<payload>")]
+pub async fn post_liveness(payload: Json<PostLiveness>,
+                           node_net: &State<Arc<dyn crate::service::fog_node_network::FogNodeNetwork>>)
+                           -> Resp {
+    respond!(controller::update_liveness(payload.0, node_net.inner()).await)
+}
+
+/// Record the periodic heartbeat of a node, answering whether it has to register again
+#[openapi]
+#[post("/heartbeat", data = "<payload>")]
+pub async fn post_heartbeat(payload: Json<PostHeartbeat>,
+                            node_net: &State<Arc<dyn crate::service::fog_node_network::FogNodeNetwork>>)
+                            -> Resp<HeartbeatAck> {
+    respond!(controller::heartbeat(payload.0, node_net.inner()).await)
 }
 
 /// Get all the successfull transactions (function provisioned) done by the market since its boot.
@@ -52,6 +116,56 @@ pub async fn get_fog(fog_node_network: &State<Arc<dyn crate::service::fog_node_n
     respond!(controller::get_fog(fog_node_network.inner()).await)
 }
 
+/// Get the tree of the nodes, annotated with the last state they reported
+#[openapi]
+#[get("/fog/topology")]
+pub async fn get_topology(fog_node_network: &State<Arc<dyn crate::service::fog_node_network::FogNodeNetwork>>)
+                          -> Resp<Topology> {
+    respond!(controller::get_topology(fog_node_network.inner()).await)
+}
+
+/// Get the tree of the nodes in the Graphviz DOT format
+#[openapi]
+#[get("/fog/topology/dot")]
+pub async fn get_topology_dot(fog_node_network: &State<Arc<dyn crate::service::fog_node_network::FogNodeNetwork>>)
+                              -> Result<RawText<String>, Error> {
+    Ok(RawText(controller::get_topology(fog_node_network.inner()).await?.to_dot()))
+}
+
+/// Get the tree of the nodes in the GraphML format
+#[openapi]
+#[get("/fog/topology/graphml")]
+pub async fn get_topology_graphml(fog_node_network: &State<Arc<dyn crate::service::fog_node_network::FogNodeNetwork>>)
+                                  -> Result<RawXml<String>, Error> {
+    Ok(RawXml(controller::get_topology(fog_node_network.inner()).await?.to_graphml()))
+}
+
+/// Get the balances of all the nodes and clients: what the nodes earned and what the clients owe
+#[openapi]
+#[get("/ledger/balances")]
+pub async fn get_balances(billing_service: &State<Arc<dyn crate::service::billing::Billing>>)
+                          -> Resp<Balances> {
+    respond!(controller::get_balances(billing_service.inner()).await)
+}
+
+/// Get what a node earned, along with its contracts not settled yet and their charges
+#[openapi]
+#[get("/ledger/node/<id>")]
+pub async fn get_node_statement(id: NodeId,
+                                billing_service: &State<Arc<dyn crate::service::billing::Billing>>)
+                                -> Resp<Statement> {
+    respond!(controller::get_node_statement(id, billing_service.inner()).await)
+}
+
+/// Get what a client owes, along with its contracts not settled yet and their charges
+#[openapi]
+#[get("/ledger/client/<client>")]
+pub async fn get_client_statement(client: String,
+                                  billing_service: &State<Arc<dyn crate::service::billing::Billing>>)
+                                  -> Resp<Statement> {
+    respond!(controller::get_client_statement(client, billing_service.inner()).await)
+}
+
 #[openapi]
 #[get("/health")]
 pub async fn health() {}
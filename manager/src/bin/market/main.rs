@@ -4,53 +4,273 @@ extern crate log;
 extern crate rocket;
 
 use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+use anyhow::Context;
 use rocket::launch;
 use rocket_okapi::openapi_get_routes;
 use rocket_okapi::swagger_ui::*;
+use uom::si::f64::Time;
+use uom::si::time::second;
+
+use manager::helper::snapshot::Snapshot;
+use manager::model::domain::auction::AuctionMechanism;
+use manager::model::domain::heartbeat::HeartbeatThresholds;
 
 use crate::handler::*;
+use crate::repository::auction::{Auction, FirstPriceAuction, KthPriceAuction, SecondPriceAuction,
+                                 SecondPriceRandomTieBreakAuction, SecondPriceReserveAuction};
 use crate::repository::fog_node::FogNodeImpl;
+use crate::repository::ledger::LedgerImpl;
+use crate::service::billing::Billing;
+use crate::service::faas::FogNodeFaaS;
+use crate::service::fog_node_network::FogNodeNetwork;
 
 mod controller;
 mod handler;
 mod repository;
 mod service;
 
+/// Load the AUCTION env variable, describing the [AuctionMechanism] in the RON format, e.g.,
+/// `SecondPriceReserve(reserve_price: 10.0)`. Defaults to the second price auction.
+fn load_auction_mechanism_from_env() -> anyhow::Result<AuctionMechanism> {
+    match env::var("AUCTION") {
+        Ok(mechanism) => Ok(ron::from_str::<AuctionMechanism>(&mechanism)?),
+        Err(env::VarError::NotPresent) => Ok(AuctionMechanism::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Period of the search for the nodes that stopped sending heartbeats
+const HEARTBEAT_CHECK_PERIOD: Duration = Duration::from_secs(5);
+
+/// Load the env variable [var], in whole seconds, or else the [default] duration
+fn load_seconds_from_env(var: &str, default: Duration) -> anyhow::Result<Duration> {
+    match env::var(var) {
+        Ok(seconds) => {
+            let seconds =
+                seconds.parse::<u64>().with_context(|| format!("Cannot parse {}", var))?;
+            Ok(Duration::from_secs(seconds))
+        }
+        Err(env::VarError::NotPresent) => Ok(default),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Load the thresholds from the HEARTBEAT_STALE_AFTER and HEARTBEAT_EVICT_AFTER env variables, in
+/// seconds. A node has to become stale before being evicted.
+fn load_heartbeat_thresholds_from_env() -> anyhow::Result<HeartbeatThresholds> {
+    let default = HeartbeatThresholds::default();
+    let stale_after = load_seconds_from_env("HEARTBEAT_STALE_AFTER", default.stale_after)?;
+    let evict_after = load_seconds_from_env("HEARTBEAT_EVICT_AFTER", default.evict_after)?;
+    anyhow::ensure!(evict_after > stale_after,
+                    "The nodes must be evicted ({:?}) after they become stale ({:?})",
+                    evict_after,
+                    stale_after);
+    Ok(HeartbeatThresholds { stale_after, evict_after })
+}
+
+/// Period of the settlement of the charges accrued by the contracts
+const SETTLEMENT_PERIOD: Duration = Duration::from_secs(10);
+
+/// Load the BILLING_PERIOD env variable, in seconds: the clearing price of a function is charged
+/// to its client every such period. Defaults to a minute.
+fn load_billing_period_from_env() -> anyhow::Result<Time> {
+    let seconds = match env::var("BILLING_PERIOD") {
+        Ok(seconds) => seconds.parse::<f64>()?,
+        Err(env::VarError::NotPresent) => 60.0,
+        Err(err) => return Err(err.into()),
+    };
+    anyhow::ensure!(seconds > 0.0, "The billing period must be positive");
+    Ok(Time::new::<second>(seconds))
+}
+
+/// The [Snapshot] named [name] in the directory given by the DATA_DIR env variable, if set, so
+/// that the market resumes with the same state after a restart. Otherwise, the state is only kept
+/// in memory.
+async fn snapshot(name: &str) -> anyhow::Result<Option<Snapshot>> {
+    match env::var("DATA_DIR") {
+        Ok(dir) => {
+            let dir = PathBuf::from(dir);
+            tokio::fs::create_dir_all(&dir).await?;
+            let path = dir.join(name);
+            info!("Persisting in {:?}", path);
+            Ok(Some(Snapshot::new(path)))
+        }
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Load the tree from its [snapshot], so that the market resumes with the same nodes and
+/// functions
+async fn fog_node_factory() -> anyhow::Result<FogNodeImpl> {
+    match snapshot("fog_nodes.json").await? {
+        Some(snapshot) => Ok(FogNodeImpl::load(snapshot).await?),
+        None => Ok(FogNodeImpl::new()),
+    }
+}
+
+/// Load the contracts and charges from their [snapshot]
+async fn ledger_factory() -> anyhow::Result<LedgerImpl> {
+    match snapshot("ledger.json").await? {
+        Some(snapshot) => Ok(LedgerImpl::load(snapshot).await?),
+        None => Ok(LedgerImpl::new()),
+    }
+}
+
+fn auction_factory(mechanism: AuctionMechanism) -> anyhow::Result<Arc<dyn Auction>> {
+    info!("Using the auction mechanism {:?}", mechanism);
+    Ok(match mechanism {
+        AuctionMechanism::FirstPrice => Arc::new(FirstPriceAuction::new()),
+        AuctionMechanism::SecondPrice => Arc::new(SecondPriceAuction::new()),
+        AuctionMechanism::SecondPriceReserve { reserve_price } => {
+            Arc::new(SecondPriceReserveAuction::new(reserve_price))
+        }
+        AuctionMechanism::KthPrice { k } => {
+            anyhow::ensure!(k > 0, "The k of the k-th price auction starts at 1");
+            Arc::new(KthPriceAuction::new(k))
+        }
+        AuctionMechanism::SecondPriceRandomTieBreak => {
+            Arc::new(SecondPriceRandomTieBreakAuction::new())
+        }
+    })
+}
+
 #[launch]
 async fn rocket() -> _ {
     std::env::set_var("RUST_LOG", "info, market=trace");
     env_logger::init();
 
-    let fog_node = Arc::new(FogNodeImpl::new());
+    let fog_node = Arc::new(fog_node_factory().await
+                                              .map_err(|err| {
+                                                  error!("Error loading the tree: {}", err);
+                                                  std::process::exit(1);
+                                              })
+                                              .unwrap());
+    let ledger = Arc::new(ledger_factory().await
+                                          .map_err(|err| {
+                                              error!("Error loading the ledger: {}", err);
+                                              std::process::exit(1);
+                                          })
+                                          .unwrap());
+    let billing_period =
+        load_billing_period_from_env().map_err(|err| {
+                                          error!("Error loading the billing period from the \
+                                                  BILLING_PERIOD env variable: {}",
+                                                 err);
+                                          std::process::exit(1);
+                                      })
+                                      .unwrap();
     let fog_node_communication =
         Arc::new(crate::repository::node_communication::NodeCommunicationThroughRoutingImpl::new(
             fog_node.clone(),
         ));
-    let auction_process = Arc::new(crate::repository::auction::SecondPriceAuction::new());
+    let auction_process = load_auction_mechanism_from_env().and_then(auction_factory)
+                                                           .map_err(|err| {
+                                                               error!("Error loading the auction \
+                                                                       mechanism from the \
+                                                                       AUCTION env variable: {}",
+                                                                      err);
+                                                               std::process::exit(1);
+                                                           })
+                                                           .unwrap();
 
     // Services
     let auction_service =
         Arc::new(service::auction::AuctionImpl::new(auction_process,
                                                     fog_node_communication.clone()));
+    let heartbeat_thresholds = load_heartbeat_thresholds_from_env().map_err(|err| {
+                                   error!("Error loading the heartbeat thresholds from the \
+                                           HEARTBEAT_STALE_AFTER and HEARTBEAT_EVICT_AFTER env \
+                                           variables: {}",
+                                          err);
+                                   std::process::exit(1);
+                               })
+                               .unwrap();
+    info!("Using the heartbeat thresholds {:?}", heartbeat_thresholds);
     let fog_node_network_service =
-        Arc::new(service::fog_node_network::FogNodeNetworkHashTreeImpl::new(fog_node.clone()));
+        Arc::new(service::fog_node_network::FogNodeNetworkHashTreeImpl::new(
+            fog_node.clone(),
+            fog_node_communication.clone(),
+            heartbeat_thresholds,
+        ));
     let faas_service =
-        Arc::new(service::faas::FogNodeFaaSImpl::new(fog_node, fog_node_communication));
+        Arc::new(service::faas::FogNodeFaaSImpl::new(fog_node.clone(),
+                                                     fog_node_communication.clone()));
+    let router_service =
+        Arc::new(service::routing::RouterImpl::new(fog_node, fog_node_communication));
+    info!("Charging the functions every {} s", billing_period.get::<second>());
+    let billing_service = Arc::new(service::billing::BillingImpl::new(ledger, billing_period));
+
+    for accepted in faas_service.get_functions().await.into_values().flatten() {
+        info!("Resuming the function {} on {}", accepted.function, accepted.chosen.bid.node_id);
+        controller::schedule_reevaluation(accepted.function,
+                                          accepted.sla.sla.reevaluation_period,
+                                          auction_service.clone(),
+                                          faas_service.clone(),
+                                          router_service.clone(),
+                                          billing_service.clone());
+    }
+
+    {
+        let fog_net = fog_node_network_service.clone() as Arc<dyn FogNodeNetwork>;
+        let auction_service = auction_service.clone() as Arc<dyn crate::service::auction::Auction>;
+        let faas_service = faas_service.clone() as Arc<dyn FogNodeFaaS>;
+        let router_service = router_service.clone() as Arc<dyn crate::service::routing::Router>;
+        let billing_service = billing_service.clone() as Arc<dyn Billing>;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_CHECK_PERIOD);
+            loop {
+                interval.tick().await;
+                controller::evict_stale_nodes(&fog_net,
+                                              &auction_service,
+                                              &faas_service,
+                                              &router_service,
+                                              &billing_service).await;
+            }
+        });
+    }
+
+    {
+        let billing_service = billing_service.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SETTLEMENT_PERIOD);
+            loop {
+                interval.tick().await;
+                if let Err(err) = billing_service.settle().await {
+                    error!("Failed to settle the contracts: {:?}", err);
+                }
+            }
+        });
+    }
 
     rocket::build().manage(auction_service as Arc<dyn crate::service::auction::Auction>)
                    .manage(fog_node_network_service
                            as Arc<dyn crate::service::fog_node_network::FogNodeNetwork>)
                    .manage(faas_service as Arc<dyn crate::service::faas::FogNodeFaaS>)
+                   .manage(router_service as Arc<dyn crate::service::routing::Router>)
+                   .manage(billing_service as Arc<dyn Billing>)
                    .mount("/",
                           make_swagger_ui(&SwaggerUIConfig { url:
                                                                  "/api/openapi.json".to_owned(),
                                                              ..Default::default() }))
                    .mount("/api/",
                           openapi_get_routes![put_function,
+                                              delete_function,
                                               post_register_node,
+                                              post_unregister_node,
+                                              post_liveness,
+                                              post_heartbeat,
                                               get_functions,
                                               get_fog,
+                                              get_topology,
+                                              get_topology_dot,
+                                              get_topology_graphml,
+                                              get_balances,
+                                              get_node_statement,
+                                              get_client_statement,
                                               health])
 }
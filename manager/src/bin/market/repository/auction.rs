@@ -1,8 +1,41 @@
+use rand::seq::SliceRandom;
+
+use manager::model::domain::auction::AuctionMechanism;
 use manager::model::dto::auction::ChosenBid;
 use manager::model::view::auction::BidProposal;
 
 pub trait Auction: Sync + Send {
     fn auction(&self, bids: &[BidProposal]) -> Option<ChosenBid>;
+
+    /// The mechanism implemented by the auction
+    fn mechanism(&self) -> AuctionMechanism;
+}
+
+/// Sort the bids in the ascending order, the first one being the lowest bid
+fn sort_bids(bids: &[BidProposal]) -> Vec<&BidProposal> {
+    let mut bids = bids.iter().collect::<Vec<_>>();
+    bids.sort_unstable_by(|a, b| a.bid.partial_cmp(&b.bid).unwrap()); // Sort asc
+    bids
+}
+
+/// The lowest bid wins and is paid the price of the k-th bid (1-indexed), or the last one if
+/// there are not enough bids
+fn kth_price(bids: &[&BidProposal], k: usize) -> Option<ChosenBid> {
+    let first = bids.first()?;
+    let kth = bids.get(k.max(1) - 1).or_else(|| bids.last())?;
+    Some(ChosenBid { price: kth.bid, bid: (*first).clone() })
+}
+
+pub struct FirstPriceAuction;
+
+impl FirstPriceAuction {
+    pub fn new() -> Self { Self {} }
+}
+
+impl Auction for FirstPriceAuction {
+    fn auction(&self, bids: &[BidProposal]) -> Option<ChosenBid> { kth_price(&sort_bids(bids), 1) }
+
+    fn mechanism(&self) -> AuctionMechanism { AuctionMechanism::FirstPrice }
 }
 
 pub struct SecondPriceAuction;
@@ -12,15 +45,135 @@ impl SecondPriceAuction {
 }
 
 impl Auction for SecondPriceAuction {
+    fn auction(&self, bids: &[BidProposal]) -> Option<ChosenBid> { kth_price(&sort_bids(bids), 2) }
+
+    fn mechanism(&self) -> AuctionMechanism { AuctionMechanism::SecondPrice }
+}
+
+pub struct SecondPriceReserveAuction {
+    reserve_price: f64,
+}
+
+impl SecondPriceReserveAuction {
+    pub fn new(reserve_price: f64) -> Self { Self { reserve_price } }
+}
+
+impl Auction for SecondPriceReserveAuction {
     fn auction(&self, bids: &[BidProposal]) -> Option<ChosenBid> {
-        let mut bids = bids.iter().collect::<Vec<_>>();
-        bids.sort_unstable_by(|a, b| a.bid.partial_cmp(&b.bid).unwrap()); // Sort asc
-        let first = bids.get(0).cloned().cloned();
-        let second = bids.get(1);
-        match (first, second) {
-            (Some(first), Some(second)) => Some(ChosenBid { price: second.bid, bid: first }),
-            (Some(first), None) => Some(ChosenBid { price: first.bid, bid: first }),
-            _ => None,
-        }
+        let bids = sort_bids(bids).into_iter()
+                                  .filter(|bid| bid.bid <= self.reserve_price)
+                                  .collect::<Vec<_>>();
+        let first = bids.first()?;
+        let price = bids.get(1).map(|second| second.bid).unwrap_or(self.reserve_price);
+        Some(ChosenBid { price, bid: (*first).clone() })
+    }
+
+    fn mechanism(&self) -> AuctionMechanism {
+        AuctionMechanism::SecondPriceReserve { reserve_price: self.reserve_price }
+    }
+}
+
+pub struct KthPriceAuction {
+    k: usize,
+}
+
+impl KthPriceAuction {
+    pub fn new(k: usize) -> Self { Self { k } }
+}
+
+impl Auction for KthPriceAuction {
+    fn auction(&self, bids: &[BidProposal]) -> Option<ChosenBid> {
+        kth_price(&sort_bids(bids), self.k)
+    }
+
+    fn mechanism(&self) -> AuctionMechanism { AuctionMechanism::KthPrice { k: self.k } }
+}
+
+pub struct SecondPriceRandomTieBreakAuction;
+
+impl SecondPriceRandomTieBreakAuction {
+    pub fn new() -> Self { Self {} }
+}
+
+impl Auction for SecondPriceRandomTieBreakAuction {
+    fn auction(&self, bids: &[BidProposal]) -> Option<ChosenBid> {
+        let bids = sort_bids(bids);
+        let lowest = bids.first()?.bid;
+        let tied = bids.iter().take_while(|bid| bid.bid == lowest).collect::<Vec<_>>();
+        let first = tied.choose(&mut rand::thread_rng())?;
+        let price = bids.get(1).map(|second| second.bid).unwrap_or(lowest);
+        Some(ChosenBid { price, bid: (**first).clone() })
+    }
+
+    fn mechanism(&self) -> AuctionMechanism { AuctionMechanism::SecondPriceRandomTieBreak }
+}
+
+#[cfg(test)]
+mod tests {
+    use manager::model::domain::pricing::Pricing;
+    use manager::model::{BidId, NodeId};
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn proposals(bids: &[f64]) -> Vec<BidProposal> {
+        bids.iter()
+            .map(|bid| BidProposal { node_id: NodeId::from(Uuid::new_v4()),
+                                     id:      BidId::from(Uuid::new_v4()),
+                                     bid:     *bid,
+                                     pricing: Pricing::default(), })
+            .collect()
+    }
+
+    #[test]
+    fn test_first_price() {
+        let chosen = FirstPriceAuction::new().auction(&proposals(&[3.0, 1.0, 2.0])).unwrap();
+        assert_eq!(chosen.bid.bid, 1.0);
+        assert_eq!(chosen.price, 1.0);
+    }
+
+    #[test]
+    fn test_second_price() {
+        let chosen = SecondPriceAuction::new().auction(&proposals(&[3.0, 1.0, 2.0])).unwrap();
+        assert_eq!(chosen.bid.bid, 1.0);
+        assert_eq!(chosen.price, 2.0);
+
+        let chosen = SecondPriceAuction::new().auction(&proposals(&[3.0])).unwrap();
+        assert_eq!(chosen.price, 3.0);
+
+        assert!(SecondPriceAuction::new().auction(&[]).is_none());
+    }
+
+    #[test]
+    fn test_second_price_reserve() {
+        let auction = SecondPriceReserveAuction::new(2.5);
+
+        let chosen = auction.auction(&proposals(&[3.0, 1.0, 2.0])).unwrap();
+        assert_eq!(chosen.bid.bid, 1.0);
+        assert_eq!(chosen.price, 2.0);
+
+        let chosen = auction.auction(&proposals(&[3.0, 1.0])).unwrap();
+        assert_eq!(chosen.price, 2.5);
+
+        assert!(auction.auction(&proposals(&[3.0])).is_none());
+    }
+
+    #[test]
+    fn test_kth_price() {
+        let chosen = KthPriceAuction::new(3).auction(&proposals(&[4.0, 1.0, 2.0, 3.0])).unwrap();
+        assert_eq!(chosen.bid.bid, 1.0);
+        assert_eq!(chosen.price, 3.0);
+
+        let chosen = KthPriceAuction::new(3).auction(&proposals(&[1.0, 2.0])).unwrap();
+        assert_eq!(chosen.price, 2.0);
+    }
+
+    #[test]
+    fn test_random_tie_break() {
+        let bids = proposals(&[1.0, 1.0, 2.0]);
+        let chosen = SecondPriceRandomTieBreakAuction::new().auction(&bids).unwrap();
+        assert_eq!(chosen.bid.bid, 1.0);
+        assert_eq!(chosen.price, 1.0);
+        assert!(bids[..2].iter().any(|bid| bid.id == chosen.bid.id));
     }
 }
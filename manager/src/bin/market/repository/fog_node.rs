@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::net::IpAddr;
 
 use async_trait::async_trait;
 use tokio::sync::RwLock;
 
+use manager::helper::snapshot::{Journal, JournaledMap, Snapshot};
+use manager::model::domain::tree_path;
 use manager::model::dto::node::{Node, NodeIdList, NodeRecord};
 use manager::model::view::auction::AcceptedBid;
+use manager::model::view::topology::Topology;
 use manager::model::NodeId;
 
 #[derive(Debug, thiserror::Error)]
@@ -19,195 +21,444 @@ pub enum Error {
     ChildDoesntExist(NodeId, NodeId),
     #[error("Multiple roots found: {0}")]
     MultipleRoots(NodeIdList),
+    #[error("Cannot find the node {0} in the tree")]
+    NodeDoesntExist(NodeId),
+    #[error("The node {0} is already in the tree")]
+    NodeAlreadyExists(NodeId),
+    #[error("Cannot move node {0} under its own descendant {1}")]
+    MoveUnderDescendant(NodeId, NodeId),
+    #[error("Cannot remove the root of the tree: {0}")]
+    CannotRemoveRoot(NodeId),
+    #[error("No route to the node {0} avoids the stale nodes")]
+    NoRoute(NodeId),
+    #[error(transparent)]
+    Path(#[from] tree_path::Error),
+    #[error(transparent)]
+    Snapshot(#[from] manager::helper::snapshot::Error),
 }
 
+/// Change to the record of a node, applied under the same lock as the change to the tree
+pub type RecordChange<'a> = Box<dyn for<'r> FnOnce(&'r mut NodeRecord) + Send + 'a>;
+
 #[async_trait]
 pub trait FogNode: Debug + Sync + Send {
     async fn get(&self, id: &NodeId) -> Option<Node<NodeRecord>>;
-    async fn update(&self, id: &NodeId, node: NodeRecord);
-    /// Append a new child to the current node, if fails, then doesn't append
-    async fn append_new_child(&self,
-                              parent: &NodeId,
-                              child: NodeId,
-                              tags: Vec<String>)
-                              -> Result<(), Error>;
+    /// Change the record of the node in place, so that concurrent changes to the same node are
+    /// not lost
+    async fn modify<'a>(&self, id: &NodeId, change: RecordChange<'a>) -> Result<(), Error>;
+    /// Append a new child to the current node, its record being filled by [register]; if fails,
+    /// then doesn't append
+    async fn append_new_child<'a>(&self,
+                                  parent: &NodeId,
+                                  child: NodeId,
+                                  register: RecordChange<'a>)
+                                  -> Result<(), Error>;
     /// Append the root of the tree, i.e., will fail if not the first node in the whole tree, and
-    /// will fail thereafter
-    async fn append_root(&self,
-                         root: NodeId,
-                         ip: IpAddr,
-                         port: u16,
-                         tags: Vec<String>)
-                         -> Result<(), Error>;
-    /// Get all the [NodeId] up to the target node (included).
-    /// Return the stack, meaning the destination is at the bottom and the next node is at the top.
-    async fn get_route_to_node(&self, to: NodeId) -> Vec<NodeId>;
+    /// will fail thereafter. The root registering again keeps its subtree, its record being
+    /// updated by [register] as well.
+    async fn append_root<'a>(&self, root: NodeId, register: RecordChange<'a>) -> Result<(), Error>;
+    /// Move a node, along with its whole subtree, under another parent, its record being updated
+    /// by [register]; if fails, then doesn't move. Return the former parent.
+    async fn move_subtree<'a>(&self,
+                              node: &NodeId,
+                              new_parent: &NodeId,
+                              register: RecordChange<'a>)
+                              -> Result<NodeId, Error>;
+    /// Get the node and all its descendants
+    async fn get_subtree(&self, node: &NodeId) -> Vec<NodeId>;
+    /// Remove a node, its children being attached to its parent; if fails, then doesn't remove
+    async fn remove(&self, node: &NodeId) -> Result<(), Error>;
+    /// Get the route to the target node (included), entering the tree through the target itself
+    /// if its address is known, or else through its closest ancestor whose address is known.
+    /// The route cannot go through a stale node.
+    /// Return the stack, meaning the destination is at the bottom and the entry node is at the
+    /// top.
+    async fn get_route_to_node(&self, to: &NodeId) -> Result<Vec<NodeId>, Error>;
+    /// Get the path between two nodes (included), through their lowest common ancestor
+    async fn get_path(&self, from: &NodeId, to: &NodeId) -> Result<Vec<NodeId>, Error>;
 
     async fn get_records(&self) -> HashMap<NodeId, Vec<AcceptedBid>>;
 
     /// Get all the connected nodes
     async fn get_nodes(&self) -> Vec<(NodeId, NodeRecord)>;
+
+    /// Get the whole tree, from its root
+    async fn get_topology(&self) -> Result<Topology, Error>;
+}
+
+/// The nodes of the tree, along with its root so that the invariants can be checked without
+/// walking the whole tree
+#[derive(Debug, Default)]
+struct Tree {
+    root:  Option<NodeId>,
+    nodes: JournaledMap<NodeId, Node<NodeRecord>>,
 }
 
 #[derive(Debug)]
 pub struct FogNodeImpl {
-    nodes: RwLock<HashMap<NodeId, Node<NodeRecord>>>,
+    tree:    RwLock<Tree>,
+    /// Where the changed nodes are saved, so that a registration does not save the whole tree
+    journal: Option<Journal>,
 }
 
 impl FogNodeImpl {
-    pub fn new() -> Self { FogNodeImpl { nodes: RwLock::new(HashMap::new()) } }
-
-    async fn check_tree(&self) -> Result<(), Error> {
-        let mut roots = self.nodes
-                            .read()
-                            .await
-                            .iter()
-                            .filter(|(_id, node)| node.parent.is_none())
-                            .map(|(id, _node)| id.clone())
-                            .collect::<Vec<_>>();
-
-        if roots.is_empty() {
-            return Err(Error::NoRoot);
+    pub fn new() -> Self { FogNodeImpl { tree: RwLock::new(Tree::default()), journal: None } }
+
+    pub async fn load(snapshot: Snapshot) -> Result<Self, Error> {
+        let journal = Journal::new(snapshot);
+        let nodes = journal.load().await?;
+        let root = if nodes.is_empty() { None } else { Some(check_tree(&nodes)?) };
+        Ok(FogNodeImpl { tree: RwLock::new(Tree { root, nodes }), journal: Some(journal) })
+    }
+
+    /// Apply the change to the tree under a single write lock, then save the changed nodes
+    async fn change<R>(&self,
+                       change: impl FnOnce(&mut Tree) -> Result<R, Error> + Send)
+                       -> Result<R, Error> {
+        let mut tree = self.tree.write().await;
+        let result = change(&mut tree)?;
+        if let Some(journal) = &self.journal {
+            journal.save(&mut tree.nodes).await?;
         }
+        Ok(result)
+    }
 
-        if roots.len() > 1 {
-            return Err(Error::MultipleRoots(roots.into()));
+    fn print_tree(tree: &Tree) {
+        // Serializing the whole tree is costly, only do it when it is actually printed
+        if log_enabled!(log::Level::Trace) {
+            trace!("{}", serde_json::to_string_pretty(&*tree.nodes).unwrap());
         }
+    }
+}
 
-        let root = roots.pop().unwrap();
-        let mut stack = vec![root];
-        while let Some(id) = stack.pop() {
-            let node_children =
-                self.nodes.read().await.get(&id).map(|node| node.children.clone()).unwrap();
-            for child in node_children.iter() {
-                if !self.nodes.read().await.contains_key(child) {
-                    return Err(Error::ChildDoesntExist(id.clone(), child.clone()));
-                }
-            }
+/// Check the whole tree, i.e., a single root from which all the parent/children links are
+/// consistent. Return the root.
+fn check_tree(nodes: &HashMap<NodeId, Node<NodeRecord>>) -> Result<NodeId, Error> {
+    let mut roots = nodes.iter()
+                         .filter(|(_id, node)| node.parent.is_none())
+                         .map(|(id, _node)| id.clone())
+                         .collect::<Vec<_>>();
 
-            for child in node_children.iter() {
-                if let Some(parent) = &self.nodes.read().await.get(child).unwrap().parent {
-                    if *parent != id {
-                        return Err(Error::ParentDoesntExist(id.clone(), parent.clone()));
-                    }
+    if roots.is_empty() {
+        return Err(Error::NoRoot);
+    }
+
+    if roots.len() > 1 {
+        return Err(Error::MultipleRoots(roots.into()));
+    }
+
+    let root = roots.pop().unwrap();
+    let mut stack = vec![root.clone()];
+    while let Some(id) = stack.pop() {
+        for child in nodes[&id].children.iter() {
+            let node = nodes.get(child)
+                            .ok_or_else(|| Error::ChildDoesntExist(id.clone(), child.clone()))?;
+            if let Some(parent) = &node.parent {
+                if *parent != id {
+                    return Err(Error::ParentDoesntExist(id.clone(), parent.clone()));
                 }
             }
-
-            stack.extend(node_children.iter().cloned());
         }
-
-        Ok(())
+        stack.extend(nodes[&id].children.iter().cloned());
     }
 
-    async fn print_tree(&self) {
-        let to_print = serde_json::to_string_pretty(&*self.nodes.read().await).unwrap();
-        trace!("{}", to_print);
-    }
+    Ok(root)
 }
 
 #[async_trait]
 impl FogNode for FogNodeImpl {
     async fn get(&self, id: &NodeId) -> Option<Node<NodeRecord>> {
-        return self.nodes.read().await.get(id).cloned();
+        return self.tree.read().await.nodes.get(id).cloned();
     }
 
-    async fn update(&self, id: &NodeId, record: NodeRecord) {
-        if let Some(node) = self.nodes.write().await.get_mut(id) {
-            node.data = record;
-        }
+    async fn modify<'a>(&self, id: &NodeId, change: RecordChange<'a>) -> Result<(), Error> {
+        self.change(|tree| {
+                let node =
+                    tree.nodes.get_mut(id).ok_or_else(|| Error::NodeDoesntExist(id.clone()))?;
+                change(&mut node.data);
+                Ok(())
+            })
+            .await
     }
 
-    async fn append_new_child(&self,
-                              parent: &NodeId,
-                              child: NodeId,
-                              tags: Vec<String>)
-                              -> Result<(), Error> {
-        self.nodes
-            .write()
+    async fn append_new_child<'a>(&self,
+                                  parent: &NodeId,
+                                  child: NodeId,
+                                  register: RecordChange<'a>)
+                                  -> Result<(), Error> {
+        self.change(|tree| {
+                if tree.nodes.contains_key(&child) {
+                    return Err(Error::NodeAlreadyExists(child));
+                }
+                tree.nodes
+                    .get_mut(parent)
+                    .ok_or_else(|| Error::ParentDoesntExist(child.clone(), parent.clone()))?
+                    .children
+                    .push(child.clone());
+                let mut data = NodeRecord::default();
+                register(&mut data);
+                tree.nodes
+                    .insert(child, Node { parent: Some(parent.clone()), children: vec![], data });
+                Self::print_tree(tree);
+                Ok(())
+            })
             .await
-            .get_mut(parent)
-            .ok_or_else(|| Error::ParentDoesntExist(parent.clone(), child.clone()))?
-            .children
-            .push(child.clone());
-        self.nodes.write().await.insert(child.clone(),
-                                        Node { parent:   Some(parent.clone()),
-                                               children: vec![],
-                                               data:     NodeRecord { tags,
-                                                                      ..NodeRecord::default() }, });
-        let result = self.check_tree().await;
-        if result.is_err() {
-            let pos = self.nodes
-                          .read()
-                          .await
-                          .get(parent)
-                          .ok_or_else(|| Error::ParentDoesntExist(parent.clone(), child.clone()))?
-                          .children
-                          .iter()
-                          .rev()
-                          .position(|node| node == &child)
-                          .ok_or_else(|| Error::ChildDoesntExist(parent.clone(), child.clone()))?;
-            self.nodes
-                .write()
-                .await
-                .get_mut(parent)
-                .ok_or_else(|| Error::ParentDoesntExist(parent.clone(), child.clone()))?
-                .children
-                .remove(pos);
-            self.nodes.write().await.remove(&child);
+    }
 
-            return result;
-        }
-        self.print_tree().await;
-        Ok(())
-    }
-
-    async fn append_root(&self,
-                         root: NodeId,
-                         ip: IpAddr,
-                         port: u16,
-                         tags: Vec<String>)
-                         -> Result<(), Error> {
-        self.nodes.write().await.insert(root.clone(),
-                                        Node { parent:   None,
-                                               children: vec![],
-                                               data:     NodeRecord { ip: Some(ip),
-                                                                      port: Some(port),
-                                                                      tags,
-                                                                      ..NodeRecord::default() }, });
-        let res = self.check_tree().await;
-        if res.is_err() {
-            self.nodes.write().await.remove(&root);
+    async fn append_root<'a>(&self, root: NodeId, register: RecordChange<'a>) -> Result<(), Error> {
+        self.change(|tree| {
+                match &tree.root {
+                    // The market node registers again: keep its subtree
+                    Some(existing) if *existing == root => {
+                        register(&mut tree.nodes.get_mut(&root).unwrap().data);
+                    }
+                    Some(existing) => {
+                        return Err(Error::MultipleRoots(vec![existing.clone(), root].into()));
+                    }
+                    None => {
+                        let mut data = NodeRecord::default();
+                        register(&mut data);
+                        tree.nodes
+                            .insert(root.clone(), Node { parent: None, children: vec![], data });
+                        tree.root = Some(root);
+                    }
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    async fn move_subtree<'a>(&self,
+                              node: &NodeId,
+                              new_parent: &NodeId,
+                              register: RecordChange<'a>)
+                              -> Result<NodeId, Error> {
+        self.change(|tree| {
+                let former_parent = tree.nodes
+                                        .get(node)
+                                        .ok_or_else(|| Error::NodeDoesntExist(node.clone()))?
+                                        .parent
+                                        .clone()
+                                        .ok_or(Error::NoRoot)?;
+                if !tree.nodes.contains_key(new_parent) {
+                    return Err(Error::ParentDoesntExist(node.clone(), new_parent.clone()));
+                }
+                // The node is an ancestor of the new parent iff the new parent is in its subtree
+                if tree_path::ancestors(&tree.nodes, new_parent)?.contains(node) {
+                    return Err(Error::MoveUnderDescendant(node.clone(), new_parent.clone()));
+                }
+
+                if let Some(former) = tree.nodes.get_mut(&former_parent) {
+                    former.children.retain(|child| child != node);
+                }
+                tree.nodes.get_mut(new_parent).unwrap().children.push(node.clone());
+                let moved = tree.nodes.get_mut(node).unwrap();
+                moved.parent = Some(new_parent.clone());
+                register(&mut moved.data);
+
+                Self::print_tree(tree);
+                Ok(former_parent)
+            })
+            .await
+    }
+
+    async fn get_subtree(&self, node: &NodeId) -> Vec<NodeId> {
+        let tree = self.tree.read().await;
+        let mut subtree = vec![];
+        let mut stack = vec![node.clone()];
+        while let Some(id) = stack.pop() {
+            if let Some(current) = tree.nodes.get(&id) {
+                stack.extend(current.children.iter().cloned());
+                subtree.push(id);
+            }
         }
-        res
+        subtree
     }
 
-    async fn get_route_to_node(&self, to: NodeId) -> Vec<NodeId> {
-        let mut current_cursor = Some(to);
-        let mut route_stack = vec![]; // bottom: dest, top: next
-        while let Some(current) = &current_cursor {
-            if let Some(node) = self.get(current).await {
-                route_stack.push(current.clone());
-                current_cursor = node.parent;
+    async fn remove(&self, node: &NodeId) -> Result<(), Error> {
+        self.change(|tree| {
+                let parent = tree.nodes
+                                 .get(node)
+                                 .ok_or_else(|| Error::NodeDoesntExist(node.clone()))?
+                                 .parent
+                                 .clone()
+                                 .ok_or_else(|| Error::CannotRemoveRoot(node.clone()))?;
+
+                let removed = tree.nodes.remove(node).unwrap();
+                for child in removed.children.iter() {
+                    if let Some(child) = tree.nodes.get_mut(child) {
+                        child.parent = Some(parent.clone());
+                    }
+                }
+                if let Some(parent) = tree.nodes.get_mut(&parent) {
+                    parent.children.retain(|child| child != node);
+                    parent.children.extend(removed.children);
+                }
+
+                Self::print_tree(tree);
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_route_to_node(&self, to: &NodeId) -> Result<Vec<NodeId>, Error> {
+        let nodes = &self.tree.read().await.nodes;
+        let mut route_stack = vec![]; // bottom: dest, top: entry
+        for id in tree_path::ancestors(nodes, to)? {
+            let record = &nodes[&id].data;
+            if record.stale {
+                trace!("the route to {} goes through the stale node {}", to, id);
+                break;
+            }
+            route_stack.push(id);
+            if record.ip.is_some() && record.port.is_some() {
+                return Ok(route_stack);
             }
         }
+        Err(Error::NoRoute(to.clone()))
+    }
 
-        route_stack
+    async fn get_path(&self, from: &NodeId, to: &NodeId) -> Result<Vec<NodeId>, Error> {
+        Ok(tree_path::path(&self.tree.read().await.nodes, from, to)?)
     }
 
     async fn get_records(&self) -> HashMap<NodeId, Vec<AcceptedBid>> {
         let mut records: HashMap<NodeId, Vec<AcceptedBid>> = HashMap::new();
-        for (node, data) in &*self.nodes.read().await {
+        for (node, data) in self.tree.read().await.nodes.iter() {
             records.insert(node.clone(), data.data.accepted_bids.values().cloned().collect());
         }
         records
     }
 
     async fn get_nodes(&self) -> Vec<(NodeId, NodeRecord)> {
-        return self.nodes
+        return self.tree
                    .read()
                    .await
+                   .nodes
                    .iter()
                    .map(|(id, record)| (id.clone(), record.data.clone()))
                    .collect();
     }
+
+    async fn get_topology(&self) -> Result<Topology, Error> {
+        let tree = self.tree.read().await;
+        let root = tree.root.as_ref().ok_or(Error::NoRoot)?;
+        Topology::from_tree(&tree.nodes, root).ok_or(Error::NoRoot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use manager::model::dto::node::tree_of;
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// Give the node the address of the localhost, on the [port]
+    fn at(port: u16) -> RecordChange<'static> {
+        Box::new(move |record| {
+            record.ip = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+            record.port = Some(port);
+        })
+    }
+
+    fn unchanged() -> RecordChange<'static> { Box::new(|_| ()) }
+
+    /// A repository holding the nodes, without snapshot
+    fn over(nodes: HashMap<NodeId, Node<NodeRecord>>) -> FogNodeImpl {
+        let root = Some(check_tree(&nodes).unwrap());
+        FogNodeImpl { tree: RwLock::new(Tree { root, nodes: nodes.into() }), journal: None }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restores_the_tree() {
+        let path = std::env::temp_dir().join(format!("fog_nodes_{}.json", Uuid::new_v4()));
+        let root = NodeId::from(Uuid::new_v4());
+        let child = NodeId::from(Uuid::new_v4());
+
+        let fog_node = FogNodeImpl::load(Snapshot::new(path.clone())).await.unwrap();
+        fog_node.append_root(root.clone(), at(3000)).await.unwrap();
+        fog_node.append_new_child(&root,
+                                  child.clone(),
+                                  Box::new(|record| record.tags = vec!["edge".to_string()]))
+                .await
+                .unwrap();
+        // Only the child changes, and is appended to the journal
+        fog_node.modify(&child, at(3001)).await.unwrap();
+
+        let restored = FogNodeImpl::load(Snapshot::new(path.clone())).await.unwrap();
+        let restored_child = restored.get(&child).await.unwrap();
+        assert_eq!(restored_child.parent, Some(root.clone()));
+        assert_eq!(restored_child.data.tags, vec!["edge".to_string()]);
+        assert_eq!(restored_child.data.port, Some(3001));
+        assert_eq!(restored.get(&root).await.unwrap().children, vec![child]);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+        tokio::fs::remove_file(path.with_extension("journal")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mutations_keep_the_tree_consistent() {
+        let [root, a, b, a1] = std::array::from_fn(|_| NodeId::from(Uuid::new_v4()));
+        let fog_node = FogNodeImpl::new();
+
+        fog_node.append_root(root.clone(), at(3000)).await.unwrap();
+        assert!(matches!(fog_node.append_root(a.clone(), at(3001)).await,
+                         Err(Error::MultipleRoots(_))));
+        fog_node.append_new_child(&root, a.clone(), unchanged()).await.unwrap();
+        fog_node.append_new_child(&root, b.clone(), unchanged()).await.unwrap();
+        fog_node.append_new_child(&a, a1.clone(), unchanged()).await.unwrap();
+        assert!(matches!(fog_node.append_new_child(&b, a1.clone(), unchanged()).await,
+                         Err(Error::NodeAlreadyExists(_))));
+
+        assert!(matches!(fog_node.move_subtree(&a, &a1, unchanged()).await,
+                         Err(Error::MoveUnderDescendant(..))));
+        assert_eq!(fog_node.move_subtree(&a, &b, unchanged()).await.unwrap(), root);
+        fog_node.remove(&b).await.unwrap();
+        assert!(matches!(fog_node.remove(&root).await, Err(Error::CannotRemoveRoot(_))));
+
+        let tree = fog_node.tree.read().await;
+        assert_eq!(check_tree(&tree.nodes).unwrap(), root);
+        assert_eq!(tree.nodes[&root].children, vec![a.clone()]);
+        assert_eq!(tree.nodes[&a1].parent, Some(a));
+    }
+
+    #[tokio::test]
+    async fn test_route_enters_at_the_closest_address_without_stale_nodes() {
+        // root -> a -> a1
+        let (nodes, [root, a, a1]) = tree_of([None, Some(0), Some(1)]);
+        let fog_node = over(nodes);
+        fog_node.modify(&root, at(3000)).await.unwrap();
+
+        assert_eq!(fog_node.get_route_to_node(&a1).await.unwrap(),
+                   vec![a1.clone(), a.clone(), root.clone()]);
+
+        fog_node.modify(&a, Box::new(|record| record.stale = true)).await.unwrap();
+        assert!(matches!(fog_node.get_route_to_node(&a1).await, Err(Error::NoRoute(_))));
+        fog_node.modify(&a1, at(3001)).await.unwrap();
+        assert_eq!(fog_node.get_route_to_node(&a1).await.unwrap(), vec![a1.clone()]);
+
+        fog_node.modify(&a1, Box::new(|record| record.stale = true)).await.unwrap();
+        assert!(matches!(fog_node.get_route_to_node(&a1).await, Err(Error::NoRoute(_))));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_changes_to_a_record_are_kept() {
+        let root = NodeId::from(Uuid::new_v4());
+        let fog_node = FogNodeImpl::new();
+        fog_node.append_root(root.clone(), at(3000)).await.unwrap();
+
+        let (tags, stale) =
+            tokio::join!(fog_node.modify(&root, Box::new(|record| record.tags = vec!["a".into()])),
+                         fog_node.modify(&root, Box::new(|record| record.stale = true)));
+        tags.unwrap();
+        stale.unwrap();
+        let record = fog_node.get(&root).await.unwrap().data;
+        assert_eq!(record.tags, vec!["a".to_string()]);
+        assert!(record.stale);
+        assert!(matches!(fog_node.modify(&NodeId::from(Uuid::new_v4()), Box::new(|_| ())).await,
+                         Err(Error::NodeDoesntExist(_))));
+    }
 }
@@ -0,0 +1,173 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use manager::helper::snapshot::{Persisted, Snapshot};
+use manager::model::domain::ledger::{Charge, Contract};
+use manager::model::view::ledger::Balances;
+use manager::model::BidId;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("A contract already exists for the bid {0}")]
+    ContractAlreadyExists(BidId),
+    #[error("No open contract corresponds to the bid {0}")]
+    ContractNotFound(BidId),
+    #[error(transparent)]
+    Snapshot(#[from] manager::helper::snapshot::Error),
+}
+
+#[async_trait]
+pub trait Ledger: Debug + Sync + Send {
+    async fn open(&self, contract: Contract) -> Result<(), Error>;
+    /// End the contract at [at]; it is kept until it is settled
+    async fn close(&self, id: &BidId, at: DateTime<Utc>) -> Result<(), Error>;
+    /// Record the charges accrued by the contracts up to [now]. The contracts charged up to their
+    /// end are settled: they are left out, their charges being added to the settled balances.
+    async fn settle(&self, now: DateTime<Utc>) -> Result<(), Error>;
+    /// The contracts not settled yet
+    async fn get_contracts(&self) -> Vec<Contract>;
+    /// What the contracts not settled yet were charged so far, one charge per contract
+    async fn get_charges(&self) -> Vec<Charge>;
+    /// The balances of the accounts over the settled contracts
+    async fn get_settled(&self) -> Balances;
+}
+
+/// Only the contracts not settled yet are detailed, so that the book does not grow with time
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Book {
+    contracts: HashMap<BidId, Contract>,
+    /// The charges of the contracts, merged over their billing periods
+    charges:   HashMap<BidId, Charge>,
+    settled:   Balances,
+}
+
+#[derive(Debug)]
+pub struct LedgerImpl {
+    book: Persisted<Book>,
+}
+
+impl LedgerImpl {
+    pub fn new() -> Self { LedgerImpl { book: Persisted::new(Book::default()) } }
+
+    pub async fn load(snapshot: Snapshot) -> Result<Self, Error> {
+        Ok(LedgerImpl { book: Persisted::load(snapshot).await? })
+    }
+}
+
+#[async_trait]
+impl Ledger for LedgerImpl {
+    async fn open(&self, contract: Contract) -> Result<(), Error> {
+        self.book
+            .try_write(|book| {
+                if book.contracts.contains_key(&contract.id) {
+                    return Err(Error::ContractAlreadyExists(contract.id));
+                }
+                book.contracts.insert(contract.id.clone(), contract);
+                Ok(())
+            })
+            .await
+    }
+
+    async fn close(&self, id: &BidId, at: DateTime<Utc>) -> Result<(), Error> {
+        self.book
+            .try_write(|book| {
+                let contract = book.contracts
+                                   .get_mut(id)
+                                   .filter(|contract| contract.ended_at.is_none())
+                                   .ok_or_else(|| Error::ContractNotFound(id.clone()))?;
+                contract.ended_at = Some(at);
+                Ok(())
+            })
+            .await
+    }
+
+    async fn settle(&self, now: DateTime<Utc>) -> Result<(), Error> {
+        self.book
+            .write(|Book { contracts, charges, settled }| {
+                for charge in contracts.values_mut().filter_map(|contract| contract.accrue(now)) {
+                    match charges.entry(charge.contract.clone()) {
+                        Entry::Occupied(mut merged) => {
+                            let merged = merged.get_mut();
+                            merged.to = charge.to;
+                            merged.amount += charge.amount;
+                        }
+                        Entry::Vacant(entry) => {
+                            entry.insert(charge);
+                        }
+                    }
+                }
+                contracts.retain(|id, contract| {
+                             if !contract.is_settled() {
+                                 return true;
+                             }
+                             if let Some(charge) = charges.remove(id) {
+                                 settled.record(&charge);
+                             }
+                             false
+                         });
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn get_contracts(&self) -> Vec<Contract> {
+        self.book.read().await.contracts.values().cloned().collect()
+    }
+
+    async fn get_charges(&self) -> Vec<Charge> {
+        self.book.read().await.charges.values().cloned().collect()
+    }
+
+    async fn get_settled(&self) -> Balances { self.book.read().await.settled.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use manager::model::NodeId;
+    use uom::si::f64::Time;
+    use uom::si::time::second;
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_closed_contracts_are_settled_once() {
+        let ledger = LedgerImpl::new();
+        let start = Utc::now();
+        let id = BidId::from(Uuid::new_v4());
+        let provider = NodeId::from(Uuid::new_v4());
+        let contract = Contract::new(id.clone(),
+                                     "client".to_string(),
+                                     provider.clone(),
+                                     3.0,
+                                     Time::new::<second>(10.0),
+                                     start);
+
+        ledger.open(contract.clone()).await.unwrap();
+        assert!(matches!(ledger.open(contract).await, Err(Error::ContractAlreadyExists(_))));
+
+        ledger.settle(start + chrono::Duration::seconds(10)).await.unwrap();
+        ledger.settle(start + chrono::Duration::seconds(20)).await.unwrap();
+        let charges = ledger.get_charges().await;
+        assert_eq!(charges.len(), 1);
+        assert_eq!((charges[0].to, charges[0].amount),
+                   (start + chrono::Duration::seconds(20), 6.0));
+
+        ledger.close(&id, start + chrono::Duration::seconds(25)).await.unwrap();
+        assert!(matches!(ledger.close(&id, start).await, Err(Error::ContractNotFound(_))));
+        ledger.settle(start + chrono::Duration::seconds(30)).await.unwrap();
+        ledger.settle(start + chrono::Duration::seconds(40)).await.unwrap();
+
+        // Settled, only its balance is left
+        assert!(ledger.get_contracts().await.is_empty());
+        assert!(ledger.get_charges().await.is_empty());
+        let settled = ledger.get_settled().await;
+        assert_eq!(settled.nodes[&provider], 7.5);
+        assert_eq!(settled.clients["client"], 7.5);
+    }
+}
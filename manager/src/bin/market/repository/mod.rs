@@ -1,3 +1,4 @@
 pub(crate) mod auction;
 pub(crate) mod fog_node;
+pub(crate) mod ledger;
 pub(crate) mod node_communication;
@@ -7,11 +7,12 @@ use bytes::Bytes;
 use uom::si::f64::Time;
 use uom::si::time::second;
 
-use manager::model::domain::routing::Packet;
+use manager::model::domain::routing::{FunctionRoutingStack, Packet};
 use manager::model::domain::sla::Sla;
 use manager::model::dto::node::NodeRecord;
-use manager::model::view::auction::{BidProposal, BidProposals, BidRequest};
-use manager::model::NodeId;
+use manager::model::view::auction::{BidProposal, BidProposals, BidRequest, TakeOffer};
+use manager::model::view::node::NeighborNode;
+use manager::model::{BidId, NodeId};
 
 use crate::repository::fog_node::FogNode;
 
@@ -31,12 +32,39 @@ pub enum Error {
     NodeIdNotFound(NodeId),
     #[error("The fog node with the id {0} doesn't have a valid ip address and/or port.")]
     NodeIpNotFound(NodeId),
+    #[error(transparent)]
+    FogNode(#[from] crate::repository::fog_node::Error),
 }
 #[async_trait]
 pub trait NodeCommunication: Debug + Sync + Send {
     async fn request_bids_from_node(&self, to: NodeId, sla: Sla) -> Result<BidProposals, Error>;
 
-    async fn take_offer(&self, to: NodeId, bid: &BidProposal) -> Result<(), Error>;
+    /// Accept the bid, the node provisioning its function under the identity [function].
+    async fn take_offer(&self,
+                        to: NodeId,
+                        bid: &BidProposal,
+                        function: &BidId)
+                        -> Result<(), Error>;
+
+    /// Drop the bid that won but is not needed, releasing its reservation on the node.
+    async fn cancel_bid(&self, to: NodeId, bid: &BidProposal) -> Result<(), Error>;
+
+    /// Send the stack to the first node of [FunctionRoutingStack::route_to_first], that will
+    /// register the route along the way.
+    async fn establish_route(&self, stack: FunctionRoutingStack) -> Result<(), Error>;
+
+    /// Send the stack to the first node of [FunctionRoutingStack::route_to_first], that will
+    /// remove the route along the way.
+    async fn remove_route(&self, stack: FunctionRoutingStack) -> Result<(), Error>;
+
+    /// Ask the node to remove the function provisioned under the identity [id].
+    async fn remove_function(&self, to: NodeId, id: &BidId) -> Result<(), Error>;
+
+    /// Tell the node that its parent left the network, replaced by [parent].
+    async fn change_parent(&self, to: NodeId, parent: &NodeId) -> Result<(), Error>;
+
+    /// Tell the node that one of its children left the network, replaced by [child].
+    async fn adopt_child(&self, to: NodeId, child: &NodeId) -> Result<(), Error>;
 }
 
 #[derive(Debug)]
@@ -50,19 +78,35 @@ impl NodeCommunicationThroughRoutingImpl {
     async fn get_address_of_first_node(&self,
                                        route_stack: &[NodeId])
                                        -> Result<(IpAddr, u16), Error> {
-        let node = route_stack.first().ok_or(Error::EmptyRoutingStack)?;
+        // The first node to contact is at the top of the stack
+        self.get_address(route_stack.last().ok_or(Error::EmptyRoutingStack)?).await
+    }
+
+    async fn get_address(&self, node: &NodeId) -> Result<(IpAddr, u16), Error> {
         let NodeRecord { ip, port, .. } =
-            self.network
-                .get(route_stack.last().ok_or(Error::EmptyRoutingStack)?)
-                .await
-                .ok_or_else(|| Error::NodeIdNotFound(node.clone()))?
-                .data;
+            self.network.get(node).await.ok_or_else(|| Error::NodeIdNotFound(node.clone()))?.data;
 
         let ip = ip.ok_or_else(|| Error::NodeIpNotFound(node.clone()))?;
         let port = port.ok_or_else(|| Error::NodeIpNotFound(node.clone()))?;
         Ok((ip, port))
     }
 
+    /// Post the [neighbor] to the [resource_uri] of the node [to]
+    async fn send_neighbor(&self,
+                           to: NodeId,
+                           resource_uri: &str,
+                           neighbor: &NodeId)
+                           -> Result<(), Error> {
+        let (ip, port) = self.get_address(neighbor).await?;
+        let neighbor = NeighborNode { node_id: neighbor.clone(), ip, port };
+        let data = Packet::FogNode { route_to_stack: self.network.get_route_to_node(&to).await?,
+                                     resource_uri:   resource_uri.to_string(),
+                                     data:           &serde_json::value::to_raw_value(&neighbor)?, };
+
+        self.call_routing(data).await?;
+        Ok(())
+    }
+
     async fn call_routing(&self, packet: Packet<'_>) -> Result<Bytes, Error> {
         let (ip, port) = match &packet {
             Packet::FogNode { route_to_stack, .. } => {
@@ -81,6 +125,23 @@ impl NodeCommunicationThroughRoutingImpl {
             Err(Error::ErrorStatus(response.status(), response.text().await.ok()))
         }
     }
+
+    async fn send_route(&self,
+                        method: reqwest::Method,
+                        stack: &FunctionRoutingStack)
+                        -> Result<(), Error> {
+        let (ip, port) = self.get_address_of_first_node(&stack.route_to_first).await?;
+        let client = reqwest::Client::new();
+        let url = format!("http://{}:{}/api/routing", ip, port);
+        trace!("Sending route ({}) to {}", method, &url);
+        let response = client.request(method, &url).json(stack).send().await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::ErrorStatus(response.status(), response.text().await.ok()))
+        }
+    }
 }
 
 #[async_trait]
@@ -92,19 +153,59 @@ impl NodeCommunication for NodeCommunicationThroughRoutingImpl {
                 sla,
                 node_origin: to.clone(),
                 accumulated_latency: Time::new::<second>(0.0),
+                timeout: None,
             })?,
-            route_to_stack: self.network.get_route_to_node(to).await,
+            route_to_stack: self.network.get_route_to_node(&to).await?,
         };
 
         Ok(serde_json::from_slice(&self.call_routing(data).await?)?)
     }
 
-    async fn take_offer(&self, to: NodeId, bid: &BidProposal) -> Result<(), Error> {
-        let data = Packet::FogNode { route_to_stack: self.network.get_route_to_node(to).await,
+    async fn take_offer(&self,
+                        to: NodeId,
+                        bid: &BidProposal,
+                        function: &BidId)
+                        -> Result<(), Error> {
+        let offer = TakeOffer { function: function.clone() };
+        let data = Packet::FogNode { route_to_stack: self.network.get_route_to_node(&to).await?,
                                      resource_uri:   format!("bid/{}", bid.id),
+                                     data:           &serde_json::value::to_raw_value(&offer)?, };
+
+        self.call_routing(data).await?;
+        Ok(())
+    }
+
+    async fn cancel_bid(&self, to: NodeId, bid: &BidProposal) -> Result<(), Error> {
+        let data = Packet::FogNode { route_to_stack: self.network.get_route_to_node(&to).await?,
+                                     resource_uri:   format!("bid/{}/cancel", bid.id),
                                      data:           &serde_json::value::to_raw_value(&())?, };
 
         self.call_routing(data).await?;
         Ok(())
     }
+
+    async fn establish_route(&self, stack: FunctionRoutingStack) -> Result<(), Error> {
+        self.send_route(reqwest::Method::PUT, &stack).await
+    }
+
+    async fn remove_route(&self, stack: FunctionRoutingStack) -> Result<(), Error> {
+        self.send_route(reqwest::Method::DELETE, &stack).await
+    }
+
+    async fn remove_function(&self, to: NodeId, id: &BidId) -> Result<(), Error> {
+        let data = Packet::FogNode { route_to_stack: self.network.get_route_to_node(&to).await?,
+                                     resource_uri:   format!("function/{}/remove", id),
+                                     data:           &serde_json::value::to_raw_value(&())?, };
+
+        self.call_routing(data).await?;
+        Ok(())
+    }
+
+    async fn change_parent(&self, to: NodeId, parent: &NodeId) -> Result<(), Error> {
+        self.send_neighbor(to, "parent", parent).await
+    }
+
+    async fn adopt_child(&self, to: NodeId, child: &NodeId) -> Result<(), Error> {
+        self.send_neighbor(to, "child", child).await
+    }
 }
@@ -42,13 +42,19 @@ impl Auction for AuctionImpl {
     async fn call_for_bids(&self, leaf_node: NodeId, sla: Sla) -> Result<BidProposals, Error> {
         trace!("call for bids: {:?}", sla);
 
-        Ok(self.node_communication.request_bids_from_node(leaf_node, sla).await?)
+        let proposals = self.node_communication.request_bids_from_node(leaf_node, sla).await?;
+        for failure in proposals.failures.iter() {
+            warn!("Subtree of {} did not bid: {:?}", failure.node_id, failure.reason);
+        }
+
+        Ok(proposals)
     }
 
     async fn do_auction(&self, proposals: &BidProposals) -> Result<AuctionResult, Error> {
         trace!("do auction: {:?}", proposals);
         let auction_result =
             self.auction_process.auction(&proposals.bids).ok_or(Error::NoWinner)?;
-        Ok(AuctionResult { chosen_bid: auction_result })
+        Ok(AuctionResult { chosen_bid: auction_result,
+                           mechanism:  self.auction_process.mechanism(), })
     }
 }
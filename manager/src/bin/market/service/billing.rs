@@ -0,0 +1,98 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use uom::si::f64::Time;
+
+use manager::model::domain::ledger::Contract;
+use manager::model::view::auction::AcceptedBid;
+use manager::model::view::ledger::{Balances, Statement};
+use manager::model::{BidId, NodeId};
+
+use crate::repository::ledger::Ledger;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Ledger(#[from] crate::repository::ledger::Error),
+}
+
+#[async_trait]
+pub trait Billing: Debug + Sync + Send {
+    /// Start charging the client for the function provisioned from the [AcceptedBid], at its
+    /// clearing price
+    async fn open_contract(&self, accepted: &AcceptedBid) -> Result<(), Error>;
+    /// Stop charging for the function, the last billing period being charged pro rata
+    async fn close_contract(&self, id: &BidId) -> Result<(), Error>;
+    /// Record the charges accrued so far by all the contracts
+    async fn settle(&self) -> Result<(), Error>;
+    async fn get_balances(&self) -> Result<Balances, Error>;
+    async fn get_node_statement(&self, node: &NodeId) -> Result<Statement, Error>;
+    async fn get_client_statement(&self, client: &str) -> Result<Statement, Error>;
+}
+
+#[derive(Debug)]
+pub struct BillingImpl {
+    ledger:         Arc<dyn Ledger>,
+    billing_period: Time,
+}
+
+impl BillingImpl {
+    pub fn new(ledger: Arc<dyn Ledger>, billing_period: Time) -> Self {
+        Self { ledger, billing_period }
+    }
+}
+
+#[async_trait]
+impl Billing for BillingImpl {
+    async fn open_contract(&self, accepted: &AcceptedBid) -> Result<(), Error> {
+        let contract = Contract::new(accepted.chosen.bid.id.clone(),
+                                     accepted.sla.client.clone(),
+                                     accepted.chosen.bid.node_id.clone(),
+                                     accepted.chosen.price,
+                                     self.billing_period,
+                                     Utc::now());
+        Ok(self.ledger.open(contract).await?)
+    }
+
+    async fn close_contract(&self, id: &BidId) -> Result<(), Error> {
+        Ok(self.ledger.close(id, Utc::now()).await?)
+    }
+
+    async fn settle(&self) -> Result<(), Error> { Ok(self.ledger.settle(Utc::now()).await?) }
+
+    async fn get_balances(&self) -> Result<Balances, Error> {
+        self.settle().await?;
+        let mut balances = self.ledger.get_settled().await;
+        for charge in self.ledger.get_charges().await {
+            balances.record(&charge);
+        }
+        Ok(balances)
+    }
+
+    async fn get_node_statement(&self, node: &NodeId) -> Result<Statement, Error> {
+        self.settle().await?;
+        let settled = self.ledger.get_settled().await.nodes.get(node).copied().unwrap_or_default();
+        let contracts = self.ledger.get_contracts().await;
+        let charges = self.ledger.get_charges().await;
+        Ok(Statement::new(settled,
+                          contracts.into_iter()
+                                   .filter(|contract| &contract.provider == node)
+                                   .collect(),
+                          charges.into_iter().filter(|charge| &charge.provider == node).collect()))
+    }
+
+    async fn get_client_statement(&self, client: &str) -> Result<Statement, Error> {
+        self.settle().await?;
+        let settled =
+            self.ledger.get_settled().await.clients.get(client).copied().unwrap_or_default();
+        let contracts = self.ledger.get_contracts().await;
+        let charges = self.ledger.get_charges().await;
+        Ok(Statement::new(settled,
+                          contracts.into_iter()
+                                   .filter(|contract| contract.client == client)
+                                   .collect(),
+                          charges.into_iter().filter(|charge| charge.client == client).collect()))
+    }
+}
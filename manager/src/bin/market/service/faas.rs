@@ -1,10 +1,11 @@
 use async_trait::async_trait;
 use manager::model::dto::node::NodeRecord;
-use manager::model::view::auction::AcceptedBid;
-use manager::model::NodeId;
+use manager::model::view::auction::{AcceptedBid, BidProposal};
+use manager::model::{BidId, NodeId};
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::repository::fog_node::FogNode;
 use crate::repository::node_communication::NodeCommunication;
@@ -16,12 +17,36 @@ pub enum Error {
     #[error("No trace of the node {0} has been found. It should have been registered as a \
              record though.")]
     NodeNotFound(NodeId),
+    #[error("No provisioned function corresponds to the id {0}.")]
+    FunctionNotFound(BidId),
+    #[error("Several instances of the function {0} are provisioned, as it is being moved.")]
+    FunctionBeingMoved(BidId),
+    #[error(transparent)]
+    FogNode(#[from] crate::repository::fog_node::Error),
 }
 
+/// Times the node is asked to take the offer before giving up
+const TAKE_OFFER_ATTEMPTS: u32 = 3;
+const TAKE_OFFER_RETRY_DELAY: Duration = Duration::from_millis(500);
+
 #[async_trait]
 pub trait FogNodeFaaS: Debug + Sync + Send {
+    /// Provision the function on the node of the winning bid, then record it.
+    /// If either step fails, the function is removed from the node, so that the node and the
+    /// market never disagree on whether it is provisioned.
     async fn provision_function(&self, bid: AcceptedBid) -> Result<(), Error>;
+    /// Drop a winning bid that is not needed, releasing what the node reserved for it
+    async fn cancel_bid(&self, bid: &BidProposal) -> Result<(), Error>;
     async fn get_functions(&self) -> HashMap<NodeId, Vec<AcceptedBid>>;
+    /// Get the accepted bid of a provisioned function, from the identity of the function.
+    /// While the function is moved, both its former and its new instances are provisioned: it
+    /// is an error, rather than picking one of them.
+    async fn get_function(&self, id: &BidId) -> Result<AcceptedBid, Error>;
+    /// Remove the function from the node hosting it, and forget about its record
+    async fn remove_function(&self, bid: &AcceptedBid) -> Result<(), Error>;
+    /// Forget about the record of the function without contacting the node hosting it, e.g.,
+    /// because it is unreachable
+    async fn forget_function(&self, bid: &AcceptedBid) -> Result<(), Error>;
 }
 
 #[derive(Debug)]
@@ -34,26 +59,110 @@ impl FogNodeFaaSImpl {
     pub fn new(fog_node: Arc<dyn FogNode>, node_communication: Arc<dyn NodeCommunication>) -> Self {
         Self { fog_node, node_communication }
     }
+
+    /// Ask the node to take the offer, retrying since it is idempotent on the node
+    async fn take_offer(&self,
+                        node: &NodeId,
+                        bid: &BidProposal,
+                        function: &BidId)
+                        -> Result<(), Error> {
+        let mut attempt = 1;
+        loop {
+            match self.node_communication.take_offer(node.clone(), bid, function).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < TAKE_OFFER_ATTEMPTS => {
+                    warn!("node {} failed to take the offer {} (attempt {}): {}",
+                          node, bid.id, attempt, err);
+                    attempt += 1;
+                    tokio::time::sleep(TAKE_OFFER_RETRY_DELAY).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Remove the function from the node after a failed provisioning, in case the node
+    /// provisioned it anyway
+    async fn compensate(&self, node: &NodeId, id: &BidId) {
+        if let Err(err) = self.node_communication.remove_function(node.clone(), id).await {
+            debug!("nothing to remove on {} after failing to provision {}: {}", node, id, err);
+        }
+    }
+
+    /// Change the record of the node hosting the functions
+    async fn modify(&self,
+                    node: &NodeId,
+                    change: impl FnOnce(&mut NodeRecord) + Send)
+                    -> Result<(), Error> {
+        match self.fog_node.modify(node, Box::new(change)).await {
+            Err(crate::repository::fog_node::Error::NodeDoesntExist(_)) => {
+                Err(Error::NodeNotFound(node.clone()))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Drop the record of the function from the node hosting it
+    async fn remove_record(&self, bid: &AcceptedBid) -> Result<(), Error> {
+        self.modify(&bid.chosen.bid.node_id, |record| {
+                record.accepted_bids.remove(&bid.function);
+            })
+            .await
+    }
 }
 
 #[async_trait]
 impl FogNodeFaaS for FogNodeFaaSImpl {
     async fn provision_function(&self, bid: AcceptedBid) -> Result<(), Error> {
         let node = bid.chosen.bid.node_id.clone();
-        let res = self.node_communication.take_offer(node.clone(), &bid.chosen.bid).await?;
+        let id = bid.function.clone();
+        if let Err(err) = self.take_offer(&node, &bid.chosen.bid, &id).await {
+            self.compensate(&node, &id).await;
+            return Err(err);
+        }
+
+        if let Err(err) = self.modify(&node, |record| {
+                                  record.accepted_bids.insert(id.clone(), bid);
+                              })
+                              .await
+        {
+            self.compensate(&node, &id).await;
+            return Err(err);
+        }
 
-        let mut record: NodeRecord = self.fog_node
-                                         .get(&node)
-                                         .await
-                                         .map(|node| node.data)
-                                         .ok_or_else(|| Error::NodeNotFound(node.clone()))?;
-        record.accepted_bids.insert(bid.chosen.bid.id.clone(), bid);
-        self.fog_node.update(&node, record).await;
+        Ok(())
+    }
 
-        Ok(res)
+    async fn cancel_bid(&self, bid: &BidProposal) -> Result<(), Error> {
+        Ok(self.node_communication.cancel_bid(bid.node_id.clone(), bid).await?)
     }
 
     async fn get_functions(&self) -> HashMap<NodeId, Vec<AcceptedBid>> {
         self.fog_node.get_records().await
     }
+
+    async fn get_function(&self, id: &BidId) -> Result<AcceptedBid, Error> {
+        let mut instances = self.fog_node
+                                .get_records()
+                                .await
+                                .into_values()
+                                .flatten()
+                                .filter(|accepted| &accepted.function == id);
+        match (instances.next(), instances.next()) {
+            (Some(accepted), None) => Ok(accepted),
+            (None, _) => Err(Error::FunctionNotFound(id.clone())),
+            (Some(_), Some(_)) => Err(Error::FunctionBeingMoved(id.clone())),
+        }
+    }
+
+    async fn remove_function(&self, bid: &AcceptedBid) -> Result<(), Error> {
+        self.node_communication
+            .remove_function(bid.chosen.bid.node_id.clone(), &bid.function)
+            .await?;
+        self.remove_record(bid).await
+    }
+
+    async fn forget_function(&self, bid: &AcceptedBid) -> Result<(), Error> {
+        self.remove_record(bid).await
+    }
 }
@@ -1,50 +1,225 @@
 use std::fmt::Debug;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_trait::async_trait;
+use manager::model::domain::heartbeat::{Freshness, HeartbeatThresholds};
+use manager::model::domain::liveness::Liveness;
 use manager::model::dto::node::NodeRecord;
 use manager::model::NodeId;
 
-use manager::model::view::node::RegisterNode;
+use manager::model::view::node::{NodeSummary, RegisterNode};
+use manager::model::view::topology::Topology;
 
-use crate::repository::fog_node::FogNode;
+use crate::repository::fog_node::{FogNode, RecordChange};
+use crate::repository::node_communication::NodeCommunication;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
     NodeUpdate(#[from] crate::repository::fog_node::Error),
+    #[error("Node {0} is not registered")]
+    NodeNotFound(NodeId),
 }
 
 #[async_trait]
 pub trait FogNodeNetwork: Debug + Sync + Send {
-    async fn register_node(&self, node: RegisterNode) -> Result<(), Error>;
+    /// Register a node. A node already registered under another parent is moved, along with its
+    /// subtree, under the new one; its former parent is then returned.
+    async fn register_node(&self, node: RegisterNode) -> Result<Option<NodeId>, Error>;
+    /// Whether registering the node would move it, along with its subtree, under another parent
+    async fn is_moving(&self, node: &RegisterNode) -> bool;
+    /// Check that the node can leave the network, i.e., it is registered and it is not the root
+    async fn check_removable(&self, node: &NodeId) -> Result<(), Error>;
+    /// Remove a node leaving the network, its children being attached to its parent. The parent
+    /// and the children are told about their new neighbors.
+    async fn unregister_node(&self, node: &NodeId) -> Result<(), Error>;
+    /// Get the node and all its descendants
+    async fn get_subtree(&self, node: &NodeId) -> Vec<NodeId>;
     /// Get all the connected nodes
     async fn get_nodes(&self) -> Vec<(NodeId, NodeRecord)>;
+    /// Get the whole tree, annotated with the last state reported by the nodes
+    async fn get_topology(&self) -> Result<Topology, Error>;
+    /// Record the liveness of a node, as reported by one of its neighbors
+    async fn update_liveness(&self, node: &NodeId, liveness: Liveness) -> Result<(), Error>;
+    /// Record a heartbeat of the node, which is not stale anymore
+    async fn heartbeat(&self, node: &NodeId, summary: NodeSummary) -> Result<(), Error>;
+    /// Mark the nodes that have been silent for a while as stale, and return the ones silent for
+    /// so long that they should be evicted. The root is never evicted.
+    async fn check_heartbeats(&self) -> Result<Vec<NodeId>, Error>;
 }
 
 #[derive(Debug)]
 pub struct FogNodeNetworkHashTreeImpl {
-    fog_node: Arc<dyn FogNode>,
+    fog_node:           Arc<dyn FogNode>,
+    node_communication: Arc<dyn NodeCommunication>,
+    thresholds:         HeartbeatThresholds,
+    /// Stands for the last heartbeat of the nodes not heard of since the start
+    started_at:         Instant,
 }
 
 impl FogNodeNetworkHashTreeImpl {
-    pub fn new(fog_node: Arc<dyn FogNode>) -> Self { FogNodeNetworkHashTreeImpl { fog_node } }
+    pub fn new(fog_node: Arc<dyn FogNode>,
+               node_communication: Arc<dyn NodeCommunication>,
+               thresholds: HeartbeatThresholds)
+               -> Self {
+        FogNodeNetworkHashTreeImpl { fog_node,
+                                     node_communication,
+                                     thresholds,
+                                     started_at: Instant::now() }
+    }
+
+    /// Change the record of a registered node
+    async fn modify(&self,
+                    node: &NodeId,
+                    change: impl FnOnce(&mut NodeRecord) + Send)
+                    -> Result<(), Error> {
+        match self.fog_node.modify(node, Box::new(change)).await {
+            Err(crate::repository::fog_node::Error::NodeDoesntExist(_)) => {
+                Err(Error::NodeNotFound(node.clone()))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// A (re-)registration counts as a heartbeat, and may come from a new address with new tags
+    fn register(ip: IpAddr, port: u16, tags: Vec<String>) -> RecordChange<'static> {
+        Box::new(move |record| {
+            record.ip = Some(ip);
+            record.port = Some(port);
+            record.tags = tags;
+            record.stale = false;
+            record.last_heartbeat = Some(Instant::now());
+        })
+    }
 }
 
 #[async_trait]
 impl FogNodeNetwork for FogNodeNetworkHashTreeImpl {
-    async fn register_node(&self, node: RegisterNode) -> Result<(), Error> {
+    async fn register_node(&self, node: RegisterNode) -> Result<Option<NodeId>, Error> {
         match node {
             RegisterNode::MarketNode { node_id, ip, port, tags } => {
-                self.fog_node.append_root(node_id, ip, port, tags).await?;
+                self.fog_node.append_root(node_id, Self::register(ip, port, tags)).await?;
+            }
+            RegisterNode::Node { node_id, parent, ip, port, tags } => {
+                let register = Self::register(ip, port, tags);
+                match self.fog_node.get(&node_id).await {
+                    Some(existing) if existing.parent.as_ref() != Some(&parent) => {
+                        let former_parent =
+                            self.fog_node.move_subtree(&node_id, &parent, register).await?;
+                        return Ok(Some(former_parent));
+                    }
+                    Some(_) => {
+                        trace!("node {} is already registered under {}", node_id, parent);
+                        self.modify(&node_id, register).await?;
+                    }
+                    None => self.fog_node.append_new_child(&parent, node_id, register).await?,
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn is_moving(&self, node: &RegisterNode) -> bool {
+        match node {
+            RegisterNode::MarketNode { .. } => false,
+            RegisterNode::Node { node_id, parent, .. } => {
+                self.fog_node
+                    .get(node_id)
+                    .await
+                    .is_some_and(|existing| existing.parent.as_ref() != Some(parent))
             }
-            RegisterNode::Node { node_id, parent, tags, .. } => {
-                self.fog_node.append_new_child(&parent, node_id, tags).await?;
+        }
+    }
+
+    async fn check_removable(&self, node: &NodeId) -> Result<(), Error> {
+        match self.fog_node.get(node).await {
+            None => Err(Error::NodeNotFound(node.clone())),
+            Some(existing) if existing.parent.is_none() => {
+                Err(crate::repository::fog_node::Error::CannotRemoveRoot(node.clone()).into())
             }
+            Some(_) => Ok(()),
         }
+    }
 
+    async fn unregister_node(&self, node: &NodeId) -> Result<(), Error> {
+        let removed =
+            self.fog_node.get(node).await.ok_or_else(|| Error::NodeNotFound(node.clone()))?;
+        self.fog_node.remove(node).await?;
+
+        // The removal succeeded, so the node had a parent
+        let parent = removed.parent.unwrap();
+        for child in removed.children.iter() {
+            // The parent must know the child before the packets to the child go through it
+            if let Err(err) = self.node_communication.adopt_child(parent.clone(), child).await {
+                warn!("failed to tell {} about its new child {}: {}", parent, child, err);
+            }
+            if let Err(err) = self.node_communication.change_parent(child.clone(), &parent).await {
+                warn!("failed to tell {} about its new parent {}: {}", child, parent, err);
+            }
+        }
         Ok(())
     }
 
+    async fn get_subtree(&self, node: &NodeId) -> Vec<NodeId> {
+        self.fog_node.get_subtree(node).await
+    }
+
     async fn get_nodes(&self) -> Vec<(NodeId, NodeRecord)> { self.fog_node.get_nodes().await }
+
+    async fn get_topology(&self) -> Result<Topology, Error> {
+        Ok(self.fog_node.get_topology().await?)
+    }
+
+    async fn update_liveness(&self, node: &NodeId, liveness: Liveness) -> Result<(), Error> {
+        self.modify(node, |record| record.liveness = liveness).await
+    }
+
+    async fn heartbeat(&self, node: &NodeId, summary: NodeSummary) -> Result<(), Error> {
+        self.modify(node, |record| {
+                if record.stale {
+                    info!("node {} is back", node);
+                }
+                let unknown_functions = summary.provisioned_functions
+                                               .iter()
+                                               .filter(|id| !record.accepted_bids.contains_key(id))
+                                               .count();
+                if unknown_functions > 0 {
+                    warn!("node {} hosts {} functions the market doesn't know",
+                          node, unknown_functions);
+                }
+                record.stale = false;
+                record.summary = Some(summary);
+                record.last_heartbeat = Some(Instant::now());
+            })
+            .await
+    }
+
+    async fn check_heartbeats(&self) -> Result<Vec<NodeId>, Error> {
+        let now = Instant::now();
+        let mut expired = vec![];
+        for (id, record) in self.fog_node.get_nodes().await {
+            let last_heartbeat = record.last_heartbeat.unwrap_or(self.started_at);
+            let freshness = self.thresholds.freshness(last_heartbeat, now);
+            if freshness == Freshness::Fresh {
+                continue;
+            }
+            if !record.stale {
+                warn!("node {} has not sent any heartbeat for a while, marking it stale", id);
+                self.modify(&id, |record| record.stale = true).await?;
+            }
+            if freshness == Freshness::Expired {
+                match self.fog_node.get(&id).await {
+                    Some(node) if node.parent.is_none() => {
+                        warn!("the root {} is silent, but cannot be evicted", id)
+                    }
+                    Some(_) => expired.push(id),
+                    None => (),
+                }
+            }
+        }
+        Ok(expired)
+    }
 }
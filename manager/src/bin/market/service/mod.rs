@@ -1,4 +1,5 @@
 pub(crate) mod auction;
+pub(crate) mod billing;
 pub(crate) mod faas;
 pub(crate) mod fog_node_network;
 pub(crate) mod routing;
@@ -1,125 +1,249 @@
-// use anyhow::{anyhow, Result};
-// use futures::future::{BoxFuture, FutureExt};
-// use if_chain::if_chain;
-// use shared_models::{
-//     view::routing::{RouteAction, RouteStack},
-//     BidId, NodeId,
-// };
-// use std::sync::Arc;
-// use tokio::sync::Mutex;
-
-// use crate::{
-//     live_store::NodesDataBase,
-// };
-
-// async fn do_routes(
-//     couples: Vec<(NodeId, NodeId)>,
-//     function: BidId,
-//     nodes_db: Arc<Mutex<NodesDataBase>>,
-//     market_attached_node_uri: String,
-// ) {
-//     trace!("do routes: {:?}", couples);
-//     let market_attached_node_uri = Arc::new(market_attached_node_uri);
-//     let function = Arc::new(function);
-
-//     let mut handles = Vec::new();
-//     for (from, to) in couples {
-//         let market_attached_node_uri = market_attached_node_uri.clone();
-//         let function = function.clone();
-//         let nodes_db = nodes_db.clone();
-//         let job = tokio::spawn(async move {
-//             request_route(market_attached_node_uri, function, from, to, nodes_db).await;
-//         });
-
-//         handles.push(job);
-//     }
-
-//     for handle in handles {
-//         handle.await.unwrap();
-//     }
-// }
-
-// async fn request_route(
-//     uri_of_market_node: Arc<String>,
-//     function: Arc<BidId>,
-//     from: NodeId,
-//     to: NodeId,
-//     nodes_db: Arc<Mutex<NodesDataBase>>,
-// ) {
-//     if let Ok(stack) = do_route(from, to, nodes_db).await {
-//         trace!("computed route: {:?}", stack);
-//         let client = reqwest::Client::new();
-//         match client
-//             .put(format!(
-//                 "http://{}/api/routing/{}",
-//                 uri_of_market_node, function
-//             ))
-//             .body(serde_json::to_string(&stack).unwrap())
-//             .send()
-//             .await
-//         {
-//             Ok(_) => (),
-
-//             Err(err) => trace!("error while sending route: {:?}", err),
-//         };
-//     }
-// }
-
-// /// Generates the [RouteStack] follinwg the path returned from the [NodesDataBase]
-// fn do_route(
-//     from: NodeId,
-//     to: NodeId,
-//     nodes_db: Arc<Mutex<NodesDataBase>>,
-// ) -> BoxFuture<'static, Result<RouteStack>> {
-//     async move {
-//         let route_stack = nodes_db.lock().await.get_path(&from, &to).ok_or(anyhow!("No path
-// found"))?;         let mut route_stack = route_stack.iter();
-//         route_stack.next(); // ignore the destination node, as it is useless to register a route
-// in it
-
-//         let mut ret = RouteStack {
-//             ..Default::default()
-//         };
-//         let mut next = route_stack.next();
-//         while let Some(node_id) = next {
-//             next = route_stack.next();
-//             if_chain! {
-//                 if let Some(node) = nodes_db.lock().await.get(node_id);
-//                 if let Some(parent) = node.parent.to_owned();
-//                 if let Some(next_id) = next;
-//                 then
-//                 {
-//                     if &parent != next_id {
-//                         ret.routes.insert(0, RouteAction::Assign{node: node_id.to_owned(),next:
-// next_id.to_owned()});                     }else{
-//                         // we are on a "^" in the tree, meaning the parent is not in the path
-//                         ret = RouteStack {
-//                             routes: vec![RouteAction::Divide{node:
-// node_id.to_owned(),next_from_side: Box::new(do_route(from, node_id.to_owned(),
-// nodes_db.to_owned()).await?),next_to_side: Box::new(ret)}],
-// ..Default::default()                         };
-//                         break;
-//                     }
-
-//                 }
-//                 else
-//                 {
-//                     return Err(anyhow!("Cannot keep goign because the topology of the tree
-// doesn't correspond to  the found path"));                 }
-//             }
-//         }
-
-//         while let Some(node_id) = next {
-//             if let Some(_node) = nodes_db.lock().await.get(node_id) {
-//                 ret.routes.insert(
-//                     0,
-//                     RouteAction::Skip {
-//                         node: node_id.to_owned(),
-//                     },
-//                 );
-//             }
-//         }
-
-//         Ok(ret)
-//     }.boxed()
-// }
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::try_join_all;
+
+use manager::model::domain::routing::FunctionRoutingStack;
+use manager::model::{BidId, NodeId};
+
+use crate::repository::fog_node::FogNode;
+use crate::repository::node_communication::NodeCommunication;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    NodeCommunication(#[from] crate::repository::node_communication::Error),
+    #[error(transparent)]
+    FogNode(#[from] crate::repository::fog_node::Error),
+}
+
+#[async_trait]
+pub trait Router: Debug + Sync + Send {
+    /// Establish the routes to the function hosted on the node [host]; from every source, and
+    /// between the function and every destination.
+    async fn register_function_routes(&self,
+                                      function: BidId,
+                                      host: NodeId,
+                                      sources: Vec<NodeId>,
+                                      destinations: Vec<NodeId>)
+                                      -> Result<(), Error>;
+
+    /// Remove the routes previously established by [Router::register_function_routes].
+    async fn unregister_function_routes(&self,
+                                        function: BidId,
+                                        host: NodeId,
+                                        sources: Vec<NodeId>,
+                                        destinations: Vec<NodeId>)
+                                        -> Result<(), Error>;
+}
+
+#[derive(Debug)]
+pub struct RouterImpl {
+    fog_node:           Arc<dyn FogNode>,
+    node_communication: Arc<dyn NodeCommunication>,
+}
+
+impl RouterImpl {
+    pub fn new(fog_node: Arc<dyn FogNode>, node_communication: Arc<dyn NodeCommunication>) -> Self {
+        Self { fog_node, node_communication }
+    }
+
+    /// Compute the route (stack) to the [from] node, and the path from the [from] node to the
+    /// [to] node, passing by their lowest common ancestor. The [destination] is set for the
+    /// routes from the function to one of its destinations.
+    async fn get_function_routing_stack(&self,
+                                        function: &BidId,
+                                        from: &NodeId,
+                                        to: &NodeId,
+                                        destination: Option<NodeId>)
+                                        -> Result<FunctionRoutingStack, Error> {
+        Ok(FunctionRoutingStack { function: function.clone(),
+                                  route_to_first: self.fog_node.get_route_to_node(from).await?,
+                                  routes: self.fog_node.get_path(from, to).await?,
+                                  destination })
+    }
+
+    /// Compute the stacks to the [host] from every source, and from the [host] to every
+    /// destination
+    async fn get_function_routing_stacks(&self,
+                                         function: &BidId,
+                                         host: &NodeId,
+                                         sources: Vec<NodeId>,
+                                         destinations: Vec<NodeId>)
+                                         -> Result<Vec<FunctionRoutingStack>, Error> {
+        let sources: HashSet<NodeId> = sources.into_iter().collect();
+        let destinations: HashSet<NodeId> = destinations.into_iter().collect();
+
+        let mut stacks = vec![];
+        for source in sources {
+            stacks.push(self.get_function_routing_stack(function, &source, host, None).await?);
+        }
+        for destination in destinations {
+            stacks.push(self.get_function_routing_stack(function,
+                                                        host,
+                                                        &destination,
+                                                        Some(destination.clone()))
+                            .await?);
+        }
+        Ok(stacks)
+    }
+}
+
+#[async_trait]
+impl Router for RouterImpl {
+    async fn register_function_routes(&self,
+                                      function: BidId,
+                                      host: NodeId,
+                                      sources: Vec<NodeId>,
+                                      destinations: Vec<NodeId>)
+                                      -> Result<(), Error> {
+        trace!("Registering routes to {} hosted on {}", function, host);
+        let stacks =
+            self.get_function_routing_stacks(&function, &host, sources, destinations).await?;
+
+        try_join_all(stacks.into_iter().map(|stack| {
+                                           trace!("Establishing route {:?}", stack);
+                                           self.node_communication.establish_route(stack)
+                                       })).await?;
+
+        Ok(())
+    }
+
+    async fn unregister_function_routes(&self,
+                                        function: BidId,
+                                        host: NodeId,
+                                        sources: Vec<NodeId>,
+                                        destinations: Vec<NodeId>)
+                                        -> Result<(), Error> {
+        trace!("Unregistering routes to {} hosted on {}", function, host);
+        let stacks =
+            self.get_function_routing_stacks(&function, &host, sources, destinations).await?;
+
+        try_join_all(stacks.into_iter().map(|stack| {
+                                           trace!("Removing route {:?}", stack);
+                                           self.node_communication.remove_route(stack)
+                                       })).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use tokio::sync::Mutex;
+    use uuid::Uuid;
+
+    use manager::model::domain::sla::Sla;
+    use manager::model::view::auction::{BidProposal, BidProposals};
+
+    use crate::repository::fog_node::FogNodeImpl;
+    use crate::repository::node_communication::Error as CommunicationError;
+
+    use super::*;
+
+    /// Only records the routes it is asked to establish, the other requests failing
+    #[derive(Debug, Default)]
+    struct RouteRecorder {
+        established: Mutex<Vec<FunctionRoutingStack>>,
+    }
+
+    #[async_trait]
+    impl NodeCommunication for RouteRecorder {
+        async fn request_bids_from_node(&self,
+                                        _to: NodeId,
+                                        _sla: Sla)
+                                        -> Result<BidProposals, CommunicationError> {
+            Err(CommunicationError::WrongPacketType)
+        }
+
+        async fn take_offer(&self,
+                            _to: NodeId,
+                            _bid: &BidProposal,
+                            _function: &BidId)
+                            -> Result<(), CommunicationError> {
+            Err(CommunicationError::WrongPacketType)
+        }
+
+        async fn cancel_bid(&self,
+                            _to: NodeId,
+                            _bid: &BidProposal)
+                            -> Result<(), CommunicationError> {
+            Err(CommunicationError::WrongPacketType)
+        }
+
+        async fn establish_route(&self,
+                                 stack: FunctionRoutingStack)
+                                 -> Result<(), CommunicationError> {
+            self.established.lock().await.push(stack);
+            Ok(())
+        }
+
+        async fn remove_route(&self,
+                              _stack: FunctionRoutingStack)
+                              -> Result<(), CommunicationError> {
+            Err(CommunicationError::WrongPacketType)
+        }
+
+        async fn remove_function(&self,
+                                 _to: NodeId,
+                                 _id: &BidId)
+                                 -> Result<(), CommunicationError> {
+            Err(CommunicationError::WrongPacketType)
+        }
+
+        async fn change_parent(&self,
+                               _to: NodeId,
+                               _parent: &NodeId)
+                               -> Result<(), CommunicationError> {
+            Err(CommunicationError::WrongPacketType)
+        }
+
+        async fn adopt_child(&self,
+                             _to: NodeId,
+                             _child: &NodeId)
+                             -> Result<(), CommunicationError> {
+            Err(CommunicationError::WrongPacketType)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_go_from_the_sources_and_to_the_destinations() {
+        // root -> (a, b), the function being hosted on the root
+        let [root, a, b] = std::array::from_fn(|_| NodeId::from(Uuid::new_v4()));
+        let fog_node = Arc::new(FogNodeImpl::new());
+        fog_node.append_root(root.clone(),
+                             Box::new(|record| {
+                                 record.ip = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+                                 record.port = Some(3000);
+                             }))
+                .await
+                .unwrap();
+        fog_node.append_new_child(&root, a.clone(), Box::new(|_| ())).await.unwrap();
+        fog_node.append_new_child(&root, b.clone(), Box::new(|_| ())).await.unwrap();
+        let recorder = Arc::new(RouteRecorder::default());
+        let router = RouterImpl::new(fog_node, recorder.clone());
+
+        let function = BidId::from(Uuid::new_v4());
+        router.register_function_routes(function.clone(),
+                                        root.clone(),
+                                        vec![a.clone()],
+                                        vec![b.clone(),])
+              .await
+              .unwrap();
+
+        let established = recorder.established.lock().await;
+        assert_eq!(established.len(), 2);
+        let to_function = established.iter().find(|stack| stack.destination.is_none()).unwrap();
+        assert_eq!(to_function.route_to_first, vec![a.clone(), root.clone()]);
+        assert_eq!(to_function.routes, vec![a, root.clone()]);
+        let to_destination =
+            established.iter().find(|stack| stack.destination.as_ref() == Some(&b)).unwrap();
+        assert_eq!(to_destination.function, function);
+        assert_eq!(to_destination.route_to_first, vec![root.clone()]);
+        assert_eq!(to_destination.routes, vec![root, b]);
+    }
+}
@@ -1,7 +1,6 @@
-use bytes::Bytes;
 use log::error;
 use okapi::openapi3::Responses;
-use rocket::http::Status;
+use rocket::http::{ContentType, Header, Status};
 use rocket::response::Responder;
 use rocket::serde::json::Json;
 use rocket::{Request, Response};
@@ -9,6 +8,8 @@ use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::response::OpenApiResponderInner;
 use std::io::Cursor;
 
+use crate::model::dto::routing::RoutedResponse;
+
 /// Shortcut type for the responses of this handler.
 pub type Resp<T = ()> = std::result::Result<Json<T>, Error>;
 
@@ -49,23 +50,25 @@ impl OpenApiResponderInner for Error {
     }
 }
 
-pub struct BytesResponse(pub Bytes);
-
-impl From<Bytes> for BytesResponse {
-    fn from(bytes: Bytes) -> Self { Self(bytes) }
-}
-
-impl<'r> Responder<'r, 'static> for BytesResponse {
+/// Answer with the status, headers and body relayed, defaulting to a JSON content
+impl<'r> Responder<'r, 'static> for RoutedResponse {
     fn respond_to(self, _request: &Request<'_>) -> rocket::response::Result<'static> {
-        let body = self.0;
-        Ok(Response::build().header(rocket::http::ContentType::JSON)
-                            .sized_body(body.len(), Cursor::new(body))
-                            .status(Status::Ok)
-                            .finalize())
+        let mut response = Response::build();
+        response.header(ContentType::JSON);
+        for (name, value) in self.headers {
+            if name.eq_ignore_ascii_case("content-type") {
+                response.header(Header::new(name, value));
+            } else {
+                response.header_adjoin(Header::new(name, value));
+            }
+        }
+        Ok(response.sized_body(self.body.len(), Cursor::new(self.body))
+                   .status(Status::from_code(self.status).unwrap_or(Status::InternalServerError))
+                   .finalize())
     }
 }
 
-impl OpenApiResponderInner for BytesResponse {
+impl OpenApiResponderInner for RoutedResponse {
     fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
         let mut responses = Responses::default();
         rocket_okapi::util::add_content_response(&mut responses,
@@ -1,4 +1,5 @@
 pub mod chrono;
 pub mod from_disk;
 pub mod handler;
+pub mod snapshot;
 pub mod uom;
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::Deref;
+use std::path::PathBuf;
+
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Failed to access the snapshot {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Failed to read the snapshot {0:?}: {1}")]
+    Format(PathBuf, serde_json::Error),
+}
+
+/// File where a state is saved, to restore it after a restart
+#[derive(Debug)]
+pub struct Snapshot {
+    path: PathBuf,
+}
+
+impl Snapshot {
+    pub fn new(path: PathBuf) -> Self { Snapshot { path } }
+
+    /// Read the saved state, [None] if nothing was saved yet
+    pub async fn load<T: DeserializeOwned>(&self) -> Result<Option<T>, Error> {
+        match tokio::fs::read(&self.path).await {
+            Ok(content) => {
+                Ok(Some(serde_json::from_slice(&content).map_err(|err| {
+                                                            Error::Format(self.path.clone(), err)
+                                                        })?))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::Io(self.path.clone(), err)),
+        }
+    }
+
+    /// Replace the saved state at once, the new one being written aside first
+    pub async fn save<T: Serialize + Sync>(&self, state: &T) -> Result<(), Error> {
+        let content =
+            serde_json::to_vec(state).map_err(|err| Error::Format(self.path.clone(), err))?;
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, content).await.map_err(|err| Error::Io(tmp.clone(), err))?;
+        tokio::fs::rename(&tmp, &self.path).await.map_err(|err| Error::Io(self.path.clone(), err))
+    }
+}
+
+/// State kept behind a lock and saved to a [Snapshot], if any, after every change.
+/// The lock is held while saving, so that the snapshots are written in the same order as the
+/// changes. A change that could not be saved is kept in memory, and saved along the next one.
+#[derive(Debug)]
+pub struct Persisted<T> {
+    state:    RwLock<T>,
+    snapshot: Option<Snapshot>,
+}
+
+impl<T> Persisted<T> where T: Serialize + DeserializeOwned + Default + Send + Sync
+{
+    /// Keep the state in memory only
+    pub fn new(state: T) -> Self { Persisted { state: RwLock::new(state), snapshot: None } }
+
+    /// Restore the state from the [Snapshot], the default one if nothing was saved yet
+    pub async fn load(snapshot: Snapshot) -> Result<Self, Error> {
+        Self::load_or(snapshot, T::default()).await
+    }
+
+    /// Restore the state from the [Snapshot], the [initial] one if nothing was saved yet
+    pub async fn load_or(snapshot: Snapshot, initial: T) -> Result<Self, Error> {
+        Ok(Persisted { state:    RwLock::new(snapshot.load().await?.unwrap_or(initial)),
+                       snapshot: Some(snapshot), })
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> { self.state.read().await }
+
+    /// Apply the change, then save the state
+    pub async fn write<R: Send>(&self,
+                                change: impl FnOnce(&mut T) -> R + Send)
+                                -> Result<R, Error> {
+        let mut state = self.state.write().await;
+        let result = change(&mut state);
+        self.save(&state).await?;
+        Ok(result)
+    }
+
+    /// Apply the change, then save the state unless the change failed
+    pub async fn try_write<R: Send, E: From<Error>>(&self,
+                                                    change: impl FnOnce(&mut T) -> Result<R, E>
+                                                        + Send)
+                                                    -> Result<R, E> {
+        let mut state = self.state.write().await;
+        let result = change(&mut state)?;
+        self.save(&state).await?;
+        Ok(result)
+    }
+
+    async fn save(&self, state: &T) -> Result<(), Error> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.save(state).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Map whose changed entries are tracked, to be appended to a [Journal]
+#[derive(Debug)]
+pub struct JournaledMap<K, V> {
+    entries:   HashMap<K, V>,
+    changed:   HashSet<K>,
+    /// Number of entries in the journal, beside the snapshot
+    journaled: usize,
+}
+
+impl<K, V> Default for JournaledMap<K, V> {
+    fn default() -> Self {
+        JournaledMap { entries: HashMap::new(), changed: HashSet::new(), journaled: 0 }
+    }
+}
+
+/// All the entries are new, and are saved at the next save
+impl<K: Eq + Hash + Clone, V> From<HashMap<K, V>> for JournaledMap<K, V> {
+    fn from(entries: HashMap<K, V>) -> Self {
+        let changed = entries.keys().cloned().collect();
+        JournaledMap { entries, changed, journaled: 0 }
+    }
+}
+
+impl<K, V> Deref for JournaledMap<K, V> {
+    type Target = HashMap<K, V>;
+
+    fn deref(&self) -> &Self::Target { &self.entries }
+}
+
+impl<K: Eq + Hash + Clone, V> JournaledMap<K, V> {
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let value = self.entries.get_mut(key)?;
+        self.changed.insert(key.clone());
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.changed.insert(key.clone());
+        self.entries.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.changed.insert(key.clone());
+        self.entries.remove(key)
+    }
+}
+
+/// [Snapshot] of a [JournaledMap], along with a journal where its changed entries are appended,
+/// so that saving a change costs the size of the change rather than the size of the map.
+/// The journal is folded into the snapshot once it outgrows the map.
+#[derive(Debug)]
+pub struct Journal {
+    snapshot: Snapshot,
+    path:     PathBuf,
+}
+
+impl Journal {
+    pub fn new(snapshot: Snapshot) -> Self {
+        let path = snapshot.path.with_extension("journal");
+        Journal { snapshot, path }
+    }
+
+    /// Read the snapshot, then replay the journal over it. A last entry cut short, as the
+    /// process stopped while appending it, is ignored.
+    pub async fn load<K, V>(&self) -> Result<JournaledMap<K, V>, Error>
+        where K: DeserializeOwned + Eq + Hash,
+              V: DeserializeOwned
+    {
+        let mut map = JournaledMap { entries: self.snapshot.load().await?.unwrap_or_default(),
+                                     ..JournaledMap::default() };
+        let content = match tokio::fs::read(&self.path).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(map),
+            Err(err) => return Err(Error::Io(self.path.clone(), err)),
+        };
+        let mut lines = content.split(|byte| *byte == b'\n').filter(|line| !line.is_empty());
+        while let Some(line) = lines.next() {
+            let (key, value): (K, Option<V>) = match serde_json::from_slice(line) {
+                Ok(entry) => entry,
+                Err(err) if lines.clone().next().is_none() => {
+                    warn!("Ignoring the last entry of the journal {:?}: {}", self.path, err);
+                    break;
+                }
+                Err(err) => return Err(Error::Format(self.path.clone(), err)),
+            };
+            match value {
+                Some(value) => map.entries.insert(key, value),
+                None => map.entries.remove(&key),
+            };
+            map.journaled += 1;
+        }
+        Ok(map)
+    }
+
+    /// Append the entries changed since the last save to the journal, or fold everything into
+    /// the snapshot if the journal would outgrow the map. The changes that could not be saved
+    /// are saved along the next ones.
+    pub async fn save<K, V>(&self, map: &mut JournaledMap<K, V>) -> Result<(), Error>
+        where K: Serialize + Eq + Hash + Sync,
+              V: Serialize + Sync
+    {
+        if map.changed.is_empty() {
+            return Ok(());
+        }
+        if map.journaled.saturating_add(map.changed.len()) > map.entries.len().max(1) {
+            self.snapshot.save(&map.entries).await?;
+            match tokio::fs::remove_file(&self.path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    // Replaying the journal over the snapshot is harmless, but it is not folded
+                    map.journaled = usize::MAX;
+                    map.changed.clear();
+                    return Err(Error::Io(self.path.clone(), err));
+                }
+                _ => (),
+            }
+            map.journaled = 0;
+            map.changed.clear();
+            return Ok(());
+        }
+
+        let mut content = vec![];
+        for key in map.changed.iter() {
+            serde_json::to_writer(&mut content, &(key, map.entries.get(key)))
+                .map_err(|err| Error::Format(self.path.clone(), err))?;
+            content.push(b'\n');
+        }
+        let appended = async {
+            let mut file =
+                tokio::fs::OpenOptions::new().create(true).append(true).open(&self.path).await?;
+            file.write_all(&content).await?;
+            file.flush().await
+        };
+        if let Err(err) = appended.await {
+            // The journal may end with a partial entry: fold it into the snapshot next time
+            map.journaled = usize::MAX;
+            return Err(Error::Io(self.path.clone(), err));
+        }
+        map.journaled += map.changed.len();
+        map.changed.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_persisted_state_round_trips() {
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", uuid::Uuid::new_v4()));
+        let persisted =
+            Persisted::<HashMap<String, u32>>::load(Snapshot::new(path.clone())).await.unwrap();
+        assert!(persisted.read().await.is_empty());
+        persisted.write(|state| state.insert("a".to_string(), 1)).await.unwrap();
+        let failed: Result<(), Error> = persisted.try_write(|state| {
+                                                     state.insert("b".to_string(), 2);
+                                                     Err(Error::Io(PathBuf::new(),
+                                                          std::io::ErrorKind::Other.into()))
+                                                 })
+                                                 .await;
+        assert!(failed.is_err());
+
+        let restored =
+            Persisted::<HashMap<String, u32>>::load(Snapshot::new(path.clone())).await.unwrap();
+        assert_eq!(*restored.read().await, HashMap::from([("a".to_string(), 1)]));
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_journal_replays_over_the_snapshot() {
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", uuid::Uuid::new_v4()));
+        let journal = Journal::new(Snapshot::new(path.clone()));
+        let mut map = journal.load::<String, u32>().await.unwrap();
+        for ii in 0..10 {
+            map.insert(ii.to_string(), ii);
+            journal.save(&mut map).await.unwrap();
+        }
+        assert_eq!(map.journaled, 10);
+        // Changing the entries again would outgrow the map: they are folded into the snapshot
+        *map.get_mut(&"3".to_string()).unwrap() = 30;
+        map.remove(&"4".to_string());
+        journal.save(&mut map).await.unwrap();
+        assert_eq!(map.journaled, 0);
+        map.insert("10".to_string(), 10);
+        journal.save(&mut map).await.unwrap();
+        assert_eq!(map.journaled, 1);
+
+        let restored =
+            Journal::new(Snapshot::new(path.clone())).load::<String, u32>().await.unwrap();
+        assert_eq!(*restored, *map);
+        tokio::fs::remove_file(&path).await.unwrap();
+        tokio::fs::remove_file(path.with_extension("journal")).await.unwrap();
+    }
+}
@@ -15,9 +15,31 @@ pub struct AuctionSummary {
     pub status: AuctionStatus,
 }
 
+/// The mechanisms the market can use to select the winning bid and the price it is paid.
+/// The lowest bid always wins, it is the price that changes from one mechanism to another.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default, JsonSchema)]
+pub enum AuctionMechanism {
+    /// The winner is paid its own bid
+    FirstPrice,
+    /// The winner is paid the second lowest bid
+    #[default]
+    SecondPrice,
+    /// Bids above the reserve price are discarded, the winner is paid the second lowest bid,
+    /// capped by the reserve price
+    SecondPriceReserve { reserve_price: f64 },
+    /// The winner is paid the k-th lowest bid (or the highest one if there are less than k
+    /// bids)
+    KthPrice { k: usize },
+    /// Same as [AuctionMechanism::SecondPrice], but the winner is drawn at random among the
+    /// lowest bids when they are tied
+    SecondPriceRandomTieBreak,
+}
+
 /// Sums up all proposal received by the market
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AuctionResult {
     pub chosen_bid: ChosenBid,
+    /// The mechanism used to select the chosen bid
+    pub mechanism:  AuctionMechanism,
 }
@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+/// Freshness of a node, as seen by the market from the heartbeats it sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    /// Sent a heartbeat recently
+    Fresh,
+    /// Silent for a while, not routed to until it sends a heartbeat again
+    Stale,
+    /// Silent for too long, to be evicted from the network
+    Expired,
+}
+
+/// Time without any heartbeat before a node becomes [Freshness::Stale], then
+/// [Freshness::Expired]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatThresholds {
+    pub stale_after: Duration,
+    pub evict_after: Duration,
+}
+
+impl Default for HeartbeatThresholds {
+    fn default() -> Self {
+        HeartbeatThresholds { stale_after: Duration::from_secs(45),
+                              evict_after: Duration::from_secs(300), }
+    }
+}
+
+impl HeartbeatThresholds {
+    /// Freshness at [now] of a node whose last heartbeat arrived at [last_heartbeat]
+    pub fn freshness(&self, last_heartbeat: Instant, now: Instant) -> Freshness {
+        let silence = now.saturating_duration_since(last_heartbeat);
+        if silence >= self.evict_after {
+            Freshness::Expired
+        } else if silence >= self.stale_after {
+            Freshness::Stale
+        } else {
+            Freshness::Fresh
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freshness_degrades_with_silence() {
+        let thresholds = HeartbeatThresholds { stale_after: Duration::from_secs(10),
+                                               evict_after: Duration::from_secs(60), };
+        let last = Instant::now();
+
+        assert_eq!(thresholds.freshness(last, last), Freshness::Fresh);
+        assert_eq!(thresholds.freshness(last, last + Duration::from_secs(9)), Freshness::Fresh);
+        assert_eq!(thresholds.freshness(last, last + Duration::from_secs(10)), Freshness::Stale);
+        assert_eq!(thresholds.freshness(last, last + Duration::from_secs(60)), Freshness::Expired);
+    }
+
+    #[test]
+    fn test_heartbeat_from_the_future_is_fresh() {
+        let thresholds = HeartbeatThresholds::default();
+        let now = Instant::now();
+
+        assert_eq!(thresholds.freshness(now + Duration::from_secs(1), now), Freshness::Fresh);
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+
+use uom::si::f64::Time;
+
+/// Number of samples kept to compute the percentiles
+const WINDOW_SIZE: usize = 40;
+/// Weight of a new sample in the moving average (same as TCP's smoothed RTT)
+const EWMA_ALPHA: f64 = 0.125;
+/// Gain of the jitter estimation (RFC 3550)
+const JITTER_GAIN: f64 = 1.0 / 16.0;
+
+/// Latency statistics over the recent exchanges with a node, so that a degrading link is
+/// reflected quickly.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    window: VecDeque<Time>,
+    ewma:   Option<Time>,
+    jitter: Time,
+}
+
+/// Snapshot of the [LatencyStats]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySummary {
+    pub ewma:   Time,
+    pub p50:    Time,
+    pub p95:    Time,
+    pub p99:    Time,
+    pub jitter: Time,
+}
+
+impl LatencyStats {
+    pub fn update(&mut self, latency: Time) {
+        match self.ewma {
+            None => self.ewma = Some(latency),
+            Some(ewma) => self.ewma = Some(ewma + (latency - ewma) * EWMA_ALPHA),
+        }
+        if let Some(last) = self.window.back() {
+            self.jitter += ((latency - *last).abs() - self.jitter) * JITTER_GAIN;
+        }
+
+        if self.window.len() == WINDOW_SIZE {
+            self.window.pop_front();
+        }
+        self.window.push_back(latency);
+    }
+
+    pub fn get_ewma(&self) -> Option<Time> { self.ewma }
+
+    /// Nearest-rank percentile over the window, `rank` being in ]0, 1]
+    pub fn get_percentile(&self, rank: f64) -> Option<Time> {
+        if self.window.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Time> = self.window.iter().copied().collect();
+        sorted.sort_by(|a, b| a.value.total_cmp(&b.value));
+        let index = ((rank * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1;
+        Some(sorted[index])
+    }
+
+    pub fn get_summary(&self) -> Option<LatencySummary> {
+        Some(LatencySummary { ewma:   self.get_ewma()?,
+                              p50:    self.get_percentile(0.50)?,
+                              p95:    self.get_percentile(0.95)?,
+                              p99:    self.get_percentile(0.99)?,
+                              jitter: self.jitter, })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::time::millisecond;
+
+    use super::*;
+
+    fn ms(value: f64) -> Time { Time::new::<millisecond>(value) }
+
+    #[test]
+    fn test_empty_stats() {
+        assert_eq!(LatencyStats::default().get_summary(), None);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut stats = LatencyStats::default();
+        for value in 1..=20 {
+            stats.update(ms(value as f64));
+        }
+        let summary = stats.get_summary().unwrap();
+        assert_eq!(summary.p50, ms(10.0));
+        assert_eq!(summary.p95, ms(19.0));
+        assert_eq!(summary.p99, ms(20.0));
+    }
+
+    #[test]
+    fn test_window_forgets_old_samples() {
+        let mut stats = LatencyStats::default();
+        for _ in 0..WINDOW_SIZE {
+            stats.update(ms(100.0));
+        }
+        for _ in 0..WINDOW_SIZE {
+            stats.update(ms(10.0));
+        }
+        assert_eq!(stats.get_percentile(0.99), Some(ms(10.0)));
+    }
+
+    #[test]
+    fn test_ewma_and_jitter() {
+        let mut stats = LatencyStats::default();
+        stats.update(ms(10.0));
+        assert_eq!(stats.get_ewma(), Some(ms(10.0)));
+        assert_eq!(stats.get_summary().unwrap().jitter, ms(0.0));
+        stats.update(ms(26.0));
+        assert_eq!(stats.get_ewma(), Some(ms(12.0)));
+        assert_eq!(stats.get_summary().unwrap().jitter, ms(1.0));
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uom::si::f64::Time;
+use uom::si::time::millisecond;
+
+use crate::helper::chrono as chrono_helper;
+use crate::helper::uom::time;
+use crate::model::{BidId, NodeId};
+
+/// A function provisioned on a node for a client, charged at the clearing price of its auction
+/// every billing period
+#[serde_with::serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Contract {
+    /// The winning bid
+    pub id:             BidId,
+    pub client:         String,
+    /// The node hosting the function, which gets paid
+    pub provider:       NodeId,
+    pub price:          f64,
+    #[schemars(schema_with = "time::schema_function")]
+    #[serde_as(as = "time::Helper")]
+    pub billing_period: Time,
+    #[schemars(schema_with = "chrono_helper::schema_function")]
+    #[serde_as(as = "chrono_helper::DateTimeHelper")]
+    pub started_at:     DateTime<Utc>,
+    /// Everything before was already charged
+    #[schemars(schema_with = "chrono_helper::schema_function")]
+    #[serde_as(as = "chrono_helper::DateTimeHelper")]
+    pub settled_until:  DateTime<Utc>,
+    #[schemars(schema_with = "chrono_helper::schema_function")]
+    #[serde_as(as = "Option<chrono_helper::DateTimeHelper>")]
+    #[serde(default)]
+    pub ended_at:       Option<DateTime<Utc>>,
+}
+
+/// An amount the client of a contract owes to its provider, for the use of the function over a
+/// time span
+#[serde_with::serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Charge {
+    pub contract: BidId,
+    pub client:   String,
+    pub provider: NodeId,
+    #[schemars(schema_with = "chrono_helper::schema_function")]
+    #[serde_as(as = "chrono_helper::DateTimeHelper")]
+    pub from:     DateTime<Utc>,
+    #[schemars(schema_with = "chrono_helper::schema_function")]
+    #[serde_as(as = "chrono_helper::DateTimeHelper")]
+    pub to:       DateTime<Utc>,
+    pub amount:   f64,
+}
+
+impl Contract {
+    pub fn new(id: BidId,
+               client: String,
+               provider: NodeId,
+               price: f64,
+               billing_period: Time,
+               started_at: DateTime<Utc>)
+               -> Self {
+        Contract { id,
+                   client,
+                   provider,
+                   price,
+                   billing_period,
+                   started_at,
+                   settled_until: started_at,
+                   ended_at: None }
+    }
+
+    /// Everything was charged, up to the end of the contract
+    pub fn is_settled(&self) -> bool { self.ended_at == Some(self.settled_until) }
+
+    /// Charge the billing periods completed by [now]. Once the contract has ended, the last
+    /// (partial) period is charged pro rata.
+    pub fn accrue(&mut self, now: DateTime<Utc>) -> Option<Charge> {
+        let period = self.billing_period.get::<millisecond>();
+        if period <= 0.0 {
+            return None;
+        }
+        let periods_until =
+            |until: DateTime<Utc>| (until - self.settled_until).num_milliseconds() as f64 / period;
+
+        let (until, periods) = match self.ended_at {
+            Some(ended_at) => (ended_at, periods_until(ended_at)),
+            None => {
+                let periods = periods_until(now).floor();
+                let completed = chrono::Duration::milliseconds((periods * period) as i64);
+                (self.settled_until + completed, periods)
+            }
+        };
+        if periods <= 0.0 {
+            // Nothing to charge, the contract having ended less than a millisecond later
+            if let Some(ended_at) = self.ended_at {
+                self.settled_until = ended_at;
+            }
+            return None;
+        }
+
+        let charge = Charge { contract: self.id.clone(),
+                              client:   self.client.clone(),
+                              provider: self.provider.clone(),
+                              from:     self.settled_until,
+                              to:       until,
+                              amount:   self.price * periods, };
+        self.settled_until = until;
+        Some(charge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::time::second;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn contract(start: DateTime<Utc>) -> Contract {
+        Contract::new(BidId::from(Uuid::new_v4()),
+                      "client".to_string(),
+                      NodeId::from(Uuid::new_v4()),
+                      2.0,
+                      Time::new::<second>(60.0),
+                      start)
+    }
+
+    #[test]
+    fn test_accrue_charges_the_completed_periods() {
+        let start = Utc::now();
+        let mut contract = contract(start);
+
+        assert_eq!(contract.accrue(start + chrono::Duration::seconds(59)), None);
+
+        let charge = contract.accrue(start + chrono::Duration::seconds(150)).unwrap();
+        assert_eq!(charge.amount, 4.0);
+        assert_eq!((charge.from, charge.to), (start, start + chrono::Duration::seconds(120)));
+        assert_eq!(contract.accrue(start + chrono::Duration::seconds(150)), None);
+        assert!(!contract.is_settled());
+    }
+
+    #[test]
+    fn test_accrue_charges_the_last_period_pro_rata() {
+        let start = Utc::now();
+        let mut contract = contract(start);
+        contract.accrue(start + chrono::Duration::seconds(60)).unwrap();
+
+        contract.ended_at = Some(start + chrono::Duration::seconds(90));
+        let charge = contract.accrue(start + chrono::Duration::seconds(300)).unwrap();
+        assert_eq!(charge.amount, 1.0);
+        assert_eq!(charge.to, start + chrono::Duration::seconds(90));
+        assert!(contract.is_settled());
+        assert_eq!(contract.accrue(start + chrono::Duration::seconds(300)), None);
+    }
+
+    #[test]
+    fn test_contract_ended_right_away_is_settled() {
+        let start = Utc::now();
+        let mut contract = contract(start);
+
+        contract.ended_at = Some(start + chrono::Duration::microseconds(10));
+        assert_eq!(contract.accrue(start + chrono::Duration::seconds(300)), None);
+        assert!(contract.is_settled());
+    }
+}
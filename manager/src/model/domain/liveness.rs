@@ -0,0 +1,89 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Liveness of a node, as seen by one of its neighbors
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+pub enum Liveness {
+    /// Answers the pings
+    #[default]
+    Alive,
+    /// Missed a few pings, still used
+    Suspect,
+    /// Missed too many pings, not used anymore until it answers again
+    Dead,
+}
+
+/// Number of consecutive missed pings before changing the [Liveness] of a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailureThresholds {
+    pub suspect_after: u32,
+    pub dead_after:    u32,
+}
+
+impl Default for FailureThresholds {
+    fn default() -> Self { FailureThresholds { suspect_after: 2, dead_after: 4 } }
+}
+
+/// Failure detector for a single node, fed with the outcome of every ping
+#[derive(Debug, Clone, Default)]
+pub struct LivenessTracker {
+    missed_pings: u32,
+    liveness:     Liveness,
+}
+
+impl LivenessTracker {
+    /// Record the outcome of a ping, and return the new [Liveness] if it changed.
+    /// A single answered ping is enough to recover.
+    pub fn record(&mut self, answered: bool, thresholds: &FailureThresholds) -> Option<Liveness> {
+        if answered {
+            self.missed_pings = 0;
+        } else {
+            self.missed_pings = self.missed_pings.saturating_add(1);
+        }
+
+        let liveness = if self.missed_pings >= thresholds.dead_after {
+            Liveness::Dead
+        } else if self.missed_pings >= thresholds.suspect_after {
+            Liveness::Suspect
+        } else {
+            Liveness::Alive
+        };
+
+        if liveness == self.liveness {
+            return None;
+        }
+        self.liveness = liveness;
+        Some(liveness)
+    }
+
+    pub fn get_liveness(&self) -> Liveness { self.liveness }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missed_pings_lead_to_death() {
+        let thresholds = FailureThresholds::default();
+        let mut tracker = LivenessTracker::default();
+        assert_eq!(tracker.record(false, &thresholds), None);
+        assert_eq!(tracker.record(false, &thresholds), Some(Liveness::Suspect));
+        assert_eq!(tracker.record(false, &thresholds), None);
+        assert_eq!(tracker.record(false, &thresholds), Some(Liveness::Dead));
+        assert_eq!(tracker.record(false, &thresholds), None);
+        assert_eq!(tracker.get_liveness(), Liveness::Dead);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let thresholds = FailureThresholds::default();
+        let mut tracker = LivenessTracker::default();
+        for _ in 0..thresholds.dead_after {
+            tracker.record(false, &thresholds);
+        }
+        assert_eq!(tracker.record(true, &thresholds), Some(Liveness::Alive));
+        assert_eq!(tracker.record(true, &thresholds), None);
+        assert_eq!(tracker.record(false, &thresholds), None);
+    }
+}
@@ -1,4 +1,10 @@
 pub mod auction;
-pub mod rolling_avg;
+pub mod heartbeat;
+pub mod latency_stats;
+pub mod ledger;
+pub mod liveness;
+pub mod placement;
+pub mod pricing;
 pub mod routing;
 pub mod sla;
+pub mod tree_path;
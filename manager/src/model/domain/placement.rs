@@ -0,0 +1,35 @@
+use std::num::NonZeroUsize;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The strategies a fog node can use to look for candidates to host a function, among itself
+/// and its neighbors.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default, JsonSchema)]
+pub enum Placement {
+    /// Bid and ask every neighbor in reach to bid as well
+    #[default]
+    Auction,
+    /// Ask the neighbors one by one, keep the first bid returned, and only bid as a last resort
+    BottomUp,
+    /// Ask the parent first, and only bid if nothing was found higher in the tree (cloud-first)
+    TopDown,
+    /// Bid, and only if unable to, ask the neighbors one by one, the nearest first; stops at the
+    /// first feasible node
+    LatencyGreedy,
+    /// Same as [Placement::Auction], but only ask the k neighbors with the lowest latency; k
+    /// cannot be zero
+    BoundedFanout { k: NonZeroUsize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_fanout_rejects_zero() {
+        let placement: Placement = serde_json::from_str(r#"{"BoundedFanout":{"k":2}}"#).unwrap();
+        assert_eq!(placement, Placement::BoundedFanout { k: NonZeroUsize::new(2).unwrap() });
+        assert!(serde_json::from_str::<Placement>(r#"{"BoundedFanout":{"k":0}}"#).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The strategies a fog node can use to price its bids, along with their parameters.
+/// Memory prices are per GB and CPU prices per CPU asked by the SLA.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default, JsonSchema)]
+pub enum Pricing {
+    /// The scarcer the resources left on the node once the SLA is placed, the higher the price
+    #[default]
+    Utilization,
+    /// Fixed price per resource unit
+    Linear { memory_price: f64, cpu_price: f64 },
+    /// Linear price, multiplied by up to `1 + surge_factor` as the utilization of the node
+    /// once the SLA is placed goes from `threshold` to 1
+    Surge { memory_price: f64, cpu_price: f64, threshold: f64, surge_factor: f64 },
+    /// Fixed and per resource unit costs, increased by the margin (e.g., 0.2 for 20%)
+    CostPlusMargin { fixed_cost: f64, memory_cost: f64, cpu_cost: f64, margin: f64 },
+}
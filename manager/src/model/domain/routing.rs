@@ -10,11 +10,30 @@ use crate::model::{BidId, NodeId};
 pub struct FunctionRoutingStack {
     pub function: BidId,
 
-    /// Route to the first node where the route need to be registered
+    /// Route to the first node where the route need to be registered.
+    /// It is a stack: the first node of [routes] is at the bottom, the next node to contact is at
+    /// the top.
     pub route_to_first: Vec<NodeId>,
 
-    /// Route to be registered, starting at the [route_to_first_node]
+    /// Route to be registered, starting at the last node of [route_to_first] and ending at the
+    /// node hosting the function, or at the [destination]
     pub routes: Vec<NodeId>,
+
+    /// Set for the routes going from the node hosting the function to one of the destinations of
+    /// its responses, instead of going to the function
+    #[serde(default)]
+    pub destination: Option<NodeId>,
+}
+
+/// How a function is invoked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+pub enum InvocationMode {
+    /// Fire and forget, the response is empty
+    #[default]
+    Async,
+    /// Wait for the function to answer; its status, headers and body are relayed back to the
+    /// caller through every hop
+    Sync,
 }
 
 /// [PacketPacket] with its direction:
@@ -22,10 +41,13 @@ pub struct FunctionRoutingStack {
 /// - [Packet::FogNode] directs to the fog node itself (at the start of the routing stack
 ///   transmitted)
 /// - [Packet::Market] directs to the market
+/// - [Packet::FunctionResponse] directs from the hosted faaSFunction to one of its destinations
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum Packet<'a> {
     FaaSFunction {
         to:   BidId,
+        #[serde(default)]
+        mode: InvocationMode,
         #[serde(borrow)]
         #[schemars(schema_with = "schema_function")]
         data: &'a RawValue,
@@ -43,6 +65,15 @@ pub enum Packet<'a> {
         #[schemars(schema_with = "schema_function")]
         data:         &'a RawValue,
     },
+    /// Delivered to the [resource_uri] of the destination fog node [to]
+    FunctionResponse {
+        from:         BidId,
+        to:           NodeId,
+        resource_uri: String,
+        #[serde(borrow)]
+        #[schemars(schema_with = "schema_function")]
+        data:         &'a RawValue,
+    },
 }
 
 pub fn schema_function(_: &mut SchemaGenerator) -> Schema {
@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use uom::si::f64::{Information, Ratio, Time};
 
 use crate::helper::uom::{information, ratio, time};
+use crate::model::domain::placement::Placement;
 
 /// Describe the SLA of a function submitted to be provisioned
 #[serde_with::serde_as]
@@ -44,4 +45,8 @@ pub struct Sla {
     pub function_image: String,
 
     pub function_live_name: Option<String>,
+
+    /// Overrides the placement strategy configured on the nodes
+    #[serde(default)]
+    pub placement: Option<Placement>,
 }
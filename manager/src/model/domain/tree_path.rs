@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use crate::model::dto::node::Node;
+use crate::model::NodeId;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("Cannot find the node {0} in the tree")]
+    UnknownNode(NodeId),
+    #[error("The ancestors of the node {0} loop back on themselves")]
+    Cycle(NodeId),
+    #[error("The nodes {0} and {1} have no common ancestor")]
+    NoCommonAncestor(NodeId, NodeId),
+}
+
+/// Get the node and all its ancestors, from the node itself up to the root
+pub fn ancestors<T>(nodes: &HashMap<NodeId, Node<T>>, id: &NodeId) -> Result<Vec<NodeId>, Error> {
+    let mut ancestors = vec![];
+    let mut cursor = Some(id.clone());
+    while let Some(current) = cursor {
+        // A tree has no path longer than its number of nodes
+        if ancestors.len() >= nodes.len() {
+            return Err(Error::Cycle(id.clone()));
+        }
+        let node = nodes.get(&current).ok_or_else(|| Error::UnknownNode(current.clone()))?;
+        cursor = node.parent.clone();
+        ancestors.push(current);
+    }
+    Ok(ancestors)
+}
+
+/// Get the deepest node that is an ancestor of both nodes, or one of the nodes themselves
+pub fn lowest_common_ancestor<T>(nodes: &HashMap<NodeId, Node<T>>,
+                                 a: &NodeId,
+                                 b: &NodeId)
+                                 -> Result<NodeId, Error> {
+    let a_ancestors = ancestors(nodes, a)?;
+    let b_ancestors = ancestors(nodes, b)?;
+    let (up, _) = split_at_lowest_common_ancestor(&a_ancestors, &b_ancestors, a, b)?;
+    // Never empty, since they have at least one ancestor in common
+    Ok(up[up.len() - 1].clone())
+}
+
+/// Get the path from the node [from] to the node [to], both included, going up to their lowest
+/// common ancestor and then down
+pub fn path<T>(nodes: &HashMap<NodeId, Node<T>>,
+               from: &NodeId,
+               to: &NodeId)
+               -> Result<Vec<NodeId>, Error> {
+    let from_ancestors = ancestors(nodes, from)?;
+    let to_ancestors = ancestors(nodes, to)?;
+    let (up, down) = split_at_lowest_common_ancestor(&from_ancestors, &to_ancestors, from, to)?;
+    Ok(up.iter().chain(down.iter().rev()).cloned().collect())
+}
+
+/// Split the ancestors of both nodes at their lowest common ancestor: the ancestors of [a] up to
+/// it (included), and the ones of [b] up to it (excluded)
+fn split_at_lowest_common_ancestor<'a>(a_ancestors: &'a [NodeId],
+                                       b_ancestors: &'a [NodeId],
+                                       a: &NodeId,
+                                       b: &NodeId)
+                                       -> Result<(&'a [NodeId], &'a [NodeId]), Error> {
+    // Both lists end at the root; walk them down from there while they agree
+    let common =
+        a_ancestors.iter().rev().zip(b_ancestors.iter().rev()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return Err(Error::NoCommonAncestor(a.clone(), b.clone()));
+    }
+    Ok((&a_ancestors[..=a_ancestors.len() - common], &b_ancestors[..b_ancestors.len() - common]))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::model::dto::node::tree_of;
+
+    use super::*;
+
+    /// root -> (a -> (a1, a2), b -> b1)
+    fn tree() -> (HashMap<NodeId, Node<()>>, [NodeId; 6]) {
+        tree_of([None, Some(0), Some(1), Some(1), Some(0), Some(4)])
+    }
+
+    #[test]
+    fn test_path_goes_through_the_lowest_common_ancestor() {
+        let (nodes, [root, a, a1, a2, b, b1]) = tree();
+
+        assert_eq!(lowest_common_ancestor(&nodes, &a1, &a2), Ok(a.clone()));
+        assert_eq!(lowest_common_ancestor(&nodes, &a1, &b1), Ok(root.clone()));
+        assert_eq!(lowest_common_ancestor(&nodes, &a, &a1), Ok(a.clone()));
+
+        assert_eq!(path(&nodes, &a1, &a2), Ok(vec![a1.clone(), a.clone(), a2.clone()]));
+        assert_eq!(path(&nodes, &a1, &b1), Ok(vec![a1.clone(), a.clone(), root, b, b1]));
+        assert_eq!(path(&nodes, &a1, &a), Ok(vec![a1.clone(), a.clone()]));
+        assert_eq!(path(&nodes, &a, &a1), Ok(vec![a.clone(), a1.clone()]));
+        assert_eq!(path(&nodes, &a1, &a1), Ok(vec![a1]));
+    }
+
+    #[test]
+    fn test_unknown_nodes_and_cycles_are_errors() {
+        let (mut nodes, [root, a, a1, ..]) = tree();
+        let unknown = NodeId::from(Uuid::new_v4());
+
+        assert_eq!(path(&nodes, &a1, &unknown), Err(Error::UnknownNode(unknown.clone())));
+
+        nodes.get_mut(&a1).unwrap().parent = Some(unknown.clone());
+        assert_eq!(ancestors(&nodes, &a1), Err(Error::UnknownNode(unknown)));
+
+        nodes.get_mut(&root).unwrap().parent = Some(a.clone());
+        assert_eq!(ancestors(&nodes, &a), Err(Error::Cycle(a)));
+    }
+}
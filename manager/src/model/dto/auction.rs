@@ -1,3 +1,4 @@
+use crate::model::domain::pricing::Pricing;
 use crate::model::domain::sla::Sla;
 use crate::model::view::auction::BidProposal;
 use schemars::JsonSchema;
@@ -5,9 +6,11 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BidRecord {
-    pub bid:  f64,
-    pub sla:  Sla,
-    pub node: String,
+    pub bid:     f64,
+    pub sla:     Sla,
+    pub node:    String,
+    /// The strategy that priced the bid
+    pub pricing: Pricing,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -1,10 +1,15 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::net::IpAddr;
+use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 
+use crate::model::domain::liveness::Liveness;
+use crate::model::domain::placement::Placement;
+use crate::model::domain::pricing::Pricing;
 use crate::model::view::auction::AcceptedBid;
+use crate::model::view::node::NodeSummary;
 use crate::model::{BidId, NodeId};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -16,13 +21,49 @@ pub struct Node<T> {
     pub data: T,
 }
 
+/// Build a tree of [N] fresh nodes out of the index of the parent of each of them, the root
+/// having none; the children are ordered by index
+#[cfg(any(test, feature = "bench"))]
+pub fn tree_of<T: Default, const N: usize>(parents: [Option<usize>; N])
+                                           -> (HashMap<NodeId, Node<T>>, [NodeId; N]) {
+    let ids: [NodeId; N] = std::array::from_fn(|_| NodeId::from(uuid::Uuid::new_v4()));
+    let mut nodes: HashMap<_, _> =
+        ids.iter()
+           .zip(parents)
+           .map(|(id, parent)| {
+               (id.clone(),
+                Node { parent:   parent.map(|parent| ids[parent].clone()),
+                       children: vec![],
+                       data:     T::default(), })
+           })
+           .collect();
+    for (id, parent) in ids.iter().zip(parents) {
+        if let Some(parent) = parent {
+            nodes.get_mut(&ids[parent]).unwrap().children.push(id.clone());
+        }
+    }
+    (nodes, ids)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct NodeRecord {
-    /// URI, only in the case of the market node
-    pub ip:            Option<IpAddr>,
-    pub port:          Option<u16>,
-    pub tags:          Vec<String>,
-    pub accepted_bids: HashMap<BidId, AcceptedBid>,
+    /// Address the node registered with, used to enter the tree close to the destination
+    pub ip:             Option<IpAddr>,
+    pub port:           Option<u16>,
+    pub tags:           Vec<String>,
+    /// Last liveness reported by the neighbors of the node
+    pub liveness:       Liveness,
+    /// No heartbeat was received for a while, the node is not routed to
+    #[serde(default)]
+    pub stale:          bool,
+    /// As sent in the last heartbeat
+    #[serde(default)]
+    pub summary:        Option<NodeSummary>,
+    /// Not saved, the nodes are given a fresh start after a restart of the market
+    #[serde(skip)]
+    pub last_heartbeat: Option<Instant>,
+    /// The functions hosted, by identity
+    pub accepted_bids:  HashMap<BidId, AcceptedBid>,
 }
 
 #[derive(Debug)]
@@ -38,12 +79,20 @@ impl From<Vec<NodeId>> for NodeIdList {
     fn from(list: Vec<NodeId>) -> Self { NodeIdList { list } }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeDescription {
     pub ip:   IpAddr,
     pub port: u16,
 }
 
+/// Node to register under when the current parent is detected as dead
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FallbackParent {
+    pub parent_id:        NodeId,
+    pub parent_node_ip:   IpAddr,
+    pub parent_node_port: u16,
+}
+
 #[derive(Debug)]
 pub enum NodeSituationData {
     MarketConnected {
@@ -60,6 +109,8 @@ pub enum NodeSituationData {
         parent_id:        NodeId,
         parent_node_ip:   IpAddr,
         parent_node_port: u16,
+        /// Tried in order when the parent dies, the former parent is put at the end
+        fallback_parents: Vec<FallbackParent>,
         my_id:            NodeId,
         my_public_ip:     IpAddr,
         my_public_port:   u16,
@@ -76,15 +127,30 @@ pub enum NodeSituationDisk {
         my_public_ip:   IpAddr,
         my_public_port: u16,
         tags:           Vec<String>,
+        /// How the node prices its bids, defaults to [Pricing::Utilization]
+        #[serde(default)]
+        pricing:        Pricing,
+        /// How the node looks for candidates, defaults to [Placement::Auction]
+        #[serde(default)]
+        placement:      Placement,
     },
     NodeConnected {
         parent_id:        NodeId,
         parent_node_ip:   IpAddr,
         parent_node_port: u16,
+        /// Ancestors to re-register under if the parent dies, e.g., the grandparent
+        #[serde(default)]
+        fallback_parents: Vec<FallbackParent>,
         my_id:            NodeId,
         my_public_ip:     IpAddr,
         my_public_port:   u16,
         tags:             Vec<String>,
+        /// How the node prices its bids, defaults to [Pricing::Utilization]
+        #[serde(default)]
+        pricing:          Pricing,
+        /// How the node looks for candidates, defaults to [Placement::Auction]
+        #[serde(default)]
+        placement:        Placement,
     },
 }
 
@@ -101,6 +167,13 @@ pub enum NodeSituationDisk {
 ///   parent_id: "e13f2a63-2934-480a-a448-b1b01af7e170",
 ///   parent_node_uri: "localhost:8080",
 ///   my_id: "49aaea47-7af7-4c68-b29a-b445ef194d3a",
+///   fallback_parents: [
+///     (
+///       parent_id: "e13f2a63-2934-480a-a448-b1b01af7e170",
+///       parent_node_ip: "127.0.0.1",
+///       parent_node_port: 3000,
+///     ),
+///   ],
 /// )
 /// ```
 impl NodeSituationDisk {
@@ -108,6 +181,20 @@ impl NodeSituationDisk {
         let situation = ron::from_str::<NodeSituationDisk>(&content)?;
         Ok(situation)
     }
+
+    pub fn pricing(&self) -> &Pricing {
+        match self {
+            NodeSituationDisk::MarketConnected { pricing, .. }
+            | NodeSituationDisk::NodeConnected { pricing, .. } => pricing,
+        }
+    }
+
+    pub fn placement(&self) -> &Placement {
+        match self {
+            NodeSituationDisk::MarketConnected { placement, .. }
+            | NodeSituationDisk::NodeConnected { placement, .. } => placement,
+        }
+    }
 }
 
 impl From<NodeSituationDisk> for NodeSituationData {
@@ -118,7 +205,8 @@ impl From<NodeSituationDisk> for NodeSituationData {
                                                  my_id,
                                                  my_public_ip,
                                                  my_public_port,
-                                                 tags, } => {
+                                                 tags,
+                                                 .. } => {
                 NodeSituationData::MarketConnected { children: HashMap::new(),
                                                      market_ip,
                                                      market_port,
@@ -130,14 +218,17 @@ impl From<NodeSituationDisk> for NodeSituationData {
             NodeSituationDisk::NodeConnected { parent_id,
                                                parent_node_port,
                                                parent_node_ip,
+                                               fallback_parents,
                                                my_id,
                                                my_public_ip,
                                                my_public_port,
-                                               tags, } => {
+                                               tags,
+                                               .. } => {
                 NodeSituationData::NodeConnected { children: HashMap::new(),
                                                    parent_id,
                                                    parent_node_ip,
                                                    parent_node_port,
+                                                   fallback_parents,
                                                    my_id,
                                                    my_public_ip,
                                                    my_public_port,
@@ -1,7 +1,49 @@
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
 use crate::model::NodeId;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Direction {
     NextNode(NodeId),
     CurrentNode,
 }
+
+/// Headers that only make sense for a single connection, and thus are not relayed
+const HOP_BY_HOP_HEADERS: [&str; 9] = ["connection",
+                                       "keep-alive",
+                                       "proxy-authenticate",
+                                       "proxy-authorization",
+                                       "te",
+                                       "trailer",
+                                       "transfer-encoding",
+                                       "upgrade",
+                                       "content-length"];
+
+/// A response relayed back along the route: its status, end-to-end headers and body
+#[derive(Debug, Clone)]
+pub struct RoutedResponse {
+    pub status:  u16,
+    pub headers: Vec<(String, String)>,
+    pub body:    Bytes,
+}
+
+impl RoutedResponse {
+    /// A successful response, without any particular header
+    pub fn ok(body: Bytes) -> Self { Self { status: 200, headers: vec![], body } }
+
+    pub async fn from_response(response: reqwest::Response) -> Result<Self, reqwest::Error> {
+        let status = response.status().as_u16();
+        let headers =
+            response.headers()
+                    .iter()
+                    .filter(|(name, _)| !HOP_BY_HOP_HEADERS.contains(&name.as_str()))
+                    .filter_map(|(name, value)| {
+                        value.to_str().ok().map(|value| (name.to_string(), value.to_string()))
+                    })
+                    .collect();
+        Ok(Self { status, headers, body: response.bytes().await? })
+    }
+
+    pub fn is_success(&self) -> bool { (200..300).contains(&self.status) }
+}
@@ -1,5 +1,7 @@
 use std::cmp::Ordering;
 
+use crate::model::domain::auction::AuctionMechanism;
+use crate::model::domain::pricing::Pricing;
 use crate::model::dto::auction::ChosenBid;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -7,6 +9,7 @@ use uom::si::f64::Time;
 
 use super::super::domain::sla::Sla;
 use super::super::{BidId, NodeId};
+use super::sla::PutSla;
 
 #[serde_with::serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -17,6 +20,12 @@ pub struct BidRequest {
     #[schemars(schema_with = "crate::helper::uom::time::schema_function")]
     #[serde_as(as = "crate::helper::uom::time::Helper")]
     pub accumulated_latency: Time,
+    /// Time left to answer, after which the requester stops waiting. Defaults to the timeout
+    /// configured on the node receiving the request
+    #[serde(default)]
+    #[schemars(schema_with = "crate::helper::uom::time::schema_function")]
+    #[serde_as(as = "Option<crate::helper::uom::time::Helper>")]
+    pub timeout:             Option<Time>,
 }
 
 /// A bid
@@ -30,8 +39,33 @@ pub struct Bid {
 /// The accepted bid
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct AcceptedBid {
-    pub chosen:    ChosenBid,
-    pub proposals: BidProposals,
+    /// Identity of the function: the id of the bid that won it first, kept when the function
+    /// moves to another node
+    pub function:        BidId,
+    pub chosen:          ChosenBid,
+    pub proposals:       BidProposals,
+    /// The request that led to the auction
+    pub sla:             PutSla,
+    /// The mechanism used to select the chosen bid
+    pub mechanism:       AuctionMechanism,
+    /// The better ranked bidders that failed to provision the function before the chosen one,
+    /// in the order they were tried
+    #[serde(default)]
+    pub failed_attempts: Vec<ProvisioningFailure>,
+}
+
+/// Sent with the acceptance of a bid, for the node to provision its function
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct TakeOffer {
+    /// Identity of the function, under which it is provisioned and routed
+    pub function: BidId,
+}
+
+/// A winning bidder that failed to provision the function
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct ProvisioningFailure {
+    pub chosen: ChosenBid,
+    pub reason: String,
 }
 
 /// The bid proposal and the node who issued it
@@ -41,11 +75,35 @@ pub struct BidProposal {
     pub node_id: NodeId,
     pub id:      BidId,
     pub bid:     f64,
+    /// The strategy the node used to price the bid
+    pub pricing: Pricing,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct BidProposals {
-    pub bids: Vec<BidProposal>,
+    pub bids:     Vec<BidProposal>,
+    /// The neighbors that did not answer, so the subtrees behind them did not bid
+    #[serde(default)]
+    pub failures: Vec<BidFailure>,
+}
+
+/// Why a neighbor did not answer with its bids
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub enum BidFailureReason {
+    /// The neighbor did not answer in time
+    Timeout,
+    /// The latency to the neighbor is not known yet
+    UnknownLatency,
+    /// The request to the neighbor failed
+    Error(String),
+}
+
+/// A neighbor that did not answer with its bids, as seen by the node that asked it
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BidFailure {
+    pub node_id: NodeId,
+    pub reason:  BidFailureReason,
 }
 
 impl PartialOrd for BidProposal {
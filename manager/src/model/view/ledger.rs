@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::model::domain::ledger::{Charge, Contract};
+use crate::model::NodeId;
+
+/// The contracts of a node or a client, and what was charged for them
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Statement {
+    /// Sum of all the charges, the settled contracts included: earned by a node, owed by a client
+    pub balance:   f64,
+    /// The contracts not settled yet
+    pub contracts: Vec<Contract>,
+    /// What they were charged so far
+    pub charges:   Vec<Charge>,
+}
+
+impl Statement {
+    /// The [settled] balance being the one of the contracts that were settled and left out
+    pub fn new(settled: f64, contracts: Vec<Contract>, charges: Vec<Charge>) -> Self {
+        Statement { balance: settled + charges.iter().map(|charge| charge.amount).sum::<f64>(),
+                    contracts,
+                    charges }
+    }
+}
+
+/// The balance of every node and client, see [Statement::balance]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Balances {
+    pub nodes:   HashMap<NodeId, f64>,
+    pub clients: HashMap<String, f64>,
+}
+
+impl Balances {
+    /// Credit the provider and debit the client of the charge
+    pub fn record(&mut self, charge: &Charge) {
+        *self.nodes.entry(charge.provider.clone()).or_default() += charge.amount;
+        *self.clients.entry(charge.client.clone()).or_default() += charge.amount;
+    }
+}
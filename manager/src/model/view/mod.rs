@@ -1,4 +1,6 @@
 pub mod auction;
+pub mod ledger;
 pub mod node;
 pub mod ping;
 pub mod sla;
+pub mod topology;
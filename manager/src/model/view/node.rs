@@ -4,8 +4,11 @@ use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::ops::Add;
+use uom::si::f64::{Information, Ratio, Time};
 
 use crate::helper::chrono as chrono_helper;
+use crate::model::domain::liveness::Liveness;
 use crate::model::dto::node::NodeRecord;
 use crate::model::view::auction::AcceptedBid;
 use crate::model::BidId;
@@ -55,16 +58,142 @@ pub enum RegisterNode {
     Node { parent: NodeId, node_id: NodeId, ip: IpAddr, port: u16, tags: Vec<String> },
 }
 
+/// A node leaving the network
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UnregisterNode {
+    pub node_id: NodeId,
+}
+
+/// A node replacing a neighbor that left the network: the new parent of its children, or a new
+/// child of its parent
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NeighborNode {
+    pub node_id: NodeId,
+    pub ip:      IpAddr,
+    pub port:    u16,
+}
+
+/// Progress of the registration of a node to its parent and the market
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RegistrationState {
+    #[default]
+    NotRegistered,
+    Registering,
+    Registered,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FogNodeHealth {
+    pub registration: RegistrationState,
+}
+
+/// Change of the [Liveness] of a node, reported by one of its neighbors
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PostLiveness {
+    pub reporter: NodeId,
+    pub node_id:  NodeId,
+    pub liveness: Liveness,
+}
+
+/// State of a node, as seen by itself
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeSummary {
+    pub children:              Vec<NodeId>,
+    /// The neighbors it detected as [Liveness::Dead]
+    pub dead_neighbors:        Vec<NodeId>,
+    pub provisioned_functions: Vec<BidId>,
+    /// Moving average of the latency to each neighbor, over the recent pings
+    #[serde(default)]
+    #[schemars(with = "HashMap<NodeId, String>")]
+    #[serde_as(as = "HashMap<_, crate::helper::uom::time::Helper>")]
+    pub latencies:             HashMap<NodeId, Time>,
+    /// Resources of all the nodes of the cluster behind the fog node
+    #[serde(default)]
+    pub resources:             Option<ResourceSummary>,
+}
+
+/// Resources of a cluster, summed over its nodes
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceSummary {
+    #[schemars(schema_with = "crate::helper::uom::information::schema_function")]
+    #[serde_as(as = "crate::helper::uom::information::Helper")]
+    pub used_memory:      Information,
+    #[schemars(schema_with = "crate::helper::uom::ratio::schema_function")]
+    #[serde_as(as = "crate::helper::uom::ratio::Helper")]
+    pub used_cpu:         Ratio,
+    /// Reserved by the pending bids
+    #[schemars(schema_with = "crate::helper::uom::information::schema_function")]
+    #[serde_as(as = "crate::helper::uom::information::Helper")]
+    pub reserved_memory:  Information,
+    #[schemars(schema_with = "crate::helper::uom::ratio::schema_function")]
+    #[serde_as(as = "crate::helper::uom::ratio::Helper")]
+    pub reserved_cpu:     Ratio,
+    #[schemars(schema_with = "crate::helper::uom::information::schema_function")]
+    #[serde_as(as = "crate::helper::uom::information::Helper")]
+    pub available_memory: Information,
+    #[schemars(schema_with = "crate::helper::uom::ratio::schema_function")]
+    #[serde_as(as = "crate::helper::uom::ratio::Helper")]
+    pub available_cpu:    Ratio,
+}
+
+impl Add for ResourceSummary {
+    type Output = ResourceSummary;
+
+    fn add(self, other: ResourceSummary) -> ResourceSummary {
+        ResourceSummary { used_memory:      self.used_memory + other.used_memory,
+                          used_cpu:         self.used_cpu + other.used_cpu,
+                          reserved_memory:  self.reserved_memory + other.reserved_memory,
+                          reserved_cpu:     self.reserved_cpu + other.reserved_cpu,
+                          available_memory: self.available_memory + other.available_memory,
+                          available_cpu:    self.available_cpu + other.available_cpu, }
+    }
+}
+
+/// Periodic sign of life of a node to the market
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PostHeartbeat {
+    pub node_id: NodeId,
+    pub summary: NodeSummary,
+}
+
+/// The answer to [PostHeartbeat]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HeartbeatAck {
+    /// The market doesn't know the node (anymore), it has to register again
+    pub forgotten: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct GetFogNodes {
     pub id:            NodeId,
     pub tags:          Vec<String>,
+    pub liveness:      Liveness,
+    /// No heartbeat was received for a while
+    pub stale:         bool,
+    /// As sent in the last heartbeat
+    pub summary:       Option<NodeSummary>,
     pub accepted_bids: HashMap<BidId, AcceptedBid>,
 }
 
 impl From<(NodeId, NodeRecord)> for GetFogNodes {
     fn from((id, record): (NodeId, NodeRecord)) -> Self {
-        GetFogNodes { id, tags: record.tags, accepted_bids: record.accepted_bids }
+        GetFogNodes { id,
+                      tags: record.tags,
+                      liveness: record.liveness,
+                      stale: record.stale,
+                      summary: record.summary,
+                      accepted_bids: record.accepted_bids }
     }
 }
@@ -2,6 +2,8 @@ use chrono::{DateTime, Utc};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::model::NodeId;
+
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[serde_with::serde_as]
 #[serde(rename_all = "camelCase")]
@@ -9,6 +11,9 @@ pub struct Ping {
     #[serde_as(as = "chrono_helper::DateTimeHelper")]
     #[schemars(schema_with = "crate::helper::chrono::schema_function")]
     pub sent_at: DateTime<Utc>,
+    /// The node sending the ping
+    #[serde(default)]
+    pub from:    Option<NodeId>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
@@ -18,4 +23,10 @@ pub struct PingResponse {
     #[serde_as(as = "chrono_helper::DateTimeHelper")]
     #[schemars(schema_with = "crate::helper::chrono::schema_function")]
     pub received_at: DateTime<Utc>,
+    #[serde_as(as = "chrono_helper::DateTimeHelper")]
+    #[schemars(schema_with = "crate::helper::chrono::schema_function")]
+    pub sent_at:     DateTime<Utc>,
+    /// The node answering doesn't know the sender as one of its neighbors, e.g., it restarted
+    #[serde(default)]
+    pub forgotten:   bool,
 }
@@ -12,4 +12,6 @@ pub struct PutSla {
     pub target_node:          NodeId,
     pub request_sources:      Vec<NodeId>,
     pub request_destinations: Vec<NodeId>,
+    /// Who is charged for the function
+    pub client:               String,
 }
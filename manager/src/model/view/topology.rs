@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+
+use schemars::JsonSchema;
+use serde::ser::Error as _;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::value::RawValue;
+use serde_with::serde_as;
+use uom::si::f64::{Information, Ratio, Time};
+use uom::si::information::megabyte;
+use uom::si::time::millisecond;
+
+use crate::helper::uom::cpu_ratio::millicpu;
+use crate::model::domain::liveness::Liveness;
+use crate::model::dto::node::{Node, NodeRecord};
+use crate::model::view::node::ResourceSummary;
+use crate::model::{BidId, NodeId};
+
+/// Declaration of the attributes of the GraphML export, the quantities being in ms, MB and
+/// millicpu
+const GRAPHML_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="address" for="node" attr.name="address" attr.type="string"/>
+  <key id="tags" for="node" attr.name="tags" attr.type="string"/>
+  <key id="liveness" for="node" attr.name="liveness" attr.type="string"/>
+  <key id="stale" for="node" attr.name="stale" attr.type="boolean"/>
+  <key id="functions" for="node" attr.name="functions" attr.type="string"/>
+  <key id="used_memory" for="node" attr.name="used_memory" attr.type="double"/>
+  <key id="available_memory" for="node" attr.name="available_memory" attr.type="double"/>
+  <key id="used_cpu" for="node" attr.name="used_cpu" attr.type="double"/>
+  <key id="available_cpu" for="node" attr.name="available_cpu" attr.type="double"/>
+  <key id="latency" for="edge" attr.name="latency" attr.type="double"/>
+  <graph id="topology" edgedefault="directed">
+"#;
+
+/// A node of the market tree, along with its whole subtree.
+/// The tree can be as deep as it has nodes, so it is built, serialized and dropped without
+/// recursion
+#[serde_as]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Topology {
+    pub id:        NodeId,
+    pub ip:        Option<IpAddr>,
+    pub port:      Option<u16>,
+    pub tags:      Vec<String>,
+    pub liveness:  Liveness,
+    pub stale:     bool,
+    /// The functions the market provisioned there
+    pub functions: Vec<BidId>,
+    /// Last latencies to its neighbors, as reported in the heartbeats
+    #[schemars(with = "HashMap<NodeId, String>")]
+    #[serde_as(as = "HashMap<_, crate::helper::uom::time::Helper>")]
+    pub latencies: HashMap<NodeId, Time>,
+    /// Last resources, as reported in the heartbeats
+    pub resources: Option<ResourceSummary>,
+    pub children:  Vec<Topology>,
+}
+
+/// The fields of a [Topology] node but its children, serialized the same way
+#[serde_as]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TopologyFields<'a> {
+    id:        &'a NodeId,
+    ip:        Option<IpAddr>,
+    port:      Option<u16>,
+    tags:      &'a [String],
+    liveness:  Liveness,
+    stale:     bool,
+    functions: &'a [BidId],
+    #[serde_as(as = "&HashMap<_, crate::helper::uom::time::Helper>")]
+    latencies: &'a HashMap<NodeId, Time>,
+    resources: Option<ResourceSummary>,
+}
+
+/// Step of the serialization of a [Topology] to JSON
+enum JsonStep<'a> {
+    /// Open the object of the node, the first child of its parent having no comma before it
+    Open(&'a Topology, bool),
+    /// Close the children and then the object of a node
+    Close,
+}
+
+impl Topology {
+    /// Build the subtree of the node [id]; the unknown nodes are left out
+    pub fn from_tree(nodes: &HashMap<NodeId, Node<NodeRecord>>, id: &NodeId) -> Option<Self> {
+        // The parents come before their children, so the children are built first in reverse
+        let mut order = vec![];
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = nodes.get(id) {
+                stack.extend(node.children.iter().rev());
+                order.push((id, node));
+            }
+        }
+
+        let mut built: HashMap<&NodeId, Topology> = HashMap::new();
+        for (id, node) in order.into_iter().rev() {
+            let summary = node.data.summary.as_ref();
+            let topology = Topology { id:        id.clone(),
+                                      ip:        node.data.ip,
+                                      port:      node.data.port,
+                                      tags:      node.data.tags.clone(),
+                                      liveness:  node.data.liveness,
+                                      stale:     node.data.stale,
+                                      functions: node.data.accepted_bids.keys().cloned().collect(),
+                                      latencies: summary.map(|summary| summary.latencies.clone())
+                                                        .unwrap_or_default(),
+                                      resources: summary.and_then(|summary| summary.resources),
+                                      children:  node.children
+                                                     .iter()
+                                                     .filter_map(|child| built.remove(child))
+                                                     .collect(), };
+            built.insert(id, topology);
+        }
+        built.remove(id)
+    }
+
+    /// Serialize the tree to nested JSON objects, one node at a time
+    fn to_json(&self) -> Result<String, serde_json::Error> {
+        let mut json = String::new();
+        let mut stack = vec![JsonStep::Open(self, true)];
+        while let Some(step) = stack.pop() {
+            match step {
+                JsonStep::Open(node, first) => {
+                    if !first {
+                        json.push(',');
+                    }
+                    let fields = serde_json::to_string(&node.fields())?;
+                    // Reopen the object to add the children
+                    json.push_str(&fields[..fields.len() - 1]);
+                    json.push_str(",\"children\":[");
+                    stack.push(JsonStep::Close);
+                    stack.extend(node.children.iter().enumerate().rev().map(|(index, child)| {
+                                                                           JsonStep::Open(child,
+                                                                                          index
+                                                                                          == 0)
+                                                                       }));
+                }
+                JsonStep::Close => json.push_str("]}"),
+            }
+        }
+        Ok(json)
+    }
+
+    fn fields(&self) -> TopologyFields<'_> {
+        TopologyFields { id:        &self.id,
+                         ip:        self.ip,
+                         port:      self.port,
+                         tags:      &self.tags,
+                         liveness:  self.liveness,
+                         stale:     self.stale,
+                         functions: &self.functions,
+                         latencies: &self.latencies,
+                         resources: self.resources, }
+    }
+
+    /// All the nodes of the subtree along with their parent, the parents coming first
+    fn nodes(&self) -> Vec<(Option<&Topology>, &Topology)> {
+        let mut nodes = vec![];
+        let mut stack = vec![(None, self)];
+        while let Some((parent, node)) = stack.pop() {
+            stack.extend(node.children.iter().rev().map(|child| (Some(node), child)));
+            nodes.push((parent, node));
+        }
+        nodes
+    }
+
+    /// Latency of the link between the parent and the child, as measured by the parent, or else by
+    /// the child
+    fn link_latency(parent: &Topology, child: &Topology) -> Option<Time> {
+        parent.latencies.get(&child.id).or_else(|| child.latencies.get(&parent.id)).copied()
+    }
+
+    /// Export the tree to the Graphviz DOT format
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph topology {\n    node [shape=box];\n");
+        for (parent, node) in self.nodes() {
+            let mut label = node.id.to_string();
+            if let (Some(ip), Some(port)) = (node.ip, node.port) {
+                let _ = write!(label, "\n{}:{}", ip, port);
+            }
+            if !node.tags.is_empty() {
+                let _ = write!(label, "\ntags: {}", node.tags.join(", "));
+            }
+            let _ = write!(label, "\nfunctions: {}", node.functions.len());
+            if let Some(resources) = &node.resources {
+                let _ = write!(label,
+                               "\nmemory: {} / cpu: {}",
+                               format_resources(resources.used_memory, resources.available_memory),
+                               format_cpu(resources.used_cpu, resources.available_cpu));
+            }
+            let color = match (node.stale, node.liveness) {
+                (true, _) | (_, Liveness::Dead) => "red",
+                (_, Liveness::Suspect) => "orange",
+                (_, Liveness::Alive) => "black",
+            };
+            let _ = writeln!(dot,
+                             "    \"{}\" [label=\"{}\", color={}];",
+                             node.id,
+                             escape_dot(&label),
+                             color);
+
+            if let Some(parent) = parent {
+                let _ = match Self::link_latency(parent, node) {
+                    Some(latency) => writeln!(dot,
+                                              "    \"{}\" -> \"{}\" [label=\"{:.1} ms\"];",
+                                              parent.id,
+                                              node.id,
+                                              latency.get::<millisecond>()),
+                    None => writeln!(dot, "    \"{}\" -> \"{}\";", parent.id, node.id),
+                };
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Export the tree to the GraphML format
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::from(GRAPHML_HEADER);
+        for (parent, node) in self.nodes() {
+            let _ = writeln!(xml, "    <node id=\"{}\">", node.id);
+            if let (Some(ip), Some(port)) = (node.ip, node.port) {
+                let _ = writeln!(xml, "      <data key=\"address\">{}:{}</data>", ip, port);
+            }
+            let _ = writeln!(xml,
+                             "      <data key=\"tags\">{}</data>",
+                             escape_xml(&node.tags.join(",")));
+            let _ = writeln!(xml, "      <data key=\"liveness\">{:?}</data>", node.liveness);
+            let _ = writeln!(xml, "      <data key=\"stale\">{}</data>", node.stale);
+            let functions =
+                node.functions.iter().map(ToString::to_string).collect::<Vec<_>>().join(",");
+            let _ = writeln!(xml, "      <data key=\"functions\">{}</data>", functions);
+            if let Some(resources) = &node.resources {
+                for (key, value) in [("used_memory", resources.used_memory.get::<megabyte>()),
+                                     ("available_memory",
+                                      resources.available_memory.get::<megabyte>()),
+                                     ("used_cpu", resources.used_cpu.get::<millicpu>()),
+                                     ("available_cpu", resources.available_cpu.get::<millicpu>())]
+                {
+                    let _ = writeln!(xml, "      <data key=\"{}\">{}</data>", key, value);
+                }
+            }
+            xml.push_str("    </node>\n");
+
+            if let Some(parent) = parent {
+                let _ = write!(xml, "    <edge source=\"{}\" target=\"{}\">", parent.id, node.id);
+                if let Some(latency) = Self::link_latency(parent, node) {
+                    let _ = write!(xml,
+                                   "\n      <data key=\"latency\">{}</data>\n    ",
+                                   latency.get::<millisecond>());
+                }
+                xml.push_str("</edge>\n");
+            }
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+}
+
+/// Serialized to JSON without recursion, which only suits the JSON serializers
+impl Serialize for Topology {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let json = self.to_json().map_err(S::Error::custom)?;
+        RawValue::from_string(json).map_err(S::Error::custom)?.serialize(serializer)
+    }
+}
+
+/// Drop the subtree without recursion
+impl Drop for Topology {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.children);
+        while let Some(mut node) = stack.pop() {
+            stack.append(&mut node.children);
+        }
+    }
+}
+
+/// The available resources are the capacity the used ones are part of
+fn format_resources(used: Information, available: Information) -> String {
+    format!("{:.0}/{:.0} MB", used.get::<megabyte>(), available.get::<megabyte>())
+}
+
+fn format_cpu(used: Ratio, available: Ratio) -> String {
+    format!("{:.0}/{:.0} millicpu", used.get::<millicpu>(), available.get::<millicpu>())
+}
+
+/// Escape a string to be put between double quotes in DOT, the line breaks becoming the ones of
+/// the labels
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn escape_xml(value: &str) -> String {
+    value.replace('&', "&amp;")
+         .replace('<', "&lt;")
+         .replace('>', "&gt;")
+         .replace('"', "&quot;")
+         .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::model::dto::node::tree_of;
+    use crate::model::view::node::{NodeSummary, ResourceSummary};
+
+    use super::*;
+
+    /// root -> (a -> a1, b), the root measuring 12 ms to a
+    fn tree() -> (HashMap<NodeId, Node<NodeRecord>>, [NodeId; 4]) {
+        let (mut nodes, ids) = tree_of::<NodeRecord, 4>([None, Some(0), Some(1), Some(0)]);
+        let [root, a, a1, _] = &ids;
+        nodes.get_mut(root).unwrap().data.tags = vec!["cloud".to_string()];
+        nodes.get_mut(a1).unwrap().data.tags = vec!["edge \"1\"".to_string()];
+        let resources = ResourceSummary { used_memory:      Information::new::<megabyte>(200.0),
+                                          used_cpu:         Ratio::new::<millicpu>(100.0),
+                                          reserved_memory:  Information::new::<megabyte>(0.0),
+                                          reserved_cpu:     Ratio::new::<millicpu>(0.0),
+                                          available_memory: Information::new::<megabyte>(1000.0),
+                                          available_cpu:    Ratio::new::<millicpu>(2000.0), };
+        let summary = NodeSummary { latencies: HashMap::from([(a.clone(),
+                                                               Time::new::<millisecond>(12.0))]),
+                                    resources: Some(resources),
+                                    ..NodeSummary::default() };
+        nodes.get_mut(root).unwrap().data.summary = Some(summary);
+        (nodes, ids)
+    }
+
+    #[test]
+    fn test_from_tree_keeps_the_structure() {
+        let (nodes, [root, a, a1, b]) = tree();
+        let topology = Topology::from_tree(&nodes, &root).unwrap();
+
+        assert_eq!(topology.tags, vec!["cloud".to_string()]);
+        let children: Vec<_> = topology.children.iter().map(|child| child.id.clone()).collect();
+        assert_eq!(children, vec![a.clone(), b.clone()]);
+        assert_eq!(topology.children[0].children[0].id, a1);
+
+        let order: Vec<_> = topology.nodes().into_iter().map(|(_, node)| node.id.clone()).collect();
+        assert_eq!(order, vec![root, a, a1, b]);
+    }
+
+    #[test]
+    fn test_exports_escape_and_annotate_the_links() {
+        let (nodes, [root, a, a1, b]) = tree();
+        let topology = Topology::from_tree(&nodes, &root).unwrap();
+
+        let dot = topology.to_dot();
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"12.0 ms\"];", root, a)));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", a, a1)));
+        assert!(dot.contains("tags: edge \\\"1\\\""));
+        assert!(dot.contains("memory: 200/1000 MB / cpu: 100/2000 millicpu"));
+
+        let graphml = topology.to_graphml();
+        assert!(graphml.contains(&format!("<edge source=\"{}\" target=\"{}\">\n      <data \
+                                           key=\"latency\">12</data>",
+                                          root, a)));
+        assert!(graphml.contains(&format!("<edge source=\"{}\" target=\"{}\"></edge>", root, b)));
+        assert!(graphml.contains("<data key=\"tags\">edge &quot;1&quot;</data>"));
+    }
+
+    #[test]
+    fn test_json_keeps_the_nesting() {
+        let (nodes, [root, a, a1, b]) = tree();
+        let topology = Topology::from_tree(&nodes, &root).unwrap();
+
+        let json = serde_json::to_string(&topology).unwrap();
+        let parsed: Topology = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.children[0].id, a);
+        assert_eq!(parsed.children[0].children[0].id, a1);
+        assert_eq!(parsed.children[0].children[0].tags, vec!["edge \"1\"".to_string()]);
+        assert_eq!(parsed.children[1].id, b);
+        assert_eq!(parsed.latencies, topology.latencies);
+        assert_eq!(parsed.resources, topology.resources);
+    }
+
+    #[test]
+    fn test_deep_chain_does_not_overflow_the_stack() {
+        const DEPTH: usize = 100_000;
+        let ids: Vec<_> = (0..DEPTH).map(|_| NodeId::from(Uuid::new_v4())).collect();
+        let nodes: HashMap<_, _> =
+            ids.iter()
+               .enumerate()
+               .map(|(index, id)| {
+                   (id.clone(),
+                    Node { parent:   index.checked_sub(1).map(|parent| ids[parent].clone()),
+                           children: ids.get(index + 1).cloned().into_iter().collect(),
+                           data:     NodeRecord::default(), })
+               })
+               .collect();
+
+        let topology = Topology::from_tree(&nodes, &ids[0]).unwrap();
+        assert_eq!(topology.nodes().len(), DEPTH);
+        let json = serde_json::to_string(&topology).unwrap();
+        assert!(json.ends_with(&"]}".repeat(DEPTH)));
+        assert!(topology.to_dot().contains(&format!("\"{}\" -> \"{}\";", ids[0], ids[1])));
+    }
+}
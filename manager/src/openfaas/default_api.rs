@@ -2,7 +2,9 @@ use async_trait::async_trait;
 use log::trace;
 use std::fmt::Debug;
 
-use super::models::{FunctionDefinition, FunctionListEntry};
+use crate::model::dto::routing::RoutedResponse;
+
+use super::models::{DeleteFunctionRequest, FunctionDefinition, FunctionListEntry};
 use super::{configuration, Error};
 
 #[derive(Clone, Debug)]
@@ -20,10 +22,16 @@ impl DefaultApiClient {
 pub trait DefaultApi: Debug + Sync + Send {
     async fn system_functions_get(&self) -> Result<Vec<FunctionListEntry>, Error<String>>;
     async fn system_functions_post(&self, body: FunctionDefinition) -> Result<(), Error<String>>;
+    async fn system_functions_delete(&self, function_name: &str) -> Result<(), Error<String>>;
     async fn async_function_name_post(&self,
                                       function_name: &str,
                                       input: String)
                                       -> Result<(), Error<String>>;
+    /// Invoke the function and wait for its response, whatever its status
+    async fn function_name_post(&self,
+                                function_name: &str,
+                                input: String)
+                                -> Result<RoutedResponse, Error<String>>;
 }
 
 #[async_trait]
@@ -62,6 +70,28 @@ impl DefaultApi for DefaultApiClient {
         }
     }
 
+    async fn system_functions_delete(&self, function_name: &str) -> Result<(), Error<String>> {
+        let uri_str = format!("{}/system/functions", self.configuration.base_path);
+        trace!("Requesting {}", uri_str);
+
+        let body = DeleteFunctionRequest { function_name: function_name.to_string() };
+        let mut builder =
+            self.configuration.client.delete(&uri_str).body(serde_json::to_string(&body)?);
+
+        if let Some((username, password)) = &self.configuration.basic_auth {
+            builder = builder.basic_auth(username, password.as_ref());
+        }
+
+        let response = builder.send().await?;
+        trace!("response: {:#?}", response);
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::from((response.status(), response.text().await)))
+        }
+    }
+
     async fn async_function_name_post(&self,
                                       function_name: &str,
                                       input: String)
@@ -84,4 +114,23 @@ impl DefaultApi for DefaultApiClient {
             Err(Error::from((response.status(), response.text().await)))
         }
     }
+
+    async fn function_name_post(&self,
+                                function_name: &str,
+                                input: String)
+                                -> Result<RoutedResponse, Error<String>> {
+        let uri_str = format!("{}/function/{}", self.configuration.base_path, function_name);
+        trace!("Requesting {}", uri_str);
+
+        let mut builder = self.configuration.client.post(&uri_str).body(input);
+
+        if let Some((username, password)) = &self.configuration.basic_auth {
+            builder = builder.basic_auth(username, password.as_ref());
+        }
+
+        let response = builder.send().await?;
+        trace!("response: {:#?}", response);
+
+        Ok(RoutedResponse::from_response(response).await?)
+    }
 }
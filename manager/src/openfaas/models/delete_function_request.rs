@@ -0,0 +1,8 @@
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteFunctionRequest {
+    /// Name of deployed function
+    pub function_name: String,
+}
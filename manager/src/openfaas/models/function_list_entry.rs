@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "camelCase")]
 pub struct FunctionListEntry {
     /// The name of the function
-    name:               String,
+    pub name:           String,
     /// The fully qualified docker image name of the function
     image:              String,
     /// The amount of invocations for the specified function
@@ -17,7 +17,7 @@ pub struct FunctionListEntry {
     /// Process for watchdog to fork
     env_process:        String,
     /// A map of labels for making scheduling or routing decisions
-    labels:             ::std::collections::HashMap<String, String>,
+    pub labels:         ::std::collections::HashMap<String, String>,
     /// A map of annotations for management, orchestration, events and build tasks
     annotations:        Option<::std::collections::HashMap<String, String>>,
 }
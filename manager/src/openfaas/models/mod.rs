@@ -2,6 +2,7 @@ use uom::fmt::DisplayStyle::Abbreviation;
 use uom::si::f64::{Information, Ratio};
 use uom::si::information;
 
+pub use delete_function_request::DeleteFunctionRequest;
 pub use function_definition::{FunctionDefinition, Limits};
 
 pub use self::function_list_entry::FunctionListEntry;
@@ -40,6 +41,8 @@ impl serde_with::SerializeAs<Ratio> for RatioHelper {
     }
 }
 
+mod delete_function_request;
+
 mod function_definition;
 
 mod function_list_entry;